@@ -11,7 +11,7 @@
 //!
 //! crate::cpu::boot::arch_boot
 
-use crate::{memory, memory::Address};
+use crate::{cpu::smp, device_tree::DeviceTree, memory, memory::Address};
 use aarch64_cpu::{asm, registers::*};
 use core::{
     arch::global_asm,
@@ -96,9 +96,29 @@ unsafe fn prepare_backtrace_reset() {
 #[no_mangle]
 pub unsafe extern "C" fn _start_rust(
     phys_kernel_tables_base_addr: u64,
-    virt_boot_core_stack_end_exclusive_addr: u64,
-    virt_kernel_init_addr: u64,
+    phys_boot_virt_addrs_addr: u64,
+    phys_code_start_addr: u64,
+    phys_rela_start_addr: u64,
+    phys_rela_end_exclusive_addr: u64,
+    kaslr_seed: u64,
+    phys_device_tree_addr: u64,
 ) -> ! {
+    // The device tree is only accessible as long as the MMU is off.
+    let device_tree = DeviceTree::new(Address::new(phys_device_tree_addr as usize));
+    smp::early_init(device_tree.as_ref());
+
+    // Relocate the kernel to a random virtual base address. Afterwards, the table that the
+    // assembly code handed over holds the final virtual addresses.
+    memory::kaslr::init(
+        Address::new(phys_code_start_addr as usize),
+        Address::new(phys_rela_start_addr as usize),
+        Address::new(phys_rela_end_exclusive_addr as usize),
+        kaslr_seed,
+    );
+    let boot_virt_addrs = phys_boot_virt_addrs_addr as *const u64;
+    let virt_boot_core_stack_end_exclusive_addr = boot_virt_addrs.read_volatile();
+    let virt_kernel_init_addr = boot_virt_addrs.add(1).read_volatile();
+
     prepare_el2_to_el1_transition(
         virt_boot_core_stack_end_exclusive_addr,
         virt_kernel_init_addr,
@@ -115,3 +135,29 @@ pub unsafe extern "C" fn _start_rust(
     // execution of kernel_init() in EL1 from its _virtual address_.
     asm::eret()
 }
+
+/// The Rust entry of secondary cores.
+///
+/// The function is called from the assembly `_start_secondary` function.
+///
+/// # Safety
+///
+/// - Exception return from EL2 must must continue execution in EL1 with `kernel_init_secondary()`.
+#[no_mangle]
+pub unsafe extern "C" fn _start_rust_secondary(
+    phys_kernel_tables_base_addr: u64,
+    virt_stack_end_exclusive_addr: u64,
+    virt_kernel_init_secondary_addr: u64,
+) -> ! {
+    prepare_el2_to_el1_transition(
+        virt_stack_end_exclusive_addr,
+        virt_kernel_init_secondary_addr,
+    );
+
+    let addr = Address::new(phys_kernel_tables_base_addr as usize);
+    memory::mmu::enable_mmu_and_caching(addr).unwrap();
+
+    prepare_backtrace_reset();
+
+    asm::eret()
+}
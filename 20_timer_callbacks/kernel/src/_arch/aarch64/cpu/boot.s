@@ -18,18 +18,6 @@
 	add	\register, \register, #:lo12:\symbol
 .endm
 
-// Load the address of a symbol into a register, absolute.
-//
-// # Resources
-//
-// - https://sourceware.org/binutils/docs-2.36/as/AArch64_002dRelocations.html
-.macro ADR_ABS register, symbol
-	movz	\register, #:abs_g3:\symbol
-	movk	\register, #:abs_g2_nc:\symbol
-	movk	\register, #:abs_g1_nc:\symbol
-	movk	\register, #:abs_g0_nc:\symbol
-.endm
-
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -39,6 +27,11 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
+	// Save the KASLR seed that a chainloader may provide, and the device tree address that the
+	// firmware passes. The firmware passes zero for the seed.
+	mov	x5, x1
+	mov	x6, x0
+
 	// Only proceed if the core executes in EL2. Park it otherwise.
 	mrs	x0, CurrentEL
 	cmp	x0, {CONST_CURRENTEL_EL2}
@@ -65,14 +58,17 @@ _start:
 
 	// Prepare the jump to Rust code.
 .L_prepare_rust:
+	// Read the CPU's timer counter frequency and store it in ARCH_TIMER_COUNTER_FREQUENCY.
+	// Abort if the frequency read back as 0.
+	ADR_REL	x7, ARCH_TIMER_COUNTER_FREQUENCY // provided by aarch64/time.rs
+	mrs	x8, CNTFRQ_EL0
+	cmp	x8, xzr
+	b.eq	.L_parking_loop
+	str	w8, [x7]
+
 	// Load the base address of the kernel's translation tables.
 	ldr	x0, PHYS_KERNEL_TABLES_BASE_ADDR // provided by bsp/__board_name__/memory/mmu.rs
 
-	// Load the _absolute_ addresses of the following symbols. Since the kernel is linked at
-	// the top of the 64 bit address space, these are effectively virtual addresses.
-	ADR_ABS	x1, __boot_core_stack_end_exclusive
-	ADR_ABS	x2, kernel_init
-
 	// Load the PC-relative address of the stack and set the stack pointer.
 	//
 	// Since _start() is the first function that runs after the firmware has loaded the kernel
@@ -80,19 +76,20 @@ _start:
 	//
 	// Setting the stack pointer to this value ensures that anything that still runs in EL2,
 	// until the kernel returns to EL1 with the MMU enabled, works as well. After the return to
-	// EL1, the virtual address of the stack retrieved above will be used.
-	ADR_REL	x3, __boot_core_stack_end_exclusive
-	mov	sp, x3
+	// EL1, the virtual address of the stack will be used.
+	ADR_REL	x1, __boot_core_stack_end_exclusive
+	mov	sp, x1
 
-	// Read the CPU's timer counter frequency and store it in ARCH_TIMER_COUNTER_FREQUENCY.
-	// Abort if the frequency read back as 0.
-	ADR_REL	x4, ARCH_TIMER_COUNTER_FREQUENCY // provided by aarch64/time.rs
-	mrs	x5, CNTFRQ_EL0
-	cmp	x5, xzr
-	b.eq	.L_parking_loop
-	str	w5, [x4]
+	// The virtual addresses are only known once the kernel has relocated itself, so pass the
+	// physical address of the table that holds them.
+	ADR_REL	x1, .L_boot_virt_addrs
+
+	// Load the physical addresses that are needed for the relocation.
+	ADR_REL	x2, __code_start
+	ADR_REL	x3, __rela_dyn_start
+	ADR_REL	x4, __rela_dyn_end_exclusive
 
-	// Jump to Rust code. x0, x1 and x2 hold the function arguments provided to _start_rust().
+	// Jump to Rust code. x0 to x6 hold the function arguments provided to _start_rust().
 	b	_start_rust
 
 	// Infinitely wait for events (aka "park the core").
@@ -103,3 +100,54 @@ _start:
 .size	_start, . - _start
 .type	_start, function
 .global	_start
+
+//------------------------------------------------------------------------------
+// fn _start_secondary()
+//------------------------------------------------------------------------------
+//
+// Entry point of the secondary cores. Runs from physical addresses with the MMU off.
+_start_secondary:
+	// Only proceed if the core executes in EL2. Park it otherwise.
+	mrs	x0, CurrentEL
+	cmp	x0, {CONST_CURRENTEL_EL2}
+	b.ne	.L_parking_loop_secondary
+
+	// Load the base address of the kernel's translation tables.
+	ldr	x0, PHYS_KERNEL_TABLES_BASE_ADDR // provided by bsp/__board_name__/memory/mmu.rs
+
+	// Load the stack addresses that were prepared by the boot core.
+	ADR_REL	x4, SECONDARY_BOOT_ARGS // provided by aarch64/cpu/smp.rs
+	ldp	x3, x1, [x4]
+
+	// The boot core has relocated the kernel already.
+	ADR_REL	x4, .L_boot_virt_addrs
+	ldr	x2, [x4, #16]
+
+	// Use the physical address of the stack until the MMU is enabled.
+	mov	sp, x3
+
+	// Jump to Rust code. x0, x1 and x2 hold the function arguments provided to
+	// _start_rust_secondary().
+	b	_start_rust_secondary
+
+	// Infinitely wait for events (aka "park the core").
+.L_parking_loop_secondary:
+	wfe
+	b	.L_parking_loop_secondary
+
+.size	_start_secondary, . - _start_secondary
+.type	_start_secondary, function
+.global	_start_secondary
+
+//--------------------------------------------------------------------------------------------------
+// Data
+//--------------------------------------------------------------------------------------------------
+.section .data._start_virt_addrs
+
+// Link addresses of the entry points in EL1. They are in a writable section, so that the kernel's
+// relocation of itself turns them into the final virtual addresses.
+.balign 8
+.L_boot_virt_addrs:
+	.quad	__boot_core_stack_end_exclusive
+	.quad	kernel_init
+	.quad	kernel_init_secondary
@@ -11,9 +11,95 @@
 //!
 //! crate::cpu::smp::arch_smp
 
-use aarch64_cpu::registers::*;
+use super::EnableMethod;
+use crate::{
+    bsp,
+    memory::{self, mmu::MemoryRegion, Address, Physical, Virtual},
+};
+use aarch64_cpu::{asm, registers::*};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
 use tock_registers::interfaces::Readable;
 
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Arguments for the secondary core that is currently being booted.
+///
+/// Read by `_start_secondary` while the MMU is still off.
+#[repr(C)]
+struct SecondaryBootArgs {
+    phys_stack_end_exclusive: AtomicU64,
+    virt_stack_end_exclusive: AtomicU64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+static SECONDARY_BOOT_ARGS: SecondaryBootArgs = SecondaryBootArgs {
+    phys_stack_end_exclusive: AtomicU64::new(0),
+    virt_stack_end_exclusive: AtomicU64::new(0),
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+extern "Rust" {
+    static _start_secondary: u8;
+}
+
+/// Release a core that is waiting in the firmware's spin table.
+fn spin_table_release(
+    core_id: usize,
+    phys_entry_addr: Address<Physical>,
+) -> Result<(), &'static str> {
+    let phys_slot = super::spin_table_entry_addr(core_id).ok_or("Core has no spin table entry")?;
+
+    // Refuse to write anywhere else than the mapped region the spin table is expected in.
+    let slot = bsp::cpu::spin_table_entry_virt_addr(phys_slot)
+        .ok_or("Spin table entry outside of the boot core stack region")?;
+
+    unsafe { (slot.as_usize() as *mut u64).write_volatile(phys_entry_addr.as_usize() as u64) };
+    memory::cache::clean_invalidate_dcache_range(slot, 8);
+
+    // The waiting core sleeps in `wfe`.
+    asm::sev();
+
+    Ok(())
+}
+
+/// Power on a core using the PSCI `CPU_ON` call, conduit `smc`.
+fn psci_cpu_on(core_id: usize, phys_entry_addr: Address<Physical>) -> Result<(), &'static str> {
+    const PSCI_CPU_ON: u64 = 0xC400_0003;
+
+    let mut ret = PSCI_CPU_ON;
+    unsafe {
+        asm!(
+            "smc #0",
+            inout("x0") ret,
+            in("x1") core_id as u64,
+            in("x2") phys_entry_addr.as_usize() as u64,
+            in("x3") 0_u64,
+            options(nostack)
+        )
+    };
+
+    match ret as i64 {
+        0 => Ok(()),
+        -1 => Err("PSCI: Not supported"),
+        -2 => Err("PSCI: Invalid parameters"),
+        -4 => Err("PSCI: Core is already on"),
+        -5 => Err("PSCI: Core is already being turned on"),
+        _ => Err("PSCI: CPU_ON failed"),
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -28,3 +114,43 @@ where
 
     T::from((MPIDR_EL1.get() & CORE_MASK) as u8)
 }
+
+/// Start a secondary core at `_start_secondary`, using the given stack.
+///
+/// Only one core may be started at a time, because the boot arguments are shared.
+///
+/// # Safety
+///
+/// - The stack must be valid and must not be used by anyone else.
+pub unsafe fn start_core(
+    core_id: usize,
+    enable_method: EnableMethod,
+    virt_stack: MemoryRegion<Virtual>,
+) -> Result<(), &'static str> {
+    let virt_entry_addr = Address::<Virtual>::new(&_start_secondary as *const _ as usize);
+    let phys_entry_addr = memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_entry_addr)?;
+    let phys_stack_start = memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_stack.start_addr())?;
+    let stack_size = virt_stack.size();
+
+    SECONDARY_BOOT_ARGS.phys_stack_end_exclusive.store(
+        (phys_stack_start + stack_size).as_usize() as u64,
+        Ordering::Relaxed,
+    );
+    SECONDARY_BOOT_ARGS.virt_stack_end_exclusive.store(
+        virt_stack.end_exclusive_page_addr().into_inner().as_usize() as u64,
+        Ordering::Relaxed,
+    );
+
+    // The new core writes to its stack with caching disabled, so there must be no lines left over
+    // that could be evicted on top of it later.
+    memory::cache::clean_invalidate_dcache_range(
+        Address::new(&SECONDARY_BOOT_ARGS as *const _ as usize),
+        core::mem::size_of::<SecondaryBootArgs>(),
+    );
+    memory::cache::clean_invalidate_dcache_range(virt_stack.start_addr(), stack_size);
+
+    match enable_method {
+        EnableMethod::SpinTable => spin_table_release(core_id, phys_entry_addr),
+        EnableMethod::Psci => psci_cpu_on(core_id, phys_entry_addr),
+    }
+}
@@ -11,7 +11,7 @@
 //!
 //! crate::exception::arch_exception
 
-use crate::{exception, memory, symbols};
+use crate::{bsp, cpu, exception, memory, process, symbols, task, warn};
 use aarch64_cpu::{asm::barrier, registers::*};
 use core::{arch::global_asm, cell::UnsafeCell, fmt};
 use tock_registers::{
@@ -23,13 +23,23 @@ use tock_registers::{
 global_asm!(
     include_str!("exception.s"),
     CONST_ESR_EL1_EC_SHIFT = const 26,
-    CONST_ESR_EL1_EC_VALUE_SVC64 = const 0x15
+    CONST_ESR_EL1_EC_VALUE_SVC64 = const 0x15,
+    CONST_CORE_ID_MASK = const 0b11,
+    CONST_EMERGENCY_STACK_SHIFT = const EMERGENCY_STACK_SHIFT
 );
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The size of a per-core emergency stack, as a power of two.
+const EMERGENCY_STACK_SHIFT: usize = 14;
+const EMERGENCY_STACK_SIZE: usize = 1 << EMERGENCY_STACK_SHIFT;
+
+/// Stacks that synchronous exception entry switches to if the interrupted stack is exhausted.
+#[repr(C, align(16))]
+struct EmergencyStacks(UnsafeCell<[[u8; EMERGENCY_STACK_SIZE]; bsp::cpu::NUM_CORES]>);
+
 /// Wrapper structs for memory copies of registers.
 #[repr(transparent)]
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
@@ -54,6 +64,18 @@ struct ExceptionContext {
     esr_el1: EsrEL1,
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+// Only ever accessed through the stack pointer of the owning core.
+unsafe impl Sync for EmergencyStacks {}
+
+#[no_mangle]
+static EMERGENCY_STACKS: EmergencyStacks = EmergencyStacks(UnsafeCell::new(
+    [[0; EMERGENCY_STACK_SIZE]; bsp::cpu::NUM_CORES],
+));
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -67,6 +89,27 @@ fn default_exception_handler(exc: &ExceptionContext) {
     );
 }
 
+/// Handles an IRQ, and runs deferred work and the scheduler afterwards.
+///
+/// Exception entry masks FIQs as well. They are unmasked again if the interrupted context had them
+/// unmasked, so that the FIQ can preempt IRQ handlers, nested ones and deferred work. Before
+/// returning, FIQs are masked again, because a FIQ taken while the context is restored would
+/// overwrite `ELR_EL1` and `SPSR_EL1`.
+fn handle_irq(e: &ExceptionContext) {
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+
+    if !e.spsr_el1.0.is_set(SPSR_EL1::F) {
+        exception::asynchronous::local_fiq_unmask();
+    }
+
+    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+    exception::asynchronous::deferred::run_pending(token);
+
+    task::preempt_if_needed(token);
+
+    exception::asynchronous::local_fiq_mask();
+}
+
 //------------------------------------------------------------------------------
 // Current, EL0
 //------------------------------------------------------------------------------
@@ -103,13 +146,35 @@ extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
         }
     }
 
+    // Translation faults in demand paged memory are resolved and the instruction is retried.
+    if let Some(addr) = e.translation_fault_addr() {
+        if memory::mmu::kernel_handle_translation_fault(addr).is_ok() {
+            return;
+        }
+    }
+
     default_exception_handler(e);
 }
 
+/// Entered on the emergency stack if the interrupted stack had no room left for the context.
+#[no_mangle]
+extern "C" fn current_elx_stack_overflow(e: &mut ExceptionContext) {
+    panic!(
+        "Kernel stack overflow!\n\n\
+        {}",
+        e
+    );
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
+    handle_irq(e);
+}
+
 #[no_mangle]
-extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+extern "C" fn current_elx_fiq(_e: &mut ExceptionContext) {
     let token = unsafe { &exception::asynchronous::IRQContext::new() };
-    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+    exception::asynchronous::fiq::handle(token);
 }
 
 #[no_mangle]
@@ -123,12 +188,28 @@ extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    if let Some(ESR_EL1::EC::Value::SVC64) = e.exception_class() {
+        let nr = e.gpr[8];
+        let args = [e.gpr[0], e.gpr[1], e.gpr[2], e.gpr[3], e.gpr[4], e.gpr[5]];
+
+        e.gpr[0] = process::syscall::dispatch(nr, &args);
+        return;
+    }
+
+    // Anything else is a fault caused by user code. Only the process is affected, not the kernel.
+    warn!("User space exception!\n\n{}", e);
+    process::kill_current();
 }
 
 #[no_mangle]
 extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    handle_irq(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_fiq(_e: &mut ExceptionContext) {
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+    exception::asynchronous::fiq::handle(token);
 }
 
 #[no_mangle]
@@ -203,6 +284,25 @@ impl EsrEL1 {
     fn iss(&self) -> u64 {
         self.0.read(ESR_EL1::ISS)
     }
+
+    /// The fault status code of instruction and data aborts.
+    #[inline(always)]
+    fn fault_status_code(&self) -> Option<u64> {
+        use ESR_EL1::EC::Value::*;
+
+        match self.exception_class() {
+            Some(
+                InstrAbortLowerEL | InstrAbortCurrentEL | DataAbortLowerEL | DataAbortCurrentEL,
+            ) => Some(self.0.read(ESR_EL1::ISS) & 0b11_1111),
+            _ => None,
+        }
+    }
+
+    /// Checks if the exception is a translation fault, at any level.
+    #[inline(always)]
+    fn is_translation_fault(&self) -> bool {
+        matches!(self.fault_status_code(), Some(0b00_0100..=0b00_0111))
+    }
 }
 
 /// Human readable ESR_EL1.
@@ -218,12 +318,33 @@ impl fmt::Display for EsrEL1 {
         // Exception class.
         let ec_translation = match self.exception_class() {
             Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
+            Some(ESR_EL1::EC::Value::DataAbortLowerEL) => "Data Abort, lower EL",
+            Some(ESR_EL1::EC::Value::InstrAbortLowerEL) => "Instruction Abort, lower EL",
             _ => "N/A",
         };
         writeln!(f, " - {}", ec_translation)?;
 
         // Raw print of instruction specific syndrome.
-        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.0.read(ESR_EL1::ISS))
+        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.0.read(ESR_EL1::ISS))?;
+
+        // Fault status code of aborts.
+        if let Some(fsc) = self.fault_status_code() {
+            let level = fsc & 0b11;
+            let fsc_translation = match fsc >> 2 {
+                0b0000 => "Address size fault",
+                0b0001 => "Translation fault",
+                0b0010 => "Access flag fault",
+                0b0011 => "Permission fault",
+                _ => "N/A",
+            };
+
+            write!(f, "\n      Fault Status Code       (FSC): {:#x} - {}", fsc, fsc_translation)?;
+            if fsc >> 2 <= 0b0011 {
+                write!(f, ", level {}", level)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -233,6 +354,23 @@ impl ExceptionContext {
         self.esr_el1.exception_class()
     }
 
+    /// The faulting address, if this is a translation fault of the kernel.
+    #[inline(always)]
+    fn translation_fault_addr(&self) -> Option<memory::Address<memory::Virtual>> {
+        use ESR_EL1::EC::Value::*;
+
+        let current_el = matches!(
+            self.exception_class(),
+            Some(InstrAbortCurrentEL | DataAbortCurrentEL)
+        );
+
+        if !current_el || !self.esr_el1.is_translation_fault() {
+            return None;
+        }
+
+        Some(memory::Address::new(FAR_EL1.get() as usize))
+    }
+
     #[inline(always)]
     fn fault_address_valid(&self) -> bool {
         use ESR_EL1::EC::Value::*;
@@ -304,6 +442,14 @@ pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     }
 }
 
+/// Checks if the address is part of the executing core's emergency stack.
+pub fn is_emergency_stack_addr(addr: memory::Address<memory::Virtual>) -> bool {
+    let start =
+        EMERGENCY_STACKS.0.get() as usize + cpu::smp::core_id::<usize>() * EMERGENCY_STACK_SIZE;
+
+    (start..start + EMERGENCY_STACK_SIZE).contains(&addr.as_usize())
+}
+
 /// Init exception handling by setting the exception vector base address register.
 ///
 /// # Safety
@@ -126,12 +126,14 @@ __exception_vector_start:
 	CALL_WITH_CONTEXT current_el0_serror, 0, 0
 
 // Current exception level with SP_ELx, x > 0.
+//
+// Synchronous exceptions might be caused by a stack overflow, so the stack is checked first.
 .org 0x200
-	CALL_WITH_CONTEXT current_elx_synchronous, 0, 1
+	b	__current_elx_synchronous_check_stack
 .org 0x280
 	CALL_WITH_CONTEXT current_elx_irq, 0, 0
 .org 0x300
-	FIQ_SUSPEND
+	CALL_WITH_CONTEXT current_elx_fiq, 0, 0
 .org 0x380
 	CALL_WITH_CONTEXT current_elx_serror, 0, 0
 
@@ -141,7 +143,7 @@ __exception_vector_start:
 .org 0x480
 	CALL_WITH_CONTEXT lower_aarch64_irq, 1, 0
 .org 0x500
-	FIQ_SUSPEND
+	CALL_WITH_CONTEXT lower_aarch64_fiq, 1, 0
 .org 0x580
 	CALL_WITH_CONTEXT lower_aarch64_serror, 1, 0
 
@@ -188,3 +190,41 @@ __exception_restore_context:
 
 .size	__exception_restore_context, . - __exception_restore_context
 .type	__exception_restore_context, function
+
+//------------------------------------------------------------------------------
+// fn __current_elx_synchronous_check_stack()
+//
+// A kernel stack overflow faults on the unmapped guard page below the stack. Saving the exception
+// context on the same stack would fault again, recursively. Therefore, probe whether the context
+// fits, and switch to the executing core's emergency stack if it does not.
+//------------------------------------------------------------------------------
+__current_elx_synchronous_check_stack:
+	// Only x0 is used before the context is saved. Stash it away in the meantime.
+	msr	TPIDR_EL1, x0
+
+	// Translate the lowest address of the context for a write. PAR_EL1.F is set on failure.
+	sub	x0,  sp,  #16 * 18
+	at	s1e1w, x0
+	isb
+	mrs	x0,  PAR_EL1
+	tbnz	x0,  #0,  __current_elx_stack_overflow
+
+	mrs	x0,  TPIDR_EL1
+	CALL_WITH_CONTEXT current_elx_synchronous, 0, 1
+
+__current_elx_stack_overflow:
+	// sp = EMERGENCY_STACKS + (core_id + 1) * stack size
+	mrs	x0,  MPIDR_EL1
+	and	x0,  x0,  {CONST_CORE_ID_MASK}
+	add	x0,  x0,  #1
+	lsl	x0,  x0,  {CONST_EMERGENCY_STACK_SHIFT}
+	mov	sp,  x0
+	adrp	x0,  EMERGENCY_STACKS
+	add	x0,  x0,  #:lo12:EMERGENCY_STACKS
+	add	sp,  sp,  x0
+
+	mrs	x0,  TPIDR_EL1
+	CALL_WITH_CONTEXT current_elx_stack_overflow, 0, 1
+
+.size	__current_elx_synchronous_check_stack, . - __current_elx_synchronous_check_stack
+.type	__current_elx_synchronous_check_stack, function
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural cache maintenance.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::memory::cache::arch_cache
+
+use super::{line_addrs, line_is_partial};
+use crate::memory::{Address, Virtual};
+use aarch64_cpu::asm::barrier;
+use core::arch::asm;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The Cache Type Register. Not provided by the `aarch64-cpu` crate.
+fn ctr_el0() -> u64 {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack)) };
+
+    ctr
+}
+
+/// The smallest data cache line size of all caches in the system.
+fn dcache_min_line_size() -> usize {
+    // DminLine, log2 of the number of words.
+    4 << (ctr_el0() >> 16 & 0xf)
+}
+
+/// The smallest instruction cache line size of all caches in the system.
+fn icache_min_line_size() -> usize {
+    // IminLine, log2 of the number of words.
+    4 << (ctr_el0() & 0xf)
+}
+
+/// CTR_EL0.IDC: Cleaning the data cache is not required for instruction to data coherence.
+fn idc() -> bool {
+    ctr_el0() & (1 << 28) != 0
+}
+
+/// CTR_EL0.DIC: Invalidating the instruction cache is not required for instruction to data
+/// coherence.
+fn dic() -> bool {
+    ctr_el0() & (1 << 29) != 0
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Clean to the Point of Coherency.
+pub fn clean_dcache_range(start: Address<Virtual>, size: usize) {
+    for line in line_addrs(start, size, dcache_min_line_size()) {
+        unsafe { asm!("dc cvac, {}", in(reg) line.as_usize(), options(nostack)) };
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Invalidate to the Point of Coherency.
+///
+/// # Safety
+///
+/// - See [`super::invalidate_dcache_range()`].
+pub unsafe fn invalidate_dcache_range(start: Address<Virtual>, size: usize) {
+    let line_size = dcache_min_line_size();
+
+    for line in line_addrs(start, size, line_size) {
+        if line_is_partial(line, start, size, line_size) {
+            asm!("dc civac, {}", in(reg) line.as_usize(), options(nostack));
+        } else {
+            asm!("dc ivac, {}", in(reg) line.as_usize(), options(nostack));
+        }
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Clean and invalidate to the Point of Coherency.
+pub fn clean_invalidate_dcache_range(start: Address<Virtual>, size: usize) {
+    for line in line_addrs(start, size, dcache_min_line_size()) {
+        unsafe { asm!("dc civac, {}", in(reg) line.as_usize(), options(nostack)) };
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Clean the data cache to the Point of Unification, then invalidate the instruction cache in the
+/// Inner Shareable domain.
+///
+/// Steps that the hardware declares unnecessary through CTR_EL0 are skipped.
+pub fn sync_icache_range(start: Address<Virtual>, size: usize) {
+    if !idc() {
+        for line in line_addrs(start, size, dcache_min_line_size()) {
+            unsafe { asm!("dc cvau, {}", in(reg) line.as_usize(), options(nostack)) };
+        }
+    }
+    barrier::dsb(barrier::ISH);
+
+    if !dic() {
+        for line in line_addrs(start, size, icache_min_line_size()) {
+            unsafe { asm!("ic ivau, {}", in(reg) line.as_usize(), options(nostack)) };
+        }
+        barrier::dsb(barrier::ISH);
+    }
+
+    barrier::isb(barrier::SY);
+}
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural kernel address space layout randomization.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::memory::kaslr::arch_kaslr
+
+use crate::memory::{Address, Physical};
+use aarch64_cpu::registers::*;
+use core::{mem::size_of, slice};
+use tock_registers::interfaces::Readable;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An `Elf64_Rela` entry.
+#[repr(C)]
+struct Rela {
+    r_offset: u64,
+    r_info: u64,
+    r_addend: i64,
+}
+
+/// Store the load offset plus the addend. The only type that a static PIE needs.
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Rela {
+    fn r_type(&self) -> u64 {
+        self.r_info & 0xffff_ffff
+    }
+}
+
+/// The dynamic relocation entries, accessed at their physical address.
+unsafe fn relocations(
+    phys_rela_start: Address<Physical>,
+    phys_rela_end_exclusive: Address<Physical>,
+) -> &'static [Rela] {
+    let size = phys_rela_end_exclusive.as_usize() - phys_rela_start.as_usize();
+
+    slice::from_raw_parts(
+        phys_rela_start.as_usize() as *const Rela,
+        size / size_of::<Rela>(),
+    )
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Entropy from the physical timer counter.
+///
+/// Boot timing varies a little from run to run, so only the lowest bits are worth something.
+pub fn timer_entropy() -> u64 {
+    CNTPCT_EL0.get()
+}
+
+/// Check that all relocations can be handled by [`apply_relocations()`].
+///
+/// # Safety
+///
+/// - The addresses must be the physical ones of the relocation section.
+pub unsafe fn check_relocations(
+    phys_rela_start: Address<Physical>,
+    phys_rela_end_exclusive: Address<Physical>,
+) -> Result<(), &'static str> {
+    let all_relative = relocations(phys_rela_start, phys_rela_end_exclusive)
+        .iter()
+        .all(|rela| rela.r_type() == R_AARCH64_RELATIVE);
+
+    if !all_relative {
+        return Err("Unsupported relocation type");
+    }
+
+    Ok(())
+}
+
+/// Relocate the kernel binary to its link address plus `offset`.
+///
+/// # Safety
+///
+/// - The MMU must be off, the relocations are written through physical addresses.
+/// - The relocations must have been checked with [`check_relocations()`].
+pub unsafe fn apply_relocations(
+    phys_code_start: Address<Physical>,
+    phys_rela_start: Address<Physical>,
+    phys_rela_end_exclusive: Address<Physical>,
+    link_virt_start: usize,
+    offset: usize,
+) {
+    for rela in relocations(phys_rela_start, phys_rela_end_exclusive) {
+        let phys_addr = (rela.r_offset as usize) - link_virt_start + phys_code_start.as_usize();
+        let value = (rela.r_addend as u64).wrapping_add(offset as u64);
+
+        core::ptr::write_volatile(phys_addr as *mut u64, value);
+    }
+}
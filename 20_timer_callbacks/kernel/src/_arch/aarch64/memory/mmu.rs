@@ -4,7 +4,7 @@
 
 //! Memory Management Unit Driver.
 //!
-//! Only 64 KiB granule is supported.
+//! The translation granule is chosen by the BSP. 4 KiB, 16 KiB and 64 KiB are supported.
 //!
 //! # Orientation
 //!
@@ -15,10 +15,13 @@
 
 use crate::{
     bsp, memory,
-    memory::{mmu::TranslationGranule, Address, Physical},
+    memory::{
+        mmu::{PageAddress, TranslationGranule},
+        Address, Physical, Virtual,
+    },
 };
 use aarch64_cpu::{asm::barrier, registers::*};
-use core::intrinsics::unlikely;
+use core::{arch::asm, intrinsics::unlikely};
 use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
 //--------------------------------------------------------------------------------------------------
@@ -32,14 +35,26 @@ struct MemoryManagementUnit;
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
+pub type Granule4KiB = TranslationGranule<{ 4 * 1024 }>;
+pub type Granule16KiB = TranslationGranule<{ 16 * 1024 }>;
 pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
 
+/// The region that a lvl2 descriptor covers with the kernel's granule.
+///
+/// This is 2 MiB for 4 KiB pages, 32 MiB for 16 KiB pages and 512 MiB for 64 KiB pages.
+pub type Lvl2Granule = TranslationGranule<{ lvl2_size() }>;
+
+/// The number of ASIDs. Only 8 bit ASIDs are used, which every implementation supports.
+pub const NUM_ASIDS: usize = 256;
+
 /// Constants for indexing the MAIR_EL1.
 #[allow(dead_code)]
 pub mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
+    pub const NORMAL_WRITE_THROUGH: u64 = 3;
+    pub const DEVICE_GRE: u64 = 4;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -52,11 +67,19 @@ static MMU: MemoryManagementUnit = MemoryManagementUnit;
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// A table holds one granule worth of 8 byte descriptors, so each level resolves
+/// `granule shift - 3` bits of the address.
+const fn lvl2_size() -> usize {
+    let shift = bsp::memory::mmu::KernelGranule::SHIFT;
+
+    1 << (shift + (shift - 3))
+}
+
 impl<const AS_SIZE: usize> memory::mmu::AddressSpace<AS_SIZE> {
     /// Checks for architectural restrictions.
     pub const fn arch_address_space_size_sanity_checks() {
-        // Size must be at least one full 512 MiB table.
-        assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
+        // Size must be at least one full lvl2 window.
+        assert!((AS_SIZE % Lvl2Granule::SIZE) == 0);
 
         // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
         // version.
@@ -70,8 +93,19 @@ impl MemoryManagementUnit {
     fn set_up_mair(&self) {
         // Define the memory types being mapped.
         MAIR_EL1.write(
-            // Attribute 1 - Cacheable normal DRAM.
-            MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
+            // Attribute 4 - Device, gathering and reordering.
+            MAIR_EL1::Attr4_Device::Gathering_Reordering_EarlyWriteAck +
+
+        // Attribute 3 - Write-through normal DRAM.
+        MAIR_EL1::Attr3_Normal_Outer::WriteThrough_NonTransient_ReadAlloc +
+        MAIR_EL1::Attr3_Normal_Inner::WriteThrough_NonTransient_ReadAlloc +
+
+        // Attribute 2 - Non-cacheable normal DRAM.
+        MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
+        MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
+
+        // Attribute 1 - Cacheable normal DRAM.
+        MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
         MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
 
         // Attribute 0 - Device.
@@ -79,22 +113,53 @@ impl MemoryManagementUnit {
         );
     }
 
+    /// Whether the hardware supports the kernel's translation granule.
+    #[inline(always)]
+    fn is_granule_supported(&self) -> bool {
+        match bsp::memory::mmu::KernelGranule::SIZE {
+            Granule4KiB::SIZE => ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::Supported),
+            Granule16KiB::SIZE => {
+                ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran16::Supported)
+            }
+            Granule64KiB::SIZE => {
+                ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported)
+            }
+            _ => false,
+        }
+    }
+
     /// Configure various settings of stage 1 of the EL1 translation regime.
     #[inline(always)]
     fn configure_translation_control(&self) {
         let t1sz = (64 - bsp::memory::mmu::KernelVirtAddrSpace::SIZE_SHIFT) as u64;
+        let t0sz = (64 - bsp::memory::mmu::UserVirtAddrSpace::SIZE_SHIFT) as u64;
+
+        let (tg1, tg0) = match bsp::memory::mmu::KernelGranule::SIZE {
+            Granule4KiB::SIZE => (TCR_EL1::TG1::KiB_4, TCR_EL1::TG0::KiB_4),
+            Granule16KiB::SIZE => (TCR_EL1::TG1::KiB_16, TCR_EL1::TG0::KiB_16),
+            _ => (TCR_EL1::TG1::KiB_64, TCR_EL1::TG0::KiB_64),
+        };
 
+        // TTBR0 walks stay disabled until the first user address space is activated. The ASID is
+        // taken from TTBR0, so that switching address spaces is a single register write.
         TCR_EL1.write(
             TCR_EL1::TBI1::Used
                 + TCR_EL1::IPS::Bits_40
-                + TCR_EL1::TG1::KiB_64
+                + TCR_EL1::AS::ASID8Bits
+                + tg1
                 + TCR_EL1::SH1::Inner
                 + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::EPD1::EnableTTBR1Walks
-                + TCR_EL1::A1::TTBR1
+                + TCR_EL1::A1::TTBR0
                 + TCR_EL1::T1SZ.val(t1sz)
-                + TCR_EL1::EPD0::DisableTTBR0Walks,
+                + TCR_EL1::TBI0::Used
+                + tg0
+                + TCR_EL1::SH0::Inner
+                + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::EPD0::DisableTTBR0Walks
+                + TCR_EL1::T0SZ.val(t0sz),
         );
     }
 }
@@ -103,6 +168,28 @@ impl MemoryManagementUnit {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// The operand of a TLB maintenance instruction by VA.
+///
+/// The operand holds the ASID in bits [63:48] and VA[55:12] in bits [43:0].
+#[inline(always)]
+pub fn tlbi_va_operand(asid: u16, virt_page_addr: PageAddress<Virtual>) -> u64 {
+    const VA_MASK: u64 = (1 << 44) - 1;
+
+    ((asid as u64) << 48) | (((virt_page_addr.into_inner().as_usize() as u64) >> 12) & VA_MASK)
+}
+
+/// Whether the size of a live translation can be changed without a break-before-make sequence.
+///
+/// This is the case from FEAT_BBM level 2 on, which guarantees that no TLB conflict aborts occur.
+pub fn supports_bbm_level_2() -> bool {
+    let mmfr2: u64;
+
+    // ID_AA64MMFR2_EL1, which older assemblers don't know by name.
+    unsafe { asm!("mrs {}, S3_0_C0_C7_2", out(reg) mmfr2, options(nomem, nostack)) };
+
+    (mmfr2 >> 52) & 0xF >= 2
+}
+
 /// Return a reference to the MMU instance.
 pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
     &MMU
@@ -123,7 +210,7 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
         }
 
         // Fail early if translation granule is not supported.
-        if unlikely(!ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported)) {
+        if unlikely(!self.is_granule_supported()) {
             return Err(MMUEnableError::Other(
                 "Translation granule not supported in HW",
             ));
@@ -155,4 +242,47 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
     fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
     }
+
+    fn kernel_tables_phys_base_addr(&self) -> Address<Physical> {
+        Address::new(TTBR1_EL1.get_baddr() as usize)
+    }
+
+    unsafe fn set_user_translation_tables(
+        &self,
+        phys_tables_base_addr: Address<Physical>,
+        asid: u16,
+    ) {
+        TTBR0_EL1.write(
+            TTBR0_EL1::ASID.val(asid as u64)
+                + TTBR0_EL1::BADDR.val((phys_tables_base_addr.as_usize() >> 1) as u64),
+        );
+        TCR_EL1.modify(TCR_EL1::EPD0::EnableTTBR0Walks);
+
+        barrier::isb(barrier::SY);
+    }
+
+    unsafe fn clear_user_translation_tables(&self) {
+        TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+        TTBR0_EL1.set(0);
+
+        barrier::isb(barrier::SY);
+    }
+
+    fn invalidate_tlb_page(&self, asid: u16, virt_page_addr: PageAddress<Virtual>) {
+        let operand = tlbi_va_operand(asid, virt_page_addr);
+
+        barrier::dsb(barrier::ISHST);
+        unsafe { asm!("tlbi vae1is, {}", in(reg) operand, options(nostack)) };
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+
+    fn invalidate_tlb_asid(&self, asid: u16) {
+        let operand = (asid as u64) << 48;
+
+        barrier::dsb(barrier::ISHST);
+        unsafe { asm!("tlbi aside1is, {}", in(reg) operand, options(nostack)) };
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
 }
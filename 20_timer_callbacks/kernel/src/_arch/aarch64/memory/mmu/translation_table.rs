@@ -4,7 +4,9 @@
 
 //! Architectural translation table.
 //!
-//! Only 64 KiB granule is supported.
+//! The descriptors and index calculations follow the translation granule chosen by the BSP. Large,
+//! suitably aligned ranges of the kernel's fixed size tables are mapped with lvl2 block
+//! descriptors, which cover 2 MiB, 32 MiB or 512 MiB each, depending on the granule.
 //!
 //! # Orientation
 //!
@@ -14,19 +16,26 @@
 //! crate::memory::mmu::translation_table::arch_translation_table
 
 use crate::{
-    bsp,
+    bsp::{self, memory::mmu::KernelGranule},
     memory::{
         self,
         mmu::{
-            arch_mmu::{Granule512MiB, Granule64KiB},
-            AccessPermissions, AttributeFields, MemAttributes, MemoryRegion, PageAddress,
+            arch_mmu::Lvl2Granule, interface::MMU, AccessPermissions, AttributeFields,
+            MemAttributes, MemoryRegion, PageAddress,
         },
         Address, Physical, Virtual,
     },
+    state,
 };
-use core::convert;
+use aarch64_cpu::asm::barrier;
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    boxed::Box,
+    vec::Vec,
+};
+use core::{arch::asm, convert};
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields,
     registers::InMemoryRegister,
 };
@@ -39,7 +48,7 @@ use tock_registers::{
 register_bitfields! {u64,
     STAGE1_TABLE_DESCRIPTOR [
         /// Physical address of the next descriptor.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        NEXT_LEVEL_TABLE_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         TYPE  OFFSET(1) NUMBITS(1) [
             Block = 0,
@@ -69,7 +78,13 @@ register_bitfields! {u64,
         ],
 
         /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
+
+        /// Not global. If set, the translation is only valid for the current ASID.
+        nG       OFFSET(11) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
 
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
@@ -106,7 +121,7 @@ register_bitfields! {u64,
     ]
 }
 
-/// A table descriptor for 64 KiB aperture.
+/// A table descriptor.
 ///
 /// The output points to the next table.
 #[derive(Copy, Clone)]
@@ -115,7 +130,7 @@ struct TableDescriptor {
     value: u64,
 }
 
-/// A page descriptor with 64 KiB aperture.
+/// A page descriptor with the aperture of one granule.
 ///
 /// The output points to physical memory.
 #[derive(Copy, Clone)]
@@ -124,39 +139,148 @@ struct PageDescriptor {
     value: u64,
 }
 
+/// A lvl2 block descriptor with the aperture of a [`Lvl2Granule`].
+///
+/// The output points to physical memory. Apart from the type bit and the alignment of the output
+/// address, the layout is the same as that of a page descriptor.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct BlockDescriptor {
+    value: u64,
+}
+
+/// The address fields of all descriptors hold bits [47:12] of an address. With granules larger
+/// than 4 KiB, the lowest bits of an aligned address are zero.
+const ADDR_FIELD_SHIFT: usize = 12;
+
+/// Each table occupies one granule, and each level resolves this many bits of an address.
+const TABLE_INDEX_BITS: usize = KernelGranule::SHIFT - 3;
+
+/// The number of descriptors in a table.
+const NUM_TABLE_ENTRIES: usize = 1 << TABLE_INDEX_BITS;
+
 trait StartAddr {
     fn virt_start_addr(&self) -> Address<Virtual>;
 }
 
+/// A table of descriptors, which occupies exactly one granule.
+///
+/// Tables must be aligned to the granule. The alignment can't be derived from a constant, so it is
+/// selected by the same features as `KernelGranule`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+#[cfg_attr(feature = "granule_4k", repr(align(4096)))]
+#[cfg_attr(feature = "granule_16k", repr(align(16384)))]
+#[cfg_attr(
+    not(any(feature = "granule_4k", feature = "granule_16k")),
+    repr(align(65536))
+)]
+struct Table<D>([D; NUM_TABLE_ENTRIES]);
+
+/// A lvl3 table.
+type Lvl3Table = Table<PageDescriptor>;
+
+/// A lvl0, lvl1 or lvl2 table.
+type InnerTable = Table<TableDescriptor>;
+
+/// A table that is allocated at runtime, together with the tables it points to.
+enum TableNode {
+    /// A lvl0, lvl1 or lvl2 table. Only the entries that are used are present in `next_lvl`.
+    Inner {
+        table: Box<InnerTable>,
+        next_lvl: Vec<Option<Box<TableNode>>>,
+    },
+
+    /// A lvl3 table.
+    Lvl3(Box<Lvl3Table>),
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
-/// aligned, so the lvl3 is put first.
+/// Big monolithic struct for storing the translation tables. Individual levels must be granule
+/// aligned, so the lvl3 is put first. Its alignment carries over to the whole struct.
+///
+/// Only two levels are supported, so the address space must fit into a single lvl2 table.
 #[repr(C)]
-#[repr(align(65536))]
 pub struct FixedSizeTranslationTable<const NUM_TABLES: usize, const START_FROM_TOP: bool> {
-    /// Page descriptors, covering 64 KiB windows per entry.
-    lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
+    /// Page descriptors, covering one granule per entry.
+    lvl3: [Lvl3Table; NUM_TABLES],
 
-    /// Table descriptors, covering 512 MiB windows.
+    /// Table or block descriptors, covering one [`Lvl2Granule`] per entry.
     lvl2: [TableDescriptor; NUM_TABLES],
 
+    /// Table descriptors pointing to the lvl3 tables.
+    ///
+    /// While a lvl2 entry holds a block descriptor, this is the only place that remembers the
+    /// address of its lvl3 table. It is needed when the block is split into pages again.
+    lvl3_table_descs: [TableDescriptor; NUM_TABLES],
+
     /// Have the tables been initialized?
     initialized: bool,
 }
 
+/// Translation tables for a TTBR0_EL1 address space.
+///
+/// In contrast to [`FixedSizeTranslationTable`], the tables are allocated from the kernel heap at
+/// runtime. The table that walks start at is allocated on creation, the ones below it only once a
+/// page in their window is mapped. Depending on the granule and the size of the address space,
+/// walks take up to four levels.
+///
+/// All pages are mapped non-global, so that TLB entries are tagged with the ASID of the address
+/// space. Unmapping or changing a page removes its TLB entries for that ASID on all cores.
+pub struct DynamicTranslationTable<const AS_SIZE: usize> {
+    root: TableNode,
+    phys_root_addr: Address<Physical>,
+    asid: u16,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// Allocate a zeroed table from the kernel heap.
+///
+/// # Safety
+///
+/// - An all-zero bit pattern must be a valid instance of `T`.
+unsafe fn alloc_zeroed_table<T>() -> Result<Box<T>, &'static str> {
+    let ptr = alloc_zeroed(Layout::new::<T>()) as *mut T;
+
+    if ptr.is_null() {
+        return Err("Out of memory allocating a translation table");
+    }
+
+    Ok(Box::from_raw(ptr))
+}
+
 impl<T, const N: usize> StartAddr for [T; N] {
     fn virt_start_addr(&self) -> Address<Virtual> {
         Address::new(self as *const _ as usize)
     }
 }
 
+/// The shift of the window that a descriptor on the given level covers.
+const fn lvl_shift(level: usize) -> usize {
+    KernelGranule::SHIFT + (3 - level) * TABLE_INDEX_BITS
+}
+
+/// The level at which walks of an address space of the given size start.
+const fn start_lvl(as_size_shift: usize) -> usize {
+    let mut level = 3;
+    while (lvl_shift(level) + TABLE_INDEX_BITS) < as_size_shift {
+        level -= 1;
+    }
+
+    level
+}
+
+/// The index of an address into a table of the given level.
+fn lvl_index(level: usize, addr: usize) -> usize {
+    (addr >> lvl_shift(level)) & (NUM_TABLE_ENTRIES - 1)
+}
+
 impl TableDescriptor {
     /// Create an instance.
     ///
@@ -169,15 +293,48 @@ impl TableDescriptor {
     pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: Address<Physical>) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = phys_next_lvl_table_addr.as_usize() >> Granule64KiB::SHIFT;
+        let shifted = phys_next_lvl_table_addr.as_usize() >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR.val(shifted as u64)
                 + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
                 + STAGE1_TABLE_DESCRIPTOR::VALID::True,
         );
 
         TableDescriptor { value: val.get() }
     }
+
+    /// Returns the valid bit.
+    fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_TABLE_DESCRIPTOR::VALID)
+    }
+
+    /// Returns the address of the next level table.
+    fn next_lvl_table_addr(&self) -> Address<Physical> {
+        let shifted = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value)
+            .read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR) as usize;
+
+        Address::new(shifted << ADDR_FIELD_SHIFT)
+    }
+
+    /// Returns the block descriptor if this is a valid block entry.
+    fn try_block(&self) -> Option<BlockDescriptor> {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value);
+
+        if !self.is_valid() || val.matches_all(STAGE1_TABLE_DESCRIPTOR::TYPE::Table) {
+            return None;
+        }
+
+        Some(BlockDescriptor { value: self.value })
+    }
+}
+
+impl convert::From<BlockDescriptor> for TableDescriptor {
+    fn from(block_desc: BlockDescriptor) -> Self {
+        Self {
+            value: block_desc.value,
+        }
+    }
 }
 
 /// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
@@ -191,27 +348,47 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::NORMAL)
             }
+            MemAttributes::WriteThroughDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx
+                        .val(memory::mmu::arch_mmu::mair::NORMAL_WRITE_THROUGH)
+            }
+            // Non-cacheable normal memory is always treated as outer shareable.
+            MemAttributes::NonCacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx
+                        .val(memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::DEVICE)
             }
+            MemAttributes::DeviceGRE => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::DEVICE_GRE)
+            }
         };
 
         // Access Permissions.
         desc += match attribute_fields.acc_perms {
             AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
             AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+            AccessPermissions::ReadOnlyUser => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1_EL0,
+            AccessPermissions::ReadWriteUser => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1_EL0,
         };
 
-        // The execute-never attribute is mapped to PXN in AArch64.
-        desc += if attribute_fields.execute_never {
+        // Execute never.
+        desc += if attribute_fields.privileged_execute_never {
             STAGE1_PAGE_DESCRIPTOR::PXN::True
         } else {
             STAGE1_PAGE_DESCRIPTOR::PXN::False
         };
 
-        // Always set unprivileged exectue-never as long as userspace is not implemented yet.
-        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
+        desc += if attribute_fields.user_execute_never {
+            STAGE1_PAGE_DESCRIPTOR::UXN::True
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::UXN::False
+        };
 
         desc
     }
@@ -226,22 +403,26 @@ impl convert::TryFrom<InMemoryRegister<u64, STAGE1_PAGE_DESCRIPTOR::Register>> f
     ) -> Result<AttributeFields, Self::Error> {
         let mem_attributes = match desc.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
             memory::mmu::arch_mmu::mair::NORMAL => MemAttributes::CacheableDRAM,
+            memory::mmu::arch_mmu::mair::NORMAL_WRITE_THROUGH => MemAttributes::WriteThroughDRAM,
+            memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheableDRAM,
             memory::mmu::arch_mmu::mair::DEVICE => MemAttributes::Device,
+            memory::mmu::arch_mmu::mair::DEVICE_GRE => MemAttributes::DeviceGRE,
             _ => return Err("Unexpected memory attribute"),
         };
 
         let acc_perms = match desc.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP) {
             Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1) => AccessPermissions::ReadOnly,
             Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1) => AccessPermissions::ReadWrite,
+            Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1_EL0) => AccessPermissions::ReadOnlyUser,
+            Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1_EL0) => AccessPermissions::ReadWriteUser,
             _ => return Err("Unexpected access permission"),
         };
 
-        let execute_never = desc.read(STAGE1_PAGE_DESCRIPTOR::PXN) > 0;
-
         Ok(AttributeFields {
             mem_attributes,
             acc_perms,
-            execute_never,
+            privileged_execute_never: desc.is_set(STAGE1_PAGE_DESCRIPTOR::PXN),
+            user_execute_never: desc.is_set(STAGE1_PAGE_DESCRIPTOR::UXN),
         })
     }
 }
@@ -261,9 +442,9 @@ impl PageDescriptor {
     ) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = phys_output_page_addr.into_inner().as_usize() >> Granule64KiB::SHIFT;
+        let shifted = phys_output_page_addr.into_inner().as_usize() >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted as u64)
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted as u64)
                 + STAGE1_PAGE_DESCRIPTOR::AF::True
                 + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
                 + STAGE1_PAGE_DESCRIPTOR::VALID::True
@@ -273,6 +454,14 @@ impl PageDescriptor {
         Self { value: val.get() }
     }
 
+    /// Mark the page as not global.
+    fn set_non_global(&mut self) {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+        val.modify(STAGE1_PAGE_DESCRIPTOR::nG::True);
+
+        self.value = val.get();
+    }
+
     /// Returns the valid bit.
     fn is_valid(&self) -> bool {
         InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
@@ -282,15 +471,81 @@ impl PageDescriptor {
     /// Returns the output page.
     fn output_page_addr(&self) -> PageAddress<Physical> {
         let shifted = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
-            .read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB) as usize;
+            .read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) as usize;
 
-        PageAddress::from(shifted << Granule64KiB::SHIFT)
+        PageAddress::from(shifted << ADDR_FIELD_SHIFT)
     }
 
     /// Returns the attributes.
     fn try_attributes(&self) -> Result<AttributeFields, &'static str> {
         InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value).try_into()
     }
+
+    /// Returns a copy with the same output page and global bit, but new attributes.
+    fn with_attributes(&self, attribute_fields: &AttributeFields) -> Self {
+        let mut new_desc = Self::from_output_page_addr(self.output_page_addr(), attribute_fields);
+
+        if InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_PAGE_DESCRIPTOR::nG)
+        {
+            new_desc.set_non_global();
+        }
+
+        new_desc
+    }
+}
+
+impl BlockDescriptor {
+    /// Create an instance from the descriptor of the first page in the block.
+    ///
+    /// The output page must be aligned to the block size.
+    fn from_first_page_descriptor(page_desc: PageDescriptor) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(page_desc.value);
+        val.modify(STAGE1_TABLE_DESCRIPTOR::TYPE::Block);
+
+        Self { value: val.get() }
+    }
+
+    /// Returns the page descriptor that maps the n-th page of the block in the same way.
+    fn page_descriptor(&self, page_index: usize) -> PageDescriptor {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        let shifted = val.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR)
+            + (page_index << (KernelGranule::SHIFT - ADDR_FIELD_SHIFT)) as u64;
+        val.modify(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted) + STAGE1_PAGE_DESCRIPTOR::TYPE::Page,
+        );
+
+        PageDescriptor { value: val.get() }
+    }
+
+    /// Returns a copy with the same output address and global bit, but new attributes.
+    fn with_attributes(&self, attribute_fields: &AttributeFields) -> Self {
+        Self::from_first_page_descriptor(self.page_descriptor(0).with_attributes(attribute_fields))
+    }
+}
+
+/// Checks that `attr` follows the mapping policy, and that all pages of a region are mapped and
+/// keep their memory type under it.
+fn check_protect(
+    descs: impl Iterator<Item = Result<PageDescriptor, &'static str>>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    attr.check_policy()?;
+
+    for desc in descs {
+        let desc = desc?;
+
+        if !desc.is_valid() {
+            return Err("Page marked invalid");
+        }
+
+        if desc.try_attributes()?.mem_attributes != attr.mem_attributes {
+            return Err("Changing the memory type of a mapping is not supported");
+        }
+    }
+
+    Ok(())
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -300,32 +555,37 @@ impl PageDescriptor {
 impl<const AS_SIZE: usize> memory::mmu::AssociatedTranslationTable
     for memory::mmu::AddressSpace<AS_SIZE>
 where
-    [u8; Self::SIZE >> Granule512MiB::SHIFT]: Sized,
+    [u8; Self::SIZE >> Lvl2Granule::SHIFT]: Sized,
 {
-    type TableStartFromTop =
-        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, true>;
+    type TableStartFromTop = FixedSizeTranslationTable<{ Self::SIZE >> Lvl2Granule::SHIFT }, true>;
 
     type TableStartFromBottom =
-        FixedSizeTranslationTable<{ Self::SIZE >> Granule512MiB::SHIFT }, false>;
+        FixedSizeTranslationTable<{ Self::SIZE >> Lvl2Granule::SHIFT }, false>;
+
+    type DynamicTableStartFromBottom = DynamicTranslationTable<AS_SIZE>;
 }
 
 impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     FixedSizeTranslationTable<NUM_TABLES, START_FROM_TOP>
 {
     const START_FROM_TOP_OFFSET: Address<Virtual> =
-        Address::new((usize::MAX - (Granule512MiB::SIZE * NUM_TABLES)) + 1);
+        Address::new((usize::MAX - (Lvl2Granule::SIZE * NUM_TABLES)) + 1);
 
     /// Create an instance.
     #[allow(clippy::assertions_on_constants)]
     const fn _new(for_precompute: bool) -> Self {
-        assert!(bsp::memory::mmu::KernelGranule::SIZE == Granule64KiB::SIZE);
+        assert!(core::mem::align_of::<Lvl3Table>() == KernelGranule::SIZE);
 
         // Can't have a zero-sized address space.
         assert!(NUM_TABLES > 0);
 
+        // Walks must start at lvl2.
+        assert!(NUM_TABLES <= NUM_TABLE_ENTRIES);
+
         Self {
-            lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
+            lvl3: [Table([PageDescriptor::new_zeroed(); NUM_TABLE_ENTRIES]); NUM_TABLES],
             lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
+            lvl3_table_descs: [TableDescriptor::new_zeroed(); NUM_TABLES],
             initialized: for_precompute,
         }
     }
@@ -351,8 +611,8 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             addr = addr - Self::START_FROM_TOP_OFFSET;
         }
 
-        let lvl2_index = addr.as_usize() >> Granule512MiB::SHIFT;
-        let lvl3_index = (addr.as_usize() & Granule512MiB::MASK) >> Granule64KiB::SHIFT;
+        let lvl2_index = addr.as_usize() >> Lvl2Granule::SHIFT;
+        let lvl3_index = (addr.as_usize() & Lvl2Granule::MASK) >> KernelGranule::SHIFT;
 
         if lvl2_index > (NUM_TABLES - 1) {
             return Err("Virtual page is out of bounds of translation table");
@@ -362,15 +622,20 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     }
 
     /// Returns the PageDescriptor corresponding to the supplied page address.
+    ///
+    /// Pages that are covered by a block yield an equivalent page descriptor.
     #[inline(always)]
     fn page_descriptor_from_page_addr(
         &self,
         virt_page_addr: PageAddress<Virtual>,
-    ) -> Result<&PageDescriptor, &'static str> {
+    ) -> Result<PageDescriptor, &'static str> {
         let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
-        let desc = &self.lvl3[lvl2_index][lvl3_index];
 
-        Ok(desc)
+        if let Some(block_desc) = self.lvl2[lvl2_index].try_block() {
+            return Ok(block_desc.page_descriptor(lvl3_index));
+        }
+
+        Ok(self.lvl3[lvl2_index].0[lvl3_index])
     }
 
     /// Sets the PageDescriptor corresponding to the supplied page address.
@@ -383,8 +648,12 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         new_desc: &PageDescriptor,
     ) -> Result<(), &'static str> {
         let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
-        let desc = &mut self.lvl3[lvl2_index][lvl3_index];
 
+        if self.lvl2[lvl2_index].try_block().is_some() {
+            return Err("Virtual page is already mapped");
+        }
+
+        let desc = &mut self.lvl3[lvl2_index].0[lvl3_index];
         if desc.is_valid() {
             return Err("Virtual page is already mapped");
         }
@@ -392,6 +661,384 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         *desc = *new_desc;
         Ok(())
     }
+
+    /// The first virtual page that the given lvl2 entry covers.
+    fn lvl2_start_page_addr(lvl2_index: usize) -> PageAddress<Virtual> {
+        let mut addr = Address::new(lvl2_index << Lvl2Granule::SHIFT);
+
+        if START_FROM_TOP {
+            addr = addr + Self::START_FROM_TOP_OFFSET.as_usize();
+        }
+
+        PageAddress::from(addr)
+    }
+
+    /// Replace a lvl2 entry using a break-before-make sequence.
+    ///
+    /// This is needed whenever the size of a translation changes, i.e. when switching between a
+    /// block and a table. Accesses to the window in the meantime take a translation fault, so this
+    /// is only used for windows that have no valid translation either before or after.
+    fn replace_lvl2_entry(&mut self, lvl2_index: usize, new_desc: TableDescriptor) {
+        self.lvl2[lvl2_index] = TableDescriptor::new_zeroed();
+
+        // Also removes walk cache entries that might still point to the lvl3 table. Kernel
+        // translations are global, so any ASID matches.
+        memory::mmu::arch_mmu::mmu().invalidate_tlb_page(0, Self::lvl2_start_page_addr(lvl2_index));
+
+        self.lvl2[lvl2_index] = new_desc;
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+    }
+
+    /// Try to map a whole lvl2 window with a single block descriptor.
+    ///
+    /// Returns `false` if the window can't be mapped as a block, either because the addresses are
+    /// not aligned, the region is too small or parts of the window are already mapped.
+    fn try_map_block(
+        &mut self,
+        virt_page_addr: PageAddress<Virtual>,
+        phys_page_addr: PageAddress<Physical>,
+        num_pages_left: usize,
+        attr: &AttributeFields,
+    ) -> Result<bool, &'static str> {
+        let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+        if (lvl3_index != 0)
+            || (phys_page_addr.into_inner().as_usize() & Lvl2Granule::MASK != 0)
+            || (num_pages_left < NUM_TABLE_ENTRIES)
+            || self.lvl2[lvl2_index].try_block().is_some()
+            || self.lvl3[lvl2_index].0.iter().any(PageDescriptor::is_valid)
+        {
+            return Ok(false);
+        }
+
+        let page_desc = PageDescriptor::from_output_page_addr(phys_page_addr, attr);
+        let block_desc = BlockDescriptor::from_first_page_descriptor(page_desc);
+        self.replace_lvl2_entry(lvl2_index, block_desc.into());
+
+        Ok(true)
+    }
+
+    /// Split a block into lvl3 pages that translate the same way.
+    ///
+    /// The window stays in use while it is split, and a translation fault in it can not be
+    /// resolved. With FEAT_BBM level 2, the table descriptor therefore replaces the block
+    /// descriptor directly. Otherwise, a break-before-make sequence leaves the window without
+    /// translation for a few instructions. That is only done while the boot core runs alone, and
+    /// if neither the sequence itself nor the lvl2 table are in the window.
+    fn split_block(&mut self, lvl2_index: usize) -> Result<(), &'static str> {
+        let block_desc = match self.lvl2[lvl2_index].try_block() {
+            None => return Ok(()),
+            Some(x) => x,
+        };
+
+        let window_start = Self::lvl2_start_page_addr(lvl2_index);
+        let in_window = |addr: usize| {
+            addr.wrapping_sub(window_start.into_inner().as_usize()) < Lvl2Granule::SIZE
+        };
+        let entry = &mut self.lvl2[lvl2_index].value as *mut u64;
+
+        let without_break = memory::mmu::arch_mmu::supports_bbm_level_2();
+        if !without_break {
+            if state::state_manager().is_multi_core_main() {
+                return Err("Splitting a block needs FEAT_BBM once the secondary cores run");
+            }
+
+            let code = Self::split_block as fn(&mut Self, usize) -> Result<(), &'static str>;
+            if in_window(code as usize) || in_window(entry as usize) {
+                return Err("Can not split the block that holds the code or tables splitting it");
+            }
+        }
+
+        for (lvl3_index, desc) in self.lvl3[lvl2_index].0.iter_mut().enumerate() {
+            *desc = block_desc.page_descriptor(lvl3_index);
+        }
+        barrier::dsb(barrier::ISHST);
+
+        let new_value = self.lvl3_table_descs[lvl2_index].value;
+        if without_break {
+            unsafe { core::ptr::write_volatile(entry, new_value) };
+
+            // Kernel translations are global, so any ASID matches.
+            memory::mmu::arch_mmu::mmu().invalidate_tlb_page(0, window_start);
+            return Ok(());
+        }
+
+        // A single asm block, so that nothing but the instructions in it and the entry are touched
+        // while the window has no translation.
+        let operand = memory::mmu::arch_mmu::tlbi_va_operand(0, window_start);
+        unsafe {
+            asm!(
+                "str xzr, [{entry}]",
+                "dsb ishst",
+                "tlbi vae1is, {operand}",
+                "dsb ish",
+                "str {new_value}, [{entry}]",
+                "dsb ishst",
+                "isb",
+                entry = in(reg) entry,
+                operand = in(reg) operand,
+                new_value = in(reg) new_value,
+                options(nostack)
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Split the blocks that are only partially covered by the region.
+    ///
+    /// Only the windows at both ends of the region can be affected.
+    fn split_partially_covered_blocks(
+        &mut self,
+        virt_region: &MemoryRegion<Virtual>,
+    ) -> Result<(), &'static str> {
+        for virt_page_addr in [
+            virt_region.start_page_addr(),
+            virt_region.end_inclusive_page_addr(),
+        ] {
+            let (lvl2_index, _) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+            let block_start = Self::lvl2_start_page_addr(lvl2_index);
+            let block_end_inclusive = block_start
+                .checked_offset(NUM_TABLE_ENTRIES as isize - 1)
+                .unwrap();
+            let fully_covered = (virt_region.start_page_addr() <= block_start)
+                && (virt_region.end_inclusive_page_addr() >= block_end_inclusive);
+
+            if !fully_covered {
+                self.split_block(lvl2_index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walk the tables the way the hardware does, starting at the lvl2 table at `phys_root_addr`.
+    ///
+    /// `f` is called with the first virtual page, the first physical page, the number of pages
+    /// and the attributes of each valid block and page, in ascending order of virtual addresses.
+    /// Fails if a table address doesn't lead to one of the tables of this struct.
+    pub fn walk(
+        &self,
+        phys_root_addr: Address<Physical>,
+        mut f: impl FnMut(
+            PageAddress<Virtual>,
+            PageAddress<Physical>,
+            usize,
+            Result<AttributeFields, &'static str>,
+        ),
+    ) -> Result<(), &'static str> {
+        let phys_lvl2_addr =
+            memory::mmu::try_kernel_virt_addr_to_phys_addr(self.lvl2.virt_start_addr())?;
+        if phys_root_addr != phys_lvl2_addr {
+            return Err("Root table address does not point to the lvl2 table");
+        }
+
+        for (lvl2_index, lvl2_desc) in self.lvl2.iter().enumerate() {
+            if !lvl2_desc.is_valid() {
+                continue;
+            }
+
+            let virt_start_page_addr = Self::lvl2_start_page_addr(lvl2_index);
+
+            if let Some(block_desc) = lvl2_desc.try_block() {
+                let page_desc = block_desc.page_descriptor(0);
+                f(
+                    virt_start_page_addr,
+                    page_desc.output_page_addr(),
+                    NUM_TABLE_ENTRIES,
+                    page_desc.try_attributes(),
+                );
+                continue;
+            }
+
+            let virt_lvl3_addr = self.lvl3[lvl2_index].0.virt_start_addr();
+            let phys_lvl3_addr = memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_lvl3_addr)?;
+            if lvl2_desc.next_lvl_table_addr() != phys_lvl3_addr {
+                return Err("Table descriptor does not point to its lvl3 table");
+            }
+
+            for (lvl3_index, page_desc) in self.lvl3[lvl2_index].0.iter().enumerate() {
+                if !page_desc.is_valid() {
+                    continue;
+                }
+
+                f(
+                    virt_start_page_addr
+                        .checked_offset(lvl3_index as isize)
+                        .unwrap(),
+                    page_desc.output_page_addr(),
+                    1,
+                    page_desc.try_attributes(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move all page mappings up by the given number of pages.
+    ///
+    /// This is used to apply the KASLR offset to the precomputed kernel tables. It runs with the
+    /// MMU turned off, so it does no TLB maintenance.
+    pub fn slide(&mut self, num_pages: usize) -> Result<(), &'static str> {
+        let num_entries = NUM_TABLES * NUM_TABLE_ENTRIES;
+        let index = |i: usize| (i / NUM_TABLE_ENTRIES, i % NUM_TABLE_ENTRIES);
+
+        if num_pages >= num_entries {
+            return Err("Slide exceeds the address space");
+        }
+
+        if self.lvl2.iter().any(|desc| desc.try_block().is_some()) {
+            return Err("Block mappings can't be slid");
+        }
+
+        let pushed_out = ((num_entries - num_pages)..num_entries).any(|i| {
+            let (lvl2_index, lvl3_index) = index(i);
+            self.lvl3[lvl2_index].0[lvl3_index].is_valid()
+        });
+        if pushed_out {
+            return Err("Slide would move mappings out of the address space");
+        }
+
+        for i in (num_pages..num_entries).rev() {
+            let (to_lvl2, to_lvl3) = index(i);
+            let (from_lvl2, from_lvl3) = index(i - num_pages);
+
+            self.lvl3[to_lvl2].0[to_lvl3] = self.lvl3[from_lvl2].0[from_lvl3];
+        }
+
+        for i in 0..num_pages {
+            let (lvl2_index, lvl3_index) = index(i);
+            self.lvl3[lvl2_index].0[lvl3_index] = PageDescriptor::new_zeroed();
+        }
+
+        Ok(())
+    }
+}
+
+impl TableNode {
+    /// Allocate an empty table for the given level.
+    ///
+    /// Returns the node together with the physical address of its table.
+    fn new(level: usize, num_entries: usize) -> Result<(Self, Address<Physical>), &'static str> {
+        if level == 3 {
+            let table: Box<Lvl3Table> = unsafe { alloc_zeroed_table()? };
+            let phys_table_addr =
+                memory::mmu::try_kernel_virt_addr_to_phys_addr(table.0.virt_start_addr())?;
+
+            return Ok((Self::Lvl3(table), phys_table_addr));
+        }
+
+        let table: Box<InnerTable> = unsafe { alloc_zeroed_table()? };
+        let phys_table_addr =
+            memory::mmu::try_kernel_virt_addr_to_phys_addr(table.0.virt_start_addr())?;
+
+        let mut next_lvl = Vec::new();
+        next_lvl.resize_with(num_entries, || None);
+
+        Ok((Self::Inner { table, next_lvl }, phys_table_addr))
+    }
+}
+
+impl<const AS_SIZE: usize> DynamicTranslationTable<AS_SIZE> {
+    /// The level that walks start at.
+    const START_LVL: usize = start_lvl(memory::mmu::AddressSpace::<AS_SIZE>::SIZE_SHIFT);
+
+    /// Create an instance for the address space with the given ASID.
+    #[allow(clippy::assertions_on_constants)]
+    pub fn new(asid: u16) -> Result<Self, &'static str> {
+        assert!(core::mem::align_of::<Lvl3Table>() == KernelGranule::SIZE);
+
+        let num_root_entries = AS_SIZE >> lvl_shift(Self::START_LVL);
+        let (root, phys_root_addr) = TableNode::new(Self::START_LVL, num_root_entries)?;
+
+        Ok(Self {
+            root,
+            phys_root_addr,
+            asid,
+        })
+    }
+
+    /// Invalidate the TLB entries of a region on all cores.
+    fn invalidate_tlb(&self, virt_region: &MemoryRegion<Virtual>) {
+        for virt_page_addr in virt_region.into_iter() {
+            memory::mmu::arch_mmu::mmu().invalidate_tlb_page(self.asid, virt_page_addr);
+        }
+    }
+
+    /// The physical address of the table that walks start at, which is what TTBR0_EL1 must point
+    /// to.
+    pub fn phys_base_address(&self) -> Address<Physical> {
+        self.phys_root_addr
+    }
+
+    /// Checks that the page is inside of the address space.
+    #[inline(always)]
+    fn addr_from_page_addr(virt_page_addr: PageAddress<Virtual>) -> Result<usize, &'static str> {
+        let addr = virt_page_addr.into_inner().as_usize();
+
+        if addr >= AS_SIZE {
+            return Err("Virtual page is out of bounds of translation table");
+        }
+
+        Ok(addr)
+    }
+
+    /// Returns the PageDescriptor corresponding to the supplied page address.
+    fn page_descriptor_from_page_addr(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<&PageDescriptor, &'static str> {
+        let addr = Self::addr_from_page_addr(virt_page_addr)?;
+
+        let mut node = &self.root;
+        let mut level = Self::START_LVL;
+        loop {
+            match node {
+                TableNode::Lvl3(table) => return Ok(&table.0[lvl_index(3, addr)]),
+                TableNode::Inner { next_lvl, .. } => {
+                    node = next_lvl[lvl_index(level, addr)]
+                        .as_deref()
+                        .ok_or("Page marked invalid")?;
+                    level += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the mutable PageDescriptor corresponding to the supplied page address.
+    ///
+    /// Allocates the tables on the way if needed.
+    fn page_descriptor_mut_from_page_addr(
+        &mut self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<&mut PageDescriptor, &'static str> {
+        let addr = Self::addr_from_page_addr(virt_page_addr)?;
+
+        let mut node = &mut self.root;
+        let mut level = Self::START_LVL;
+        loop {
+            match node {
+                TableNode::Lvl3(table) => return Ok(&mut table.0[lvl_index(3, addr)]),
+                TableNode::Inner { table, next_lvl } => {
+                    let index = lvl_index(level, addr);
+
+                    if next_lvl[index].is_none() {
+                        let (new_node, phys_table_addr) =
+                            TableNode::new(level + 1, NUM_TABLE_ENTRIES)?;
+
+                        table.0[index] = TableDescriptor::from_next_lvl_table_addr(phys_table_addr);
+                        next_lvl[index] = Some(Box::new(new_node));
+                    }
+
+                    node = next_lvl[index].as_deref_mut().unwrap();
+                    level += 1;
+                }
+            }
+        }
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -409,11 +1056,12 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
 
         // Populate the l2 entries.
         for (lvl2_nr, lvl2_entry) in self.lvl2.iter_mut().enumerate() {
-            let virt_table_addr = self.lvl3[lvl2_nr].virt_start_addr();
+            let virt_table_addr = self.lvl3[lvl2_nr].0.virt_start_addr();
             let phys_table_addr = memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_table_addr)?;
 
             let new_desc = TableDescriptor::from_next_lvl_table_addr(phys_table_addr);
             *lvl2_entry = new_desc;
+            self.lvl3_table_descs[lvl2_nr] = new_desc;
         }
 
         self.initialized = true;
@@ -433,19 +1081,108 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
             return Err("Tried to map memory regions with unequal sizes");
         }
 
+        attr.check_policy()?;
+
         if phys_region.end_exclusive_page_addr() > bsp::memory::phys_addr_space_end_exclusive_addr()
         {
             return Err("Tried to map outside of physical address space");
         }
 
-        let iter = phys_region.into_iter().zip(virt_region.into_iter());
-        for (phys_page_addr, virt_page_addr) in iter {
+        let num_pages = virt_region.num_pages();
+        let mut page_index = 0;
+        while page_index < num_pages {
+            let offset = page_index as isize;
+            let virt_page_addr = virt_region
+                .start_page_addr()
+                .checked_offset(offset)
+                .unwrap();
+            let phys_page_addr = phys_region
+                .start_page_addr()
+                .checked_offset(offset)
+                .unwrap();
+
+            if self.try_map_block(virt_page_addr, phys_page_addr, num_pages - page_index, attr)? {
+                page_index += NUM_TABLE_ENTRIES;
+                continue;
+            }
+
             let new_desc = PageDescriptor::from_output_page_addr(phys_page_addr, attr);
-            let virt_page = virt_page_addr;
+            self.set_page_descriptor_from_page_addr(virt_page_addr, &new_desc)?;
+            page_index += 1;
+        }
+
+        // Make the new descriptors visible to the table walker, also for the instructions that
+        // follow on this core.
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+
+        Ok(())
+    }
+
+    unsafe fn unmap_at(&mut self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        for virt_page_addr in virt_region.into_iter() {
+            let desc = self.page_descriptor_from_page_addr(virt_page_addr)?;
+
+            if !desc.is_valid() {
+                return Err("Page marked invalid");
+            }
+        }
+
+        self.split_partially_covered_blocks(virt_region)?;
+
+        for virt_page_addr in virt_region.into_iter() {
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+            // Blocks that are left over are fully covered by the region. Their lvl3 table is empty.
+            if self.lvl2[lvl2_index].try_block().is_some() {
+                self.replace_lvl2_entry(lvl2_index, self.lvl3_table_descs[lvl2_index]);
+                continue;
+            }
+
+            self.lvl3[lvl2_index].0[lvl3_index] = PageDescriptor::new_zeroed();
+        }
+
+        barrier::dsb(barrier::ISHST);
+
+        Ok(())
+    }
+
+    unsafe fn protect_at(
+        &mut self,
+        virt_region: &MemoryRegion<Virtual>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        check_protect(
+            virt_region
+                .into_iter()
+                .map(|virt_page_addr| self.page_descriptor_from_page_addr(virt_page_addr)),
+            attr,
+        )?;
+
+        self.split_partially_covered_blocks(virt_region)?;
+
+        for virt_page_addr in virt_region.into_iter() {
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+
+            // Blocks that are left over are fully covered by the region. The size of the
+            // translation stays the same, so they can be updated in place.
+            if let Some(block_desc) = self.lvl2[lvl2_index].try_block() {
+                if lvl3_index == 0 {
+                    self.lvl2[lvl2_index] = block_desc.with_attributes(attr).into();
+                }
+                continue;
+            }
 
-            self.set_page_descriptor_from_page_addr(virt_page, &new_desc)?;
+            let desc = &mut self.lvl3[lvl2_index].0[lvl3_index];
+            *desc = desc.with_attributes(attr);
         }
 
+        barrier::dsb(barrier::ISHST);
+
         Ok(())
     }
 
@@ -489,6 +1226,131 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
     }
 }
 
+impl<const AS_SIZE: usize> memory::mmu::translation_table::interface::TranslationTable
+    for DynamicTranslationTable<AS_SIZE>
+{
+    fn init(&mut self) -> Result<(), &'static str> {
+        // Tables are set up on creation and on demand.
+        Ok(())
+    }
+
+    unsafe fn map_at(
+        &mut self,
+        virt_region: &MemoryRegion<Virtual>,
+        phys_region: &MemoryRegion<Physical>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        if virt_region.size() != phys_region.size() {
+            return Err("Tried to map memory regions with unequal sizes");
+        }
+
+        attr.check_policy()?;
+
+        if phys_region.end_exclusive_page_addr() > bsp::memory::phys_addr_space_end_exclusive_addr()
+        {
+            return Err("Tried to map outside of physical address space");
+        }
+
+        let iter = phys_region.into_iter().zip(virt_region.into_iter());
+        for (phys_page_addr, virt_page_addr) in iter {
+            let mut new_desc = PageDescriptor::from_output_page_addr(phys_page_addr, attr);
+            new_desc.set_non_global();
+
+            let desc = self.page_descriptor_mut_from_page_addr(virt_page_addr)?;
+            if desc.is_valid() {
+                return Err("Virtual page is already mapped");
+            }
+
+            *desc = new_desc;
+        }
+
+        // Make the new descriptors visible to the table walker.
+        barrier::dsb(barrier::ISHST);
+
+        Ok(())
+    }
+
+    unsafe fn unmap_at(&mut self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        for virt_page_addr in virt_region.into_iter() {
+            let desc = self.page_descriptor_from_page_addr(virt_page_addr)?;
+
+            if !desc.is_valid() {
+                return Err("Page marked invalid");
+            }
+        }
+
+        for virt_page_addr in virt_region.into_iter() {
+            let desc = self.page_descriptor_mut_from_page_addr(virt_page_addr)?;
+            *desc = PageDescriptor::new_zeroed();
+        }
+
+        // Also publishes the zeroed descriptors.
+        self.invalidate_tlb(virt_region);
+
+        Ok(())
+    }
+
+    unsafe fn protect_at(
+        &mut self,
+        virt_region: &MemoryRegion<Virtual>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        check_protect(
+            virt_region
+                .into_iter()
+                .map(|virt_page_addr| self.page_descriptor_from_page_addr(virt_page_addr).copied()),
+            attr,
+        )?;
+
+        for virt_page_addr in virt_region.into_iter() {
+            let desc = self.page_descriptor_mut_from_page_addr(virt_page_addr)?;
+
+            *desc = desc.with_attributes(attr);
+        }
+
+        // Also publishes the updated descriptors.
+        self.invalidate_tlb(virt_region);
+
+        Ok(())
+    }
+
+    fn try_virt_page_addr_to_phys_page_addr(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<PageAddress<Physical>, &'static str> {
+        let page_desc = self.page_descriptor_from_page_addr(virt_page_addr)?;
+
+        if !page_desc.is_valid() {
+            return Err("Page marked invalid");
+        }
+
+        Ok(page_desc.output_page_addr())
+    }
+
+    fn try_page_attributes(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<AttributeFields, &'static str> {
+        let page_desc = self.page_descriptor_from_page_addr(virt_page_addr)?;
+
+        if !page_desc.is_valid() {
+            return Err("Page marked invalid");
+        }
+
+        page_desc.try_attributes()
+    }
+
+    fn try_virt_addr_to_phys_addr(
+        &self,
+        virt_addr: Address<Virtual>,
+    ) -> Result<Address<Physical>, &'static str> {
+        let virt_page = PageAddress::from(virt_addr.align_down_page());
+        let phys_page = self.try_virt_page_addr_to_phys_page_addr(virt_page)?;
+
+        Ok(phys_page.into_inner() + virt_addr.offset_into_page())
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
@@ -499,6 +1361,9 @@ pub type MinSizeTranslationTable = FixedSizeTranslationTable<1, true>;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::mmu::{
+        arch_mmu::Granule64KiB, translation_table::interface::TranslationTable,
+    };
     use test_macros::kernel_test;
 
     /// Check if the size of `struct TableDescriptor` is as expected.
@@ -518,4 +1383,176 @@ mod tests {
             core::mem::size_of::<u64>()
         );
     }
+
+    /// All memory attributes survive the round trip through a page descriptor.
+    #[kernel_test]
+    fn page_descriptor_attributes_round_trip() {
+        let phys_page_addr = PageAddress::<Physical>::from(KernelGranule::SIZE);
+
+        for mem_attributes in [
+            MemAttributes::CacheableDRAM,
+            MemAttributes::WriteThroughDRAM,
+            MemAttributes::NonCacheableDRAM,
+            MemAttributes::Device,
+            MemAttributes::DeviceGRE,
+        ] {
+            let attr = AttributeFields {
+                mem_attributes,
+                acc_perms: AccessPermissions::ReadWrite,
+                privileged_execute_never: true,
+                user_execute_never: true,
+            };
+            let desc = PageDescriptor::from_output_page_addr(phys_page_addr, &attr);
+
+            assert_eq!(desc.try_attributes(), Ok(attr));
+            assert_eq!(desc.output_page_addr(), phys_page_addr);
+        }
+    }
+
+    /// Aligned regions that cover a whole lvl2 window are mapped with a block, which is split
+    /// when only a part of it changes.
+    #[kernel_test]
+    fn block_mapping_and_split() {
+        // Too large for the stack.
+        let mut tables: Box<FixedSizeTranslationTable<1, false>> =
+            unsafe { alloc_zeroed_table().unwrap() };
+        assert_eq!(tables.init(), Ok(()));
+
+        let virt_region = MemoryRegion::new(
+            PageAddress::<Virtual>::from(0),
+            PageAddress::from(Lvl2Granule::SIZE),
+        );
+        let phys_region = MemoryRegion::new(
+            PageAddress::<Physical>::from(0),
+            PageAddress::from(Lvl2Granule::SIZE),
+        );
+        let rw = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        let ro = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnly,
+            ..rw
+        };
+
+        unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &rw), Ok(())) };
+        assert!(tables.lvl2[0].try_block().is_some());
+
+        let page_5 = virt_region.start_page_addr().checked_offset(5).unwrap();
+        let page_6 = page_5.checked_offset(1).unwrap();
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(page_5),
+            Ok(PageAddress::from(5 * KernelGranule::SIZE))
+        );
+
+        // Changing a single page splits the block.
+        let sub_region = MemoryRegion::new(page_5, page_6);
+        unsafe { assert_eq!(tables.protect_at(&sub_region, &ro), Ok(())) };
+        assert!(tables.lvl2[0].try_block().is_none());
+        assert_eq!(tables.try_page_attributes(page_5), Ok(ro));
+        assert_eq!(tables.try_page_attributes(page_6), Ok(rw));
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(page_6),
+            Ok(PageAddress::from(6 * KernelGranule::SIZE))
+        );
+
+        // Once the window is empty again, it can hold a block again.
+        unsafe { assert_eq!(tables.unmap_at(&virt_region), Ok(())) };
+        assert!(tables.try_page_attributes(page_6).is_err());
+
+        unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &rw), Ok(())) };
+        assert!(tables.lvl2[0].try_block().is_some());
+        unsafe { assert_eq!(tables.unmap_at(&virt_region), Ok(())) };
+        assert!(tables.lvl2[0].is_valid());
+        assert!(tables.lvl2[0].try_block().is_none());
+    }
+
+    /// Sliding moves the page mappings up, but never out of the address space.
+    #[kernel_test]
+    fn slide_moves_page_mappings() {
+        let mut tables: Box<FixedSizeTranslationTable<1, false>> =
+            unsafe { alloc_zeroed_table().unwrap() };
+        assert_eq!(tables.init(), Ok(()));
+
+        let virt_page = PageAddress::<Virtual>::from(KernelGranule::SIZE);
+        let virt_region = MemoryRegion::new(virt_page, virt_page.checked_offset(2).unwrap());
+        let phys_page = PageAddress::<Physical>::from(8 * KernelGranule::SIZE);
+        let phys_region = MemoryRegion::new(phys_page, phys_page.checked_offset(2).unwrap());
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+
+        unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
+        assert_eq!(tables.slide(3), Ok(()));
+
+        assert!(tables.try_page_attributes(virt_page).is_err());
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(virt_page.checked_offset(3).unwrap()),
+            Ok(phys_page)
+        );
+        assert_eq!(
+            tables.try_virt_page_addr_to_phys_page_addr(virt_page.checked_offset(4).unwrap()),
+            Ok(phys_page.checked_offset(1).unwrap())
+        );
+
+        assert_eq!(
+            tables.slide(NUM_TABLE_ENTRIES - 4),
+            Err("Slide would move mappings out of the address space")
+        );
+        assert_eq!(
+            tables.slide(NUM_TABLE_ENTRIES),
+            Err("Slide exceeds the address space")
+        );
+    }
+
+    /// Walks of large address spaces take more than two levels.
+    #[kernel_test]
+    fn dynamic_table_multi_level_walk() {
+        type Tables = DynamicTranslationTable<{ 1 << 48 }>;
+
+        // 64 KiB pages have no lvl0.
+        let expected_start_lvl = match KernelGranule::SIZE {
+            Granule64KiB::SIZE => 1,
+            _ => 0,
+        };
+        assert_eq!(Tables::START_LVL, expected_start_lvl);
+
+        let mut tables = Tables::new(0).unwrap();
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWriteUser,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+
+        let low_page = PageAddress::<Virtual>::from(0);
+        let high_page = PageAddress::<Virtual>::from((1 << 48) - KernelGranule::SIZE);
+        let phys_page = PageAddress::<Physical>::from(0);
+        let phys_region = MemoryRegion::new(phys_page, phys_page.checked_offset(1).unwrap());
+
+        for virt_page in [low_page, high_page] {
+            let virt_region = MemoryRegion::new(virt_page, virt_page.checked_offset(1).unwrap());
+
+            unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
+            assert_eq!(
+                tables.try_virt_page_addr_to_phys_page_addr(virt_page),
+                Ok(phys_page)
+            );
+        }
+
+        let unmapped_page = low_page.checked_offset(1).unwrap();
+        assert_eq!(
+            tables.try_page_attributes(unmapped_page),
+            Err("Page marked invalid")
+        );
+        assert_eq!(
+            tables.try_page_attributes(PageAddress::from(1 << 48)),
+            Err("Virtual page is out of bounds of translation table")
+        );
+    }
 }
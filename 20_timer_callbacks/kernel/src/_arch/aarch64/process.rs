@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural user process support.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::process::arch_process
+
+use crate::{
+    exception,
+    memory::{Address, Virtual},
+    process::syscall,
+};
+use aarch64_cpu::registers::*;
+use core::{arch::global_asm, cell::UnsafeCell};
+use tock_registers::interfaces::Writeable;
+
+// Assembly counterpart to this file.
+global_asm!(
+    include_str!("process.s"),
+    CONST_SYS_EXIT = const syscall::nr::EXIT
+);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Drop to user space and start executing at `entry`.
+///
+/// If `entry` returns, the process exits with code 0.
+///
+/// # Safety
+///
+/// - `entry` and `user_stack_top` must be mapped accessible from user space.
+/// - The calling thread's kernel stack below the current stack pointer will be used for exceptions
+///   taken from user space.
+pub unsafe fn enter_user(entry: Address<Virtual>, user_stack_top: Address<Virtual>) -> ! {
+    extern "Rust" {
+        static __process_user_exit_trampoline: UnsafeCell<()>;
+    }
+
+    extern "C" {
+        fn __process_eret_to_user(user_lr: u64) -> !;
+    }
+
+    // An IRQ or FIQ taken from here on would overwrite ELR_EL1 and SPSR_EL1.
+    exception::asynchronous::local_irq_mask();
+    exception::asynchronous::local_fiq_mask();
+
+    // Return to EL0 with IRQs and FIQs unmasked. Debug exceptions and SError stay masked.
+    SPSR_EL1.write(
+        SPSR_EL1::D::Masked
+            + SPSR_EL1::A::Masked
+            + SPSR_EL1::I::Unmasked
+            + SPSR_EL1::F::Unmasked
+            + SPSR_EL1::M::EL0t,
+    );
+
+    ELR_EL1.set(entry.as_usize() as u64);
+    SP_EL0.set(user_stack_top.as_usize() as u64);
+
+    __process_eret_to_user(__process_user_exit_trampoline.get() as u64)
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text.__process_eret_to_user
+
+//------------------------------------------------------------------------------
+// fn __process_eret_to_user(user_lr: u64) -> !
+//------------------------------------------------------------------------------
+//
+// ELR_EL1, SPSR_EL1 and SP_EL0 must have been prepared by the caller.
+__process_eret_to_user:
+	mov	lr,  x0
+
+	// Do not leak kernel register contents to user space. A zero frame pointer also marks the root
+	// frame for backtraces in user space.
+	mov	x0,  xzr
+	mov	x1,  xzr
+	mov	x2,  xzr
+	mov	x3,  xzr
+	mov	x4,  xzr
+	mov	x5,  xzr
+	mov	x6,  xzr
+	mov	x7,  xzr
+	mov	x8,  xzr
+	mov	x9,  xzr
+	mov	x10, xzr
+	mov	x11, xzr
+	mov	x12, xzr
+	mov	x13, xzr
+	mov	x14, xzr
+	mov	x15, xzr
+	mov	x16, xzr
+	mov	x17, xzr
+	mov	x18, xzr
+	mov	x19, xzr
+	mov	x20, xzr
+	mov	x21, xzr
+	mov	x22, xzr
+	mov	x23, xzr
+	mov	x24, xzr
+	mov	x25, xzr
+	mov	x26, xzr
+	mov	x27, xzr
+	mov	x28, xzr
+	mov	x29, xzr
+
+	eret
+
+.size	__process_eret_to_user, . - __process_eret_to_user
+.type	__process_eret_to_user, function
+.global	__process_eret_to_user
+
+//--------------------------------------------------------------------------------------------------
+// User Code
+//--------------------------------------------------------------------------------------------------
+.section .user_text.__process_user_exit_trampoline, "ax", %progbits
+
+//------------------------------------------------------------------------------
+// fn __process_user_exit_trampoline() -> !
+//------------------------------------------------------------------------------
+//
+// Executed in user space when the entry function of a process returns.
+__process_user_exit_trampoline:
+	mov	x0,  xzr
+	mov	x8,  {CONST_SYS_EXIT}
+	svc	#0
+
+	// The exit system call does not return.
+1:	b	1b
+
+.size	__process_user_exit_trampoline, . - __process_user_exit_trampoline
+.type	__process_user_exit_trampoline, function
+.global	__process_user_exit_trampoline
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural kernel thread support.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::task::arch_task
+
+use crate::memory::{Address, Virtual};
+use core::arch::global_asm;
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("task.s"));
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The saved register context of a thread that is not running.
+///
+/// Only callee-saved registers are part of the context, because a switch always happens through a
+/// regular function call into `__task_context_switch`.
+#[repr(C)]
+pub struct Context {
+    /// Callee-saved general purpose registers x19 - x28.
+    gpr: [u64; 10],
+
+    /// The frame pointer, aka x29.
+    fp: u64,
+
+    /// The link register, aka x30.
+    lr: u64,
+
+    /// The stack pointer.
+    sp: u64,
+
+    /// The user space stack pointer.
+    sp_el0: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Context {
+    /// Create an empty instance.
+    ///
+    /// Used for threads that are already running. The context will be filled on the first switch
+    /// away from them.
+    pub const fn new() -> Self {
+        Self {
+            gpr: [0; 10],
+            fp: 0,
+            lr: 0,
+            sp: 0,
+            sp_el0: 0,
+        }
+    }
+
+    /// Create an instance that starts execution at `entry` on the stack ending at `stack_top`.
+    ///
+    /// The frame pointer is zero, so `entry` becomes the root frame of backtraces.
+    pub fn new_for_entry(stack_top: Address<Virtual>, entry: extern "C" fn() -> !) -> Self {
+        Self {
+            gpr: [0; 10],
+            fp: 0,
+            lr: entry as usize as u64,
+            sp: stack_top.as_usize() as u64,
+            sp_el0: 0,
+        }
+    }
+}
+
+/// Switch from the thread owning `prev` to the thread owning `next`.
+///
+/// Returns once another thread switches back to `prev`.
+///
+/// # Safety
+///
+/// - IRQs must be masked on the executing core.
+/// - Both pointers must point to valid contexts that live until the next switch.
+/// - `next` must contain a context that was either saved by this function or created through
+///   `Context::new_for_entry()`.
+pub unsafe fn switch(prev: *mut Context, next: *const Context) {
+    extern "C" {
+        fn __task_context_switch(prev: *mut Context, next: *const Context);
+    }
+
+    __task_context_switch(prev, next);
+}
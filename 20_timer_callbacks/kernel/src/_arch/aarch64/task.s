@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text.__task_context_switch
+
+//------------------------------------------------------------------------------
+// fn __task_context_switch(prev: *mut Context, next: *const Context)
+//------------------------------------------------------------------------------
+//
+// Only the callee-saved registers as defined by the AAPCS64 need to be preserved, because the
+// switch is entered through a regular function call. Everything else has either been saved by the
+// caller or by the exception entry code. SP_EL0 is the exception: It belongs to the user space part
+// of a thread, if there is one.
+__task_context_switch:
+	// Save the context of the outgoing thread.
+	mov	x9,  sp
+	mrs	x10, SP_EL0
+	stp	x19, x20, [x0, #16 * 0]
+	stp	x21, x22, [x0, #16 * 1]
+	stp	x23, x24, [x0, #16 * 2]
+	stp	x25, x26, [x0, #16 * 3]
+	stp	x27, x28, [x0, #16 * 4]
+	stp	x29, lr,  [x0, #16 * 5]
+	stp	x9,  x10, [x0, #16 * 6]
+
+	// Load the context of the incoming thread.
+	ldp	x19, x20, [x1, #16 * 0]
+	ldp	x21, x22, [x1, #16 * 1]
+	ldp	x23, x24, [x1, #16 * 2]
+	ldp	x25, x26, [x1, #16 * 3]
+	ldp	x27, x28, [x1, #16 * 4]
+	ldp	x29, lr,  [x1, #16 * 5]
+	ldp	x9,  x10, [x1, #16 * 6]
+	mov	sp,  x9
+	msr	SP_EL0, x10
+
+	// Continue wherever the incoming thread left off, or at its entry function if it never ran
+	// before.
+	ret
+
+.size	__task_context_switch, . - __task_context_switch
+.type	__task_context_switch, function
+.global	__task_context_switch
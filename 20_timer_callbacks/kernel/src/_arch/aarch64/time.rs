@@ -169,6 +169,13 @@ pub const fn timeout_irq() -> exception::asynchronous::IRQNumber {
     bsp::exception::asynchronous::irq_map::ARM_NS_PHYSICAL_TIMER
 }
 
+/// Stop the executing core's timer.
+///
+/// The reset value of the control register is unknown, so the timer might be running already.
+pub fn core_init() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
 /// Program a timer IRQ to be fired after `delay` has passed.
 pub fn set_timeout_irq(due_time: Duration) {
     let counter_value_target: GenericTimerCounterValue = match due_time.try_into() {
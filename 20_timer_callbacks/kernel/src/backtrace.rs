@@ -39,6 +39,30 @@ pub struct Backtrace;
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Capture the link addresses of the current backtrace into `links`.
+///
+/// Capturing stops when `links` is full or at the first invalid frame. Returns the number of
+/// captured links.
+pub fn capture(links: &mut [Address<Virtual>]) -> usize {
+    let mut num_captured = 0;
+
+    arch_backtrace::backtrace(|maybe_iter| {
+        if let Some(iter) = maybe_iter {
+            let valid_links = iter.map_while(|item| match item {
+                BacktraceItem::Link(addr) => Some(addr),
+                _ => None,
+            });
+
+            for (slot, addr) in links.iter_mut().zip(valid_links) {
+                *slot = addr;
+                num_captured += 1;
+            }
+        }
+    });
+
+    num_captured
+}
+
 impl fmt::Display for Backtrace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Backtrace:")?;
@@ -84,15 +84,16 @@ use crate::{
     cpu, driver, exception,
     memory::{Address, Virtual},
     synchronization,
-    synchronization::InitStateLock,
+    synchronization::{InitStateLock, RwSpinLock},
 };
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<IRQNumber>>>;
+type HandlerTable = exception::asynchronous::IRQHandlerTable<IRQNumber>;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -109,8 +110,49 @@ pub struct GICv2 {
     /// The CPU Interface.
     gicc: gicc::GICC,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers run while the table is read-locked.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Statistics for each IRQ number. Sized during kernel init.
+    irq_stats: InitStateLock<Vec<exception::asynchronous::IRQStats>>,
+
+    /// IRQs that were acknowledged with a spurious interrupt ID, or that no handler claimed.
+    num_spurious: AtomicU64,
+
+    /// The IRQ number that is routed as FIQ. Set during kernel init.
+    fiq_number: InitStateLock<Option<IRQNumber>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Translate to the priority field of the distributor. Lower values are more urgent.
+///
+/// Only the upper bits are used, because implementations may leave the lower ones out.
+fn priority_field(priority: exception::asynchronous::IRQPriority) -> u8 {
+    use exception::asynchronous::IRQPriority;
+
+    match priority {
+        IRQPriority::High => 0x40,
+        IRQPriority::Normal => 0x80,
+        IRQPriority::Low => 0xC0,
+    }
+}
+
+impl GICv2 {
+    /// Set up the executing core's CPU interface, which is banked.
+    fn cpu_interface_init(&self) {
+        self.gicc.priority_accept_all();
+        self.gicc.preempt_on_all_priority_bits();
+        self.gicc.enable();
+    }
+
+    /// Returns true if `irq_number` is routed as FIQ.
+    fn is_fiq(&self, irq_number: &IRQNumber) -> bool {
+        self.fiq_number
+            .read(|fiq| matches!(fiq, Some(x) if x.get() == irq_number.get()))
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -134,7 +176,10 @@ impl GICv2 {
         Self {
             gicd: gicd::GICD::new(gicd_mmio_start_addr),
             gicc: gicc::GICC::new(gicc_mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: RwSpinLock::new(HandlerTable::new()),
+            irq_stats: InitStateLock::new(Vec::new()),
+            num_spurious: AtomicU64::new(0),
+            fiq_number: InitStateLock::new(None),
         }
     }
 }
@@ -153,14 +198,19 @@ impl driver::interface::DeviceDriver for GICv2 {
 
     unsafe fn init(&self) -> Result<(), &'static str> {
         self.handler_table
-            .write(|table| table.resize(IRQNumber::MAX_INCLUSIVE + 1, None));
+            .write(|table| table.resize(IRQNumber::MAX_INCLUSIVE + 1));
+        self.irq_stats.write(|stats| {
+            stats.resize_with(
+                IRQNumber::MAX_INCLUSIVE + 1,
+                exception::asynchronous::IRQStats::new,
+            )
+        });
 
         if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
             self.gicd.boot_core_init();
         }
 
-        self.gicc.priority_accept_all();
-        self.gicc.enable();
+        self.cpu_interface_init();
 
         Ok(())
     }
@@ -173,14 +223,34 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         &self,
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
+        if self.is_fiq(&irq_handler_descriptor.number()) {
+            return Err("IRQ is routed as FIQ");
+        }
+
         self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+            table.add(
+                irq_handler_descriptor.number().get(),
+                irq_handler_descriptor,
+            )?;
+            self.gicd.set_priority(
+                &irq_handler_descriptor.number(),
+                priority_field(irq_handler_descriptor.priority()),
+            );
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+            Ok(())
+        })
+    }
 
-            table[irq_number] = Some(irq_handler_descriptor);
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        let irq_number = irq_handler_descriptor.number();
+
+        self.handler_table.write(|table| {
+            if table.remove(irq_number.get(), &irq_handler_descriptor)? {
+                self.gicd.disable(&irq_number);
+            }
 
             Ok(())
         })
@@ -190,32 +260,111 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         self.gicd.enable(irq_number);
     }
 
+    fn disable(&self, irq_number: &Self::IRQNumberType) {
+        self.gicd.disable(irq_number);
+    }
+
+    fn secondary_core_init(&self) {
+        self.cpu_interface_init();
+
+        // Priorities and enable bits of private IRQs are banked, so the settings made on the boot
+        // core do not apply here.
+        self.handler_table.read(|table| {
+            for (_, chain) in table.iter().filter(|(i, _)| *i < 32) {
+                if let Some(descriptor) = chain.last() {
+                    self.gicd
+                        .set_priority(&descriptor.number(), priority_field(descriptor.priority()));
+                    self.gicd.enable(&descriptor.number());
+                }
+            }
+        });
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
         // Extract the highest priority pending IRQ number from the Interrupt Acknowledge Register
         // (IAR).
-        let irq_number = self.gicc.pending_irq_number(ic);
+        let (irq_number, cpu_id) = self.gicc.pending_irq_number(ic);
 
         // Guard against spurious interrupts.
         if irq_number > GICv2::MAX_IRQ_NUMBER {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
             return;
         }
 
-        // Call the IRQ handler. Panic if there is none.
-        self.handler_table.read(|table| {
-            match table[irq_number] {
-                None => panic!("No handler registered for IRQ {}", irq_number),
-                Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler().handle().expect("Error handling IRQ");
-                }
-            }
+        // Call the IRQ handlers. Panics on failure.
+        //
+        // Acknowledging raised the running priority of the CPU interface to the priority of this
+        // IRQ, so only IRQs of higher priority can preempt a handler.
+        let ret = self.handler_table.read(|table| {
+            self.irq_stats.read(|stats| {
+                stats[irq_number].measure(|| {
+                    table.dispatch(irq_number, |descriptor| {
+                        if descriptor.allows_nesting() {
+                            exception::asynchronous::exec_nested(ic, || {
+                                descriptor.handler().handle()
+                            })
+                        } else {
+                            descriptor.handler().handle()
+                        }
+                    })
+                })
+            })
         });
 
+        // An IRQ might have been in flight while its last handler was removed.
+        if ret == exception::asynchronous::IRQReturn::NotHandled {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
+        }
+
         // Signal completion of handling.
-        self.gicc.mark_comleted(irq_number as u32, ic);
+        self.gicc.mark_comleted(irq_number as u32, cpu_id, ic);
+    }
+
+    fn route_to_fiq(&self, irq_number: &Self::IRQNumberType) -> Result<(), &'static str> {
+        self.handler_table.read(|table| {
+            if !table.chain(irq_number.get()).is_empty() {
+                return Err("IRQ has regular handlers");
+            }
+
+            // Find out before touching the distributor, so that nothing needs to be rolled back.
+            if !self.gicc.supports_fiq() {
+                return Err("FIQs need Secure access to the GIC");
+            }
+
+            // FIQs are Group 0. Regroup before the CPU interface starts signaling Group 0 as FIQ,
+            // so that no IRQ ends up on the FIQ path.
+            self.gicd.set_priority(irq_number, 0x00);
+            self.gicd.set_only_group0(irq_number);
+            self.gicc.enable_fiq();
+            self.gicd.enable(irq_number);
+            self.fiq_number.write(|fiq| *fiq = Some(*irq_number));
+
+            Ok(())
+        })
+    }
+
+    fn handle_pending_fiq<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+        descriptor: &exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) {
+        let irq_number = self.gicc.pending_fiq_number(ic);
+
+        // Guard against spurious interrupts.
+        if irq_number > GICv2::MAX_IRQ_NUMBER {
+            return;
+        }
+
+        descriptor.handler().handle().expect("Error handling FIQ");
+
+        self.gicc.mark_fiq_completed(irq_number as u32, ic);
+    }
+
+    fn raise_software_irq(&self, irq_number: &Self::IRQNumberType) -> Result<(), &'static str> {
+        self.gicd.raise_local_sgi(irq_number)
     }
 
     fn print_handler(&self) {
@@ -224,11 +373,36 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         info!("      Peripheral handler:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().skip(32).enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i + 32, handler.name());
+            for (i, chain) in table.iter().filter(|(i, _)| *i >= 32) {
+                for handler in chain {
+                    info!("            {: >3}. {}", i, handler.name());
                 }
             }
         });
     }
+
+    fn print_stats(&self) {
+        use exception::asynchronous::IRQStats;
+
+        IRQStats::print_header();
+
+        self.handler_table.read(|table| {
+            self.irq_stats.read(|stats| {
+                for (i, chain) in table.iter() {
+                    stats[i].print(&i, chain);
+                }
+            })
+        });
+
+        IRQStats::print_spurious(self.num_spurious.load(Ordering::Relaxed));
+    }
+
+    fn irq_count(&self, irq_number: &Self::IRQNumberType) -> Option<u64> {
+        self.irq_stats
+            .read(|stats| stats.get(irq_number.get()).map(|stats| stats.count()))
+    }
+
+    fn num_spurious(&self) -> Option<u64> {
+        Some(self.num_spurious.load(Ordering::Relaxed))
+    }
 }
@@ -5,12 +5,13 @@
 //! GICC Driver - GIC CPU interface.
 
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper,
-    exception,
+    bsp::{self, device_driver::common::MMIODerefWrapper},
+    cpu, exception,
     memory::{Address, Virtual},
 };
+use core::sync::atomic::{AtomicBool, Ordering};
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::ReadWrite,
 };
@@ -24,6 +25,8 @@ register_bitfields! {
 
     /// CPU Interface Control Register
     CTLR [
+        FIQEn OFFSET(3) NUMBITS(1) [],
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
         Enable OFFSET(0) NUMBITS(1) []
     ],
 
@@ -32,13 +35,20 @@ register_bitfields! {
         Priority OFFSET(0) NUMBITS(8) []
     ],
 
+    /// Binary Point Register
+    BPR [
+        BinaryPoint OFFSET(0) NUMBITS(3) []
+    ],
+
     /// Interrupt Acknowledge Register
     IAR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         InterruptID OFFSET(0) NUMBITS(10) []
     ],
 
     /// End of Interrupt Register
     EOIR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         EOIINTID OFFSET(0) NUMBITS(10) []
     ]
 }
@@ -48,16 +58,23 @@ register_structs! {
     pub RegisterBlock {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => PMR: ReadWrite<u32, PMR::Register>),
-        (0x008 => _reserved1),
+        (0x008 => BPR: ReadWrite<u32, BPR::Register>),
         (0x00C => IAR: ReadWrite<u32, IAR::Register>),
         (0x010 => EOIR: ReadWrite<u32, EOIR::Register>),
-        (0x014  => @END),
+        (0x014 => _reserved1),
+        (0x01C => ABPR: ReadWrite<u32, BPR::Register>),
+        (0x020 => AIAR: ReadWrite<u32, IAR::Register>),
+        (0x024 => AEOIR: ReadWrite<u32, EOIR::Register>),
+        (0x028  => @END),
     }
 }
 
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_FALSE: AtomicBool = AtomicBool::new(false);
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -65,6 +82,10 @@ type Registers = MMIODerefWrapper<RegisterBlock>;
 /// Representation of the GIC CPU interface.
 pub struct GICC {
     registers: Registers,
+
+    /// Per core, set once Group 0 is signaled as FIQ. IRQs are Group 1 then, and must be
+    /// acknowledged through the aliased registers.
+    fiq_enabled: [AtomicBool; bsp::cpu::NUM_CORES],
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -80,6 +101,7 @@ impl GICC {
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            fiq_enabled: [ATOMIC_FALSE; bsp::cpu::NUM_CORES],
         }
     }
 
@@ -98,6 +120,19 @@ impl GICC {
         self.registers.PMR.write(PMR::Priority.val(255)); // Comment in arch spec.
     }
 
+    /// Use as many priority bits for preemption as possible.
+    ///
+    /// Values below the implementation's minimum binary point are raised to it by the hardware.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn preempt_on_all_priority_bits(&self) {
+        self.registers.BPR.write(BPR::BinaryPoint.val(0));
+        self.registers.ABPR.write(BPR::BinaryPoint.val(0));
+    }
+
     /// Enable the interface - start accepting IRQs.
     ///
     /// # Safety
@@ -108,7 +143,53 @@ impl GICC {
         self.registers.CTLR.write(CTLR::Enable::SET);
     }
 
-    /// Extract the number of the highest-priority pending IRQ.
+    /// Returns whether the executing core's interface can signal Group 0 interrupts as FIQ.
+    ///
+    /// This needs Secure access to the CPU interface. Non-secure writes to the Group 0 controls
+    /// are ignored, which is detected by reading `FIQEn` back. FIQs are masked while probing,
+    /// because Group 0 holds all interrupts until they are regrouped.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn supports_fiq(&self) -> bool {
+        let saved = exception::asynchronous::local_irq_mask_save();
+        exception::asynchronous::local_fiq_mask();
+
+        self.registers.CTLR.modify(CTLR::FIQEn::SET);
+        let supported = self.registers.CTLR.is_set(CTLR::FIQEn);
+        self.registers.CTLR.modify(CTLR::FIQEn::CLEAR);
+
+        exception::asynchronous::local_irq_restore(saved);
+
+        supported
+    }
+
+    /// Signal Group 0 interrupts as FIQ on the executing core, and start accepting Group 1
+    /// interrupts as IRQ.
+    ///
+    /// Check [`GICC::supports_fiq()`] first.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn enable_fiq(&self) {
+        self.registers
+            .CTLR
+            .modify(CTLR::EnableGrp1::SET + CTLR::FIQEn::SET);
+
+        self.fiq_enabled[cpu::smp::core_id::<usize>()].store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether IRQs on the executing core are Group 1.
+    fn is_fiq_enabled(&self) -> bool {
+        self.fiq_enabled[cpu::smp::core_id::<usize>()].load(Ordering::Relaxed)
+    }
+
+    /// Extract the number of the highest-priority pending IRQ, and the number of the requesting
+    /// core if it is an SGI.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
@@ -120,15 +201,21 @@ impl GICC {
     pub fn pending_irq_number<'irq_context>(
         &self,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
-    ) -> usize {
-        self.registers.IAR.read(IAR::InterruptID) as usize
+    ) -> (usize, u32) {
+        let iar = if self.is_fiq_enabled() {
+            self.registers.AIAR.extract()
+        } else {
+            self.registers.IAR.extract()
+        };
+
+        (iar.read(IAR::InterruptID) as usize, iar.read(IAR::CPUID))
     }
 
     /// Complete handling of the currently active IRQ.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
-    /// To be called after `pending_irq_number()`.
+    /// To be called after `pending_irq_number()`, with the values it returned.
     ///
     /// # Safety
     ///
@@ -136,6 +223,46 @@ impl GICC {
     ///   of `&mut self`.
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub fn mark_comleted<'irq_context>(
+        &self,
+        irq_number: u32,
+        cpu_id: u32,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        let eoir = if self.is_fiq_enabled() {
+            &self.registers.AEOIR
+        } else {
+            &self.registers.EOIR
+        };
+
+        eoir.write(EOIR::EOIINTID.val(irq_number) + EOIR::CPUID.val(cpu_id));
+    }
+
+    /// Extract the number of the pending Group 0 interrupt.
+    ///
+    /// Can only be called from FIQ context, which is ensured by taking an `IRQContext` token.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn pending_fiq_number<'irq_context>(
+        &self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) -> usize {
+        self.registers.IAR.read(IAR::InterruptID) as usize
+    }
+
+    /// Complete handling of the currently active Group 0 interrupt.
+    ///
+    /// Can only be called from FIQ context, which is ensured by taking an `IRQContext` token.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn mark_fiq_completed<'irq_context>(
         &self,
         irq_number: u32,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
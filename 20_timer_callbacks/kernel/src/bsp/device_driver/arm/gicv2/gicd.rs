@@ -11,12 +11,12 @@ use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
     memory::{Address, Virtual},
     state, synchronization,
-    synchronization::IRQSafeNullLock,
+    synchronization::IRQSafeSpinLock,
 };
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
-    registers::{ReadOnly, ReadWrite},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -28,6 +28,7 @@ register_bitfields! {
 
     /// Distributor Control Register
     CTLR [
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
         Enable OFFSET(0) NUMBITS(1) []
     ],
 
@@ -42,6 +43,16 @@ register_bitfields! {
         Offset2 OFFSET(16) NUMBITS(8) [],
         Offset1 OFFSET(8)  NUMBITS(8) [],
         Offset0 OFFSET(0)  NUMBITS(8) []
+    ],
+
+    /// Software Generated Interrupt Register
+    SGIR [
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00,
+            AllOthers = 0b01,
+            RequestingCoreOnly = 0b10
+        ],
+        SGIINTID OFFSET(0) NUMBITS(4) []
     ]
 }
 
@@ -51,10 +62,18 @@ register_structs! {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
         (0x008 => _reserved1),
+        (0x084 => IGROUPR: [ReadWrite<u32>; 31]),
+        (0x100 => _reserved2),
         (0x104 => ISENABLER: [ReadWrite<u32>; 31]),
-        (0x180 => _reserved2),
+        (0x180 => _reserved3),
+        (0x184 => ICENABLER: [WriteOnly<u32>; 31]),
+        (0x200 => _reserved4),
+        (0x420 => IPRIORITYR: [ReadWrite<u32>; 247]),
+        (0x7FC => _reserved5),
         (0x820 => ITARGETSR: [ReadWrite<u32, ITARGETSR::Register>; 248]),
-        (0xC00 => @END),
+        (0xC00 => _reserved6),
+        (0xF00 => SGIR: WriteOnly<u32, SGIR::Register>),
+        (0xF04 => @END),
     }
 }
 
@@ -62,8 +81,14 @@ register_structs! {
     #[allow(non_snake_case)]
     BankedRegisterBlock {
         (0x000 => _reserved1),
+        (0x080 => IGROUPR: ReadWrite<u32>),
+        (0x084 => _reserved2),
         (0x100 => ISENABLER: ReadWrite<u32>),
-        (0x104 => _reserved2),
+        (0x104 => _reserved3),
+        (0x180 => ICENABLER: WriteOnly<u32>),
+        (0x184 => _reserved4),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x420 => _reserved5),
         (0x800 => ITARGETSR: [ReadOnly<u32, ITARGETSR::Register>; 8]),
         (0x820 => @END),
     }
@@ -82,7 +107,7 @@ type BankedRegisters = MMIODerefWrapper<BankedRegisterBlock>;
 /// Representation of the GIC Distributor.
 pub struct GICD {
     /// Access to shared registers is guarded with a lock.
-    shared_registers: IRQSafeNullLock<SharedRegisters>,
+    shared_registers: IRQSafeSpinLock<SharedRegisters>,
 
     /// Access to banked registers is unguarded.
     banked_registers: BankedRegisters,
@@ -132,7 +157,7 @@ impl GICD {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            shared_registers: IRQSafeNullLock::new(SharedRegisters::new(mmio_start_addr)),
+            shared_registers: IRQSafeSpinLock::new(SharedRegisters::new(mmio_start_addr)),
             banked_registers: BankedRegisters::new(mmio_start_addr),
         }
     }
@@ -198,4 +223,103 @@ impl GICD {
             }
         }
     }
+
+    /// Disable an interrupt.
+    pub fn disable(&self, irq_num: &super::IRQNumber) {
+        let irq_num = irq_num.get();
+
+        // Same layout as ISENABLER. Writing a 1 to a bit disables the IRQ, zeros have no effect.
+        let disable_reg_index = irq_num >> 5;
+        let disable_bit: u32 = 1u32 << (irq_num % 32);
+
+        // Check if we are handling a private or shared IRQ.
+        match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ICENABLER.set(disable_bit),
+            // Shared.
+            _ => {
+                let disable_reg_index_shared = disable_reg_index - 1;
+
+                self.shared_registers
+                    .lock(|regs| regs.ICENABLER[disable_reg_index_shared].set(disable_bit));
+            }
+        }
+    }
+
+    /// Set the priority of an interrupt. Lower values are more urgent.
+    ///
+    /// The priorities of private IRQs are banked, so only the executing core's copy is changed.
+    pub fn set_priority(&self, irq_num: &super::IRQNumber, priority: u8) {
+        let irq_num = irq_num.get();
+
+        // Each u32 priority register holds the byte-sized fields of four IRQ numbers.
+        let priority_reg_index = irq_num >> 2;
+        let shift = (irq_num % 4) * 8;
+        let update = |reg: &ReadWrite<u32>| {
+            let others = reg.get() & !(0xff << shift);
+            reg.set(others | (u32::from(priority) << shift));
+        };
+
+        // Check if we are handling a private or shared IRQ.
+        match irq_num {
+            // Private.
+            0..=31 => update(&self.banked_registers.IPRIORITYR[priority_reg_index]),
+            // Shared.
+            _ => {
+                let priority_reg_index_shared = priority_reg_index - 8;
+
+                self.shared_registers
+                    .lock(|regs| update(&regs.IPRIORITYR[priority_reg_index_shared]));
+            }
+        }
+    }
+
+    /// Put an interrupt into Group 0, and all others into Group 1. Enables both groups.
+    ///
+    /// Group membership of private IRQs is banked, so only the executing core's copy is changed.
+    /// Writes are ignored without Secure access to the distributor.
+    pub fn set_only_group0(&self, irq_num: &super::IRQNumber) {
+        let irq_num = irq_num.get();
+
+        // Each bit in the u32 group register corresponds to one IRQ number. A set bit selects
+        // Group 1.
+        let group_reg_index = irq_num >> 5;
+        let group0_bit: u32 = 1u32 << (irq_num % 32);
+        let mask = |index: usize| {
+            if index == group_reg_index {
+                !group0_bit
+            } else {
+                u32::MAX
+            }
+        };
+
+        self.banked_registers.IGROUPR.set(mask(0));
+
+        self.shared_registers.lock(|regs| {
+            let num_shared_regs = (regs.num_irqs() >> 5) - 1;
+
+            for (i, reg) in regs.IGROUPR[0..num_shared_regs].iter().enumerate() {
+                reg.set(mask(i + 1));
+            }
+
+            regs.CTLR.write(CTLR::Enable::SET + CTLR::EnableGrp1::SET);
+        });
+    }
+
+    /// Raise an SGI on the executing core.
+    pub fn raise_local_sgi(&self, irq_num: &super::IRQNumber) -> Result<(), &'static str> {
+        let irq_num = irq_num.get();
+
+        if irq_num > 15 {
+            return Err("Only SGIs can be raised from software");
+        }
+
+        self.shared_registers.lock(|regs| {
+            regs.SGIR.write(
+                SGIR::TargetListFilter::RequestingCoreOnly + SGIR::SGIINTID.val(irq_num as u32),
+            )
+        });
+
+        Ok(())
+    }
 }
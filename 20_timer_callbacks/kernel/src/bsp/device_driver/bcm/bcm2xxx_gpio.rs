@@ -10,7 +10,7 @@ use crate::{
     exception::asynchronous::IRQNumber,
     memory::{Address, Virtual},
     synchronization,
-    synchronization::IRQSafeNullLock,
+    synchronization::IRQSafeSpinLock,
 };
 use tock_registers::{
     interfaces::{ReadWriteable, Writeable},
@@ -122,7 +122,7 @@ struct GPIOInner {
 
 /// Representation of the GPIO HW.
 pub struct GPIO {
-    inner: IRQSafeNullLock<GPIOInner>,
+    inner: IRQSafeSpinLock<GPIOInner>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -204,7 +204,7 @@ impl GPIO {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(GPIOInner::new(mmio_start_addr)),
+            inner: IRQSafeSpinLock::new(GPIOInner::new(mmio_start_addr)),
         }
     }
 
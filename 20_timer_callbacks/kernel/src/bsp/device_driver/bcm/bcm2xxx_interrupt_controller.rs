@@ -9,11 +9,13 @@ mod peripheral_ic;
 
 use crate::{
     bsp::device_driver::common::BoundedUsize,
-    driver,
-    exception::{self, asynchronous::IRQHandlerDescriptor},
+    driver, exception,
     memory::{Address, Virtual},
 };
-use core::fmt;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -43,6 +45,9 @@ pub enum IRQNumber {
 pub struct InterruptController {
     local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
+
+    /// IRQs that were signaled while no source was pending.
+    num_spurious: AtomicU64,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -53,6 +58,10 @@ impl PendingIRQs {
     pub fn new(bitmask: u64) -> Self {
         Self { bitmask }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.bitmask == 0
+    }
 }
 
 impl Iterator for PendingIRQs {
@@ -101,6 +110,7 @@ impl InterruptController {
         Self {
             local: local_ic::LocalIC::new(local_mmio_start_addr),
             periph: peripheral_ic::PeripheralIC::new(periph_mmio_start_addr),
+            num_spurious: AtomicU64::new(0),
         }
     }
 }
@@ -132,24 +142,26 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
         match irq_handler_descriptor.number() {
-            IRQNumber::Local(lirq) => {
-                let local_descriptor = IRQHandlerDescriptor::new(
-                    lirq,
-                    irq_handler_descriptor.name(),
-                    irq_handler_descriptor.handler(),
-                );
-
-                self.local.register_handler(local_descriptor)
-            }
-            IRQNumber::Peripheral(pirq) => {
-                let periph_descriptor = IRQHandlerDescriptor::new(
-                    pirq,
-                    irq_handler_descriptor.name(),
-                    irq_handler_descriptor.handler(),
-                );
-
-                self.periph.register_handler(periph_descriptor)
-            }
+            IRQNumber::Local(lirq) => self
+                .local
+                .register_handler(irq_handler_descriptor.with_number(lirq)),
+            IRQNumber::Peripheral(pirq) => self
+                .periph
+                .register_handler(irq_handler_descriptor.with_number(pirq)),
+        }
+    }
+
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        match irq_handler_descriptor.number() {
+            IRQNumber::Local(lirq) => self
+                .local
+                .unregister_handler(irq_handler_descriptor.with_number(lirq)),
+            IRQNumber::Peripheral(pirq) => self
+                .periph
+                .unregister_handler(irq_handler_descriptor.with_number(pirq)),
         }
     }
 
@@ -160,16 +172,83 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         }
     }
 
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+        }
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
+        // Only the core that peripheral IRQs are routed to may handle them.
+        let periph_pending =
+            self.local.is_periph_irq_pending() && !self.periph.pending_irqs().is_empty();
+
+        if self.local.pending_irqs().is_empty() && !periph_pending {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
         self.local.handle_pending_irqs(ic);
-        self.periph.handle_pending_irqs(ic)
+
+        if periph_pending {
+            self.periph.handle_pending_irqs(ic)
+        }
+    }
+
+    fn secondary_core_init(&self) {
+        self.local.secondary_core_init();
+    }
+
+    fn route_to_fiq(&self, irq: &Self::IRQNumberType) -> Result<(), &'static str> {
+        match irq {
+            IRQNumber::Local(_) => Err("Only peripheral IRQs can be routed as FIQ"),
+            IRQNumber::Peripheral(pirq) => self.periph.route_to_fiq(pirq),
+        }
+    }
+
+    fn handle_pending_fiq<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+        descriptor: &exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) {
+        // There is a single FIQ source, and nothing to acknowledge at the controller.
+        descriptor.handler().handle().expect("Error handling FIQ");
     }
 
     fn print_handler(&self) {
         self.local.print_handler();
         self.periph.print_handler();
     }
+
+    fn print_stats(&self) {
+        use exception::asynchronous::IRQStats;
+
+        IRQStats::print_header();
+        self.local.print_stats();
+        self.periph.print_stats();
+        IRQStats::print_spurious(
+            self.num_spurious.load(Ordering::Relaxed)
+                + self.local.num_spurious()
+                + self.periph.num_spurious(),
+        );
+    }
+
+    fn irq_count(&self, irq: &Self::IRQNumberType) -> Option<u64> {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.irq_count(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.irq_count(pirq),
+        }
+    }
+
+    fn num_spurious(&self) -> Option<u64> {
+        Some(
+            self.num_spurious.load(Ordering::Relaxed)
+                + self.local.num_spurious()
+                + self.periph.num_spurious(),
+        )
+    }
 }
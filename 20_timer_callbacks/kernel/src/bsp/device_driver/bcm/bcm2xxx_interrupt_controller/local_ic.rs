@@ -11,16 +11,17 @@
 use super::{LocalIRQ, PendingIRQs};
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
-    exception,
+    cpu, exception,
     memory::{Address, Virtual},
     synchronization,
-    synchronization::{IRQSafeNullLock, InitStateLock},
+    synchronization::{IRQSafeSpinLock, InitStateLock, RwSpinLock},
 };
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_structs,
-    registers::{ReadOnly, WriteOnly},
+    registers::{ReadOnly, ReadWrite},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -29,10 +30,10 @@ use tock_registers::{
 
 register_structs! {
     #[allow(non_snake_case)]
-    WORegisterBlock {
+    RWRegisterBlock {
         (0x00 => _reserved1),
-        (0x40 => CORE0_TIMER_INTERRUPT_CONTROL: WriteOnly<u32>),
-        (0x44 => @END),
+        (0x40 => CORE_TIMER_INTERRUPT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x50 => @END),
     }
 }
 
@@ -40,33 +41,43 @@ register_structs! {
     #[allow(non_snake_case)]
     RORegisterBlock {
         (0x00 => _reserved1),
-        (0x60 => CORE0_INTERRUPT_SOURCE: ReadOnly<u32>),
-        (0x64 => @END),
+        (0x60 => CORE_INTERRUPT_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => @END),
     }
 }
 
-/// Abstraction for the WriteOnly parts of the associated MMIO registers.
-type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
+/// Abstraction for the ReadWrite parts of the associated MMIO registers.
+type ReadWriteRegisters = MMIODerefWrapper<RWRegisterBlock>;
 
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<LocalIRQ>>>;
+type HandlerTable = exception::asynchronous::IRQHandlerTable<LocalIRQ>;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Representation of the peripheral interrupt controller.
+/// Representation of the local interrupt controller.
+///
+/// Each core has its own copy of the registers. Enabling, disabling and querying local IRQs always
+/// refers to the executing core.
 pub struct LocalIC {
-    /// Access to write registers is guarded with a lock.
-    wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
+    /// Access to read-write registers is guarded with a lock, because enabling and disabling are
+    /// read-modify-write operations that an IRQ handler on the same core might interrupt.
+    rw_registers: IRQSafeSpinLock<ReadWriteRegisters>,
 
     /// Register read access is unguarded.
     ro_registers: ReadOnlyRegisters,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers run while the table is read-locked.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Statistics for each IRQ number. Sized during kernel init.
+    irq_stats: InitStateLock<Vec<exception::asynchronous::IRQStats>>,
+
+    /// Pending IRQs that no handler claimed.
+    num_spurious: AtomicU64,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -84,24 +95,88 @@ impl LocalIC {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
+            rw_registers: IRQSafeSpinLock::new(ReadWriteRegisters::new(mmio_start_addr)),
             ro_registers: ReadOnlyRegisters::new(mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: RwSpinLock::new(HandlerTable::new()),
+            irq_stats: InitStateLock::new(Vec::new()),
+            num_spurious: AtomicU64::new(0),
         }
     }
 
     /// Called by the kernel to bring up the device.
     pub fn init(&self) {
         self.handler_table
-            .write(|table| table.resize(LocalIRQ::MAX_INCLUSIVE + 1, None));
+            .write(|table| table.resize(LocalIRQ::MAX_INCLUSIVE + 1));
+        self.irq_stats.write(|stats| {
+            stats.resize_with(
+                LocalIRQ::MAX_INCLUSIVE + 1,
+                exception::asynchronous::IRQStats::new,
+            )
+        });
+    }
+
+    /// The number of pending IRQs that no handler claimed.
+    pub fn num_spurious(&self) -> u64 {
+        self.num_spurious.load(Ordering::Relaxed)
+    }
+
+    /// The number of times an IRQ was handled.
+    pub fn irq_count(&self, irq: &LocalIRQ) -> Option<u64> {
+        self.irq_stats
+            .read(|stats| stats.get(irq.get()).map(|stats| stats.count()))
+    }
+
+    /// Call the handler of `descriptor`.
+    ///
+    /// The controller has no priorities. A handler that allows nesting runs with its IRQ disabled
+    /// and IRQs unmasked, so that all other IRQs can preempt it.
+    fn call_handler(
+        &self,
+        ic: &exception::asynchronous::IRQContext,
+        descriptor: &exception::asynchronous::IRQHandlerDescriptor<LocalIRQ>,
+    ) -> Result<exception::asynchronous::IRQReturn, &'static str> {
+        use exception::asynchronous::interface::IRQManager;
+
+        if !descriptor.allows_nesting() {
+            return descriptor.handler().handle();
+        }
+
+        self.disable(&descriptor.number());
+        let ret = exception::asynchronous::exec_nested(ic, || descriptor.handler().handle());
+        self.enable(&descriptor.number());
+
+        ret
     }
 
     /// Query the list of pending IRQs.
-    fn pending_irqs(&self) -> PendingIRQs {
+    pub(super) fn pending_irqs(&self) -> PendingIRQs {
         // Ignore the indicator bit for a peripheral IRQ.
-        PendingIRQs::new(
-            (self.ro_registers.CORE0_INTERRUPT_SOURCE.get() & !Self::PERIPH_IRQ_MASK).into(),
-        )
+        PendingIRQs::new((self.interrupt_source() & !Self::PERIPH_IRQ_MASK).into())
+    }
+
+    /// Checks if a peripheral IRQ is signaled to the executing core.
+    ///
+    /// Peripheral IRQs are routed to a single core, the boot core by default.
+    pub(super) fn is_periph_irq_pending(&self) -> bool {
+        (self.interrupt_source() & Self::PERIPH_IRQ_MASK) != 0
+    }
+
+    /// Enable the local IRQs that have handlers for the executing core.
+    pub(super) fn secondary_core_init(&self) {
+        use exception::asynchronous::interface::IRQManager;
+
+        self.handler_table.read(|table| {
+            for (_, chain) in table.iter() {
+                if let Some(descriptor) = chain.first() {
+                    self.enable(&descriptor.number());
+                }
+            }
+        });
+    }
+
+    /// The executing core's interrupt source register.
+    fn interrupt_source(&self) -> u32 {
+        self.ro_registers.CORE_INTERRUPT_SOURCE[cpu::smp::core_id::<usize>()].get()
     }
 }
 
@@ -118,42 +193,64 @@ impl exception::asynchronous::interface::IRQManager for LocalIC {
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
         self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+            table.add(
+                irq_handler_descriptor.number().get(),
+                irq_handler_descriptor,
+            )
+        })
+    }
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        let irq = irq_handler_descriptor.number();
 
-            table[irq_number] = Some(irq_handler_descriptor);
+        self.handler_table.write(|table| {
+            if table.remove(irq.get(), &irq_handler_descriptor)? {
+                self.disable(&irq);
+            }
 
             Ok(())
         })
     }
 
     fn enable(&self, irq: &Self::IRQNumberType) {
-        self.wo_registers.lock(|regs| {
+        self.rw_registers.lock(|regs| {
             let enable_bit: u32 = 1 << (irq.get());
 
-            // Writing a 1 to a bit will set the corresponding IRQ enable bit. All other IRQ enable
-            // bits are unaffected. So we don't need read and OR'ing here.
-            regs.CORE0_TIMER_INTERRUPT_CONTROL.set(enable_bit);
+            // The register holds one enable bit per timer IRQ, so the other bits must be kept.
+            let control = &regs.CORE_TIMER_INTERRUPT_CONTROL[cpu::smp::core_id::<usize>()];
+            control.set(control.get() | enable_bit);
+        });
+    }
+
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        self.rw_registers.lock(|regs| {
+            let enable_bit: u32 = 1 << (irq.get());
+
+            let control = &regs.CORE_TIMER_INTERRUPT_CONTROL[cpu::smp::core_id::<usize>()];
+            control.set(control.get() & !enable_bit);
         });
     }
 
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
-        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
         self.handler_table.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler().handle().expect("Error handling IRQ");
+            self.irq_stats.read(|stats| {
+                for irq_number in self.pending_irqs() {
+                    // Call the IRQ handlers. Panics on failure.
+                    let ret = stats[irq_number].measure(|| {
+                        table.dispatch(irq_number, |descriptor| self.call_handler(ic, descriptor))
+                    });
+
+                    if ret == exception::asynchronous::IRQReturn::NotHandled {
+                        self.num_spurious.fetch_add(1, Ordering::Relaxed);
                     }
                 }
-            }
+            })
         })
     }
 
@@ -163,11 +260,21 @@ impl exception::asynchronous::interface::IRQManager for LocalIC {
         info!("      Local handler:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
+            for (i, chain) in table.iter() {
+                for handler in chain {
                     info!("            {: >3}. {}", i, handler.name());
                 }
             }
         });
     }
+
+    fn print_stats(&self) {
+        self.handler_table.read(|table| {
+            self.irq_stats.read(|stats| {
+                for (i, chain) in table.iter() {
+                    stats[i].print(&format_args!("Local({})", i), chain);
+                }
+            })
+        });
+    }
 }
@@ -14,12 +14,13 @@ use crate::{
     exception,
     memory::{Address, Virtual},
     synchronization,
-    synchronization::{IRQSafeNullLock, InitStateLock},
+    synchronization::{IRQSafeSpinLock, InitStateLock, RwSpinLock},
 };
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 use tock_registers::{
     interfaces::{Readable, Writeable},
-    register_structs,
+    register_bitfields, register_structs,
     registers::{ReadOnly, WriteOnly},
 };
 
@@ -27,13 +28,29 @@ use tock_registers::{
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+register_bitfields! {
+    u32,
+
+    /// FIQ Control Register
+    FIQ_CONTROL [
+        Enable OFFSET(7) NUMBITS(1) [],
+
+        /// Sources 0..=63 are the peripheral IRQs.
+        Source OFFSET(0) NUMBITS(7) []
+    ]
+}
+
 register_structs! {
     #[allow(non_snake_case)]
     WORegisterBlock {
         (0x00 => _reserved1),
+        (0x0c => FIQ_CONTROL: WriteOnly<u32, FIQ_CONTROL::Register>),
         (0x10 => ENABLE_1: WriteOnly<u32>),
         (0x14 => ENABLE_2: WriteOnly<u32>),
-        (0x18 => @END),
+        (0x18 => _reserved2),
+        (0x1c => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => @END),
     }
 }
 
@@ -53,7 +70,7 @@ type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<PeripheralIRQ>>>;
+type HandlerTable = exception::asynchronous::IRQHandlerTable<PeripheralIRQ>;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -62,13 +79,22 @@ type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<Per
 /// Representation of the peripheral interrupt controller.
 pub struct PeripheralIC {
     /// Access to write registers is guarded with a lock.
-    wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
+    wo_registers: IRQSafeSpinLock<WriteOnlyRegisters>,
 
     /// Register read access is unguarded.
     ro_registers: ReadOnlyRegisters,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers run while the table is read-locked.
+    handler_table: RwSpinLock<HandlerTable>,
+
+    /// Statistics for each IRQ number. Sized during kernel init.
+    irq_stats: InitStateLock<Vec<exception::asynchronous::IRQStats>>,
+
+    /// Pending IRQs that no handler claimed.
+    num_spurious: AtomicU64,
+
+    /// The IRQ number that is routed as FIQ. Set during kernel init.
+    fiq_number: InitStateLock<Option<PeripheralIRQ>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -83,20 +109,68 @@ impl PeripheralIC {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
+            wo_registers: IRQSafeSpinLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
             ro_registers: ReadOnlyRegisters::new(mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: RwSpinLock::new(HandlerTable::new()),
+            irq_stats: InitStateLock::new(Vec::new()),
+            num_spurious: AtomicU64::new(0),
+            fiq_number: InitStateLock::new(None),
         }
     }
 
     /// Called by the kernel to bring up the device.
     pub fn init(&self) {
         self.handler_table
-            .write(|table| table.resize(PeripheralIRQ::MAX_INCLUSIVE + 1, None));
+            .write(|table| table.resize(PeripheralIRQ::MAX_INCLUSIVE + 1));
+        self.irq_stats.write(|stats| {
+            stats.resize_with(
+                PeripheralIRQ::MAX_INCLUSIVE + 1,
+                exception::asynchronous::IRQStats::new,
+            )
+        });
+    }
+
+    /// The number of pending IRQs that no handler claimed.
+    pub fn num_spurious(&self) -> u64 {
+        self.num_spurious.load(Ordering::Relaxed)
+    }
+
+    /// The number of times an IRQ was handled.
+    pub fn irq_count(&self, irq: &PeripheralIRQ) -> Option<u64> {
+        self.irq_stats
+            .read(|stats| stats.get(irq.get()).map(|stats| stats.count()))
+    }
+
+    /// Returns true if `irq` is routed as FIQ.
+    fn is_fiq(&self, irq: &PeripheralIRQ) -> bool {
+        self.fiq_number
+            .read(|fiq| matches!(fiq, Some(x) if x.get() == irq.get()))
+    }
+
+    /// Call the handler of `descriptor`.
+    ///
+    /// The controller has no priorities. A handler that allows nesting runs with its IRQ disabled
+    /// and IRQs unmasked, so that all other IRQs can preempt it.
+    fn call_handler(
+        &self,
+        ic: &exception::asynchronous::IRQContext,
+        descriptor: &exception::asynchronous::IRQHandlerDescriptor<PeripheralIRQ>,
+    ) -> Result<exception::asynchronous::IRQReturn, &'static str> {
+        use exception::asynchronous::interface::IRQManager;
+
+        if !descriptor.allows_nesting() {
+            return descriptor.handler().handle();
+        }
+
+        self.disable(&descriptor.number());
+        let ret = exception::asynchronous::exec_nested(ic, || descriptor.handler().handle());
+        self.enable(&descriptor.number());
+
+        ret
     }
 
     /// Query the list of pending IRQs.
-    fn pending_irqs(&self) -> PendingIRQs {
+    pub(super) fn pending_irqs(&self) -> PendingIRQs {
         let pending_mask: u64 = (u64::from(self.ro_registers.PENDING_2.get()) << 32)
             | u64::from(self.ro_registers.PENDING_1.get());
 
@@ -116,20 +190,39 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         &self,
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
+        if self.is_fiq(&irq_handler_descriptor.number()) {
+            return Err("IRQ is routed as FIQ");
+        }
+
         self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+            table.add(
+                irq_handler_descriptor.number().get(),
+                irq_handler_descriptor,
+            )
+        })
+    }
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+    fn unregister_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        let irq = irq_handler_descriptor.number();
 
-            table[irq_number] = Some(irq_handler_descriptor);
+        self.handler_table.write(|table| {
+            if table.remove(irq.get(), &irq_handler_descriptor)? {
+                self.disable(&irq);
+            }
 
             Ok(())
         })
     }
 
     fn enable(&self, irq: &Self::IRQNumberType) {
+        // The FIQ must not be signaled as IRQ as well.
+        if self.is_fiq(irq) {
+            return;
+        }
+
         self.wo_registers.lock(|regs| {
             let enable_reg = if irq.get() <= 31 {
                 &regs.ENABLE_1
@@ -145,20 +238,59 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         });
     }
 
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        self.wo_registers.lock(|regs| {
+            let disable_reg = if irq.get() <= 31 {
+                &regs.DISABLE_1
+            } else {
+                &regs.DISABLE_2
+            };
+
+            let disable_bit: u32 = 1 << (irq.get() % 32);
+
+            // Writing a 1 to a bit will clear the corresponding IRQ enable bit. All other IRQ
+            // enable bits are unaffected.
+            disable_reg.set(disable_bit);
+        });
+    }
+
+    fn route_to_fiq(&self, irq: &Self::IRQNumberType) -> Result<(), &'static str> {
+        self.handler_table.read(|table| {
+            if !table.chain(irq.get()).is_empty() {
+                return Err("IRQ has regular handlers");
+            }
+
+            // The IRQ must not be signaled as IRQ as well.
+            self.disable(irq);
+
+            // There is a single FIQ source. Selecting it replaces any earlier one.
+            self.wo_registers.lock(|regs| {
+                regs.FIQ_CONTROL
+                    .write(FIQ_CONTROL::Enable::SET + FIQ_CONTROL::Source.val(irq.get() as u32))
+            });
+            self.fiq_number.write(|fiq| *fiq = Some(*irq));
+
+            Ok(())
+        })
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
-        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
         self.handler_table.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler().handle().expect("Error handling IRQ");
+            self.irq_stats.read(|stats| {
+                for irq_number in self.pending_irqs() {
+                    // Call the IRQ handlers. Panics on failure.
+                    let ret = stats[irq_number].measure(|| {
+                        table.dispatch(irq_number, |descriptor| self.call_handler(ic, descriptor))
+                    });
+
+                    if ret == exception::asynchronous::IRQReturn::NotHandled {
+                        self.num_spurious.fetch_add(1, Ordering::Relaxed);
                     }
                 }
-            }
+            })
         })
     }
 
@@ -168,11 +300,21 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         info!("      Peripheral handler:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
+            for (i, chain) in table.iter() {
+                for handler in chain {
                     info!("            {: >3}. {}", i, handler.name());
                 }
             }
         });
     }
+
+    fn print_stats(&self) {
+        self.handler_table.read(|table| {
+            self.irq_stats.read(|stats| {
+                for (i, chain) in table.iter() {
+                    stats[i].print(&format_args!("Peripheral({})", i), chain);
+                }
+            })
+        });
+    }
 }
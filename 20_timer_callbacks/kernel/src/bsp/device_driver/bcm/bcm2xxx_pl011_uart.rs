@@ -15,7 +15,7 @@ use crate::{
     exception::{self, asynchronous::IRQNumber},
     memory::{Address, Virtual},
     synchronization,
-    synchronization::IRQSafeNullLock,
+    synchronization::IRQSafeSpinLock,
 };
 use core::fmt;
 use tock_registers::{
@@ -232,7 +232,7 @@ struct PL011UartInner {
 
 /// Representation of the UART.
 pub struct PL011Uart {
-    inner: IRQSafeNullLock<PL011UartInner>,
+    inner: IRQSafeSpinLock<PL011UartInner>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -398,6 +398,9 @@ impl fmt::Write for PL011UartInner {
 impl PL011Uart {
     pub const COMPATIBLE: &'static str = "BCM PL011 UART";
 
+    /// Depth of the RX FIFO. Received characters are drained in chunks of this size.
+    const RX_FIFO_DEPTH: usize = 16;
+
     /// Create an instance.
     ///
     /// # Safety
@@ -405,7 +408,7 @@ impl PL011Uart {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(PL011UartInner::new(mmio_start_addr)),
+            inner: IRQSafeSpinLock::new(PL011UartInner::new(mmio_start_addr)),
         }
     }
 }
@@ -432,9 +435,12 @@ impl driver::interface::DeviceDriver for PL011Uart {
         &'static self,
         irq_number: &Self::IRQNumberType,
     ) -> Result<(), &'static str> {
-        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
 
-        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+        // Draining the RX FIFO can take a while. Let more urgent IRQs, like the timer, cut in.
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self)
+            .with_priority(IRQPriority::Low)
+            .with_nesting();
 
         irq_manager().register_handler(descriptor)?;
         irq_manager().enable(irq_number);
@@ -495,22 +501,51 @@ impl console::interface::Statistics for PL011Uart {
 impl console::interface::All for PL011Uart {}
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
-    fn handle(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| {
+    fn handle(&self) -> Result<exception::asynchronous::IRQReturn, &'static str> {
+        use exception::asynchronous::IRQReturn;
+
+        let pending = self.inner.lock(|inner| {
             let pending = inner.registers.MIS.extract();
 
             // Clear all pending IRQs.
             inner.registers.ICR.write(ICR::ALL::CLEAR);
 
-            // Check for any kind of RX interrupt.
-            if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                // Echo any received characters.
-                while let Some(c) = inner.read_char_converting(BlockingMode::NonBlocking) {
-                    inner.write_char(c)
+            pending
+        });
+
+        if pending.get() == 0 {
+            return Ok(IRQReturn::NotHandled);
+        }
+
+        // Check for any kind of RX interrupt.
+        if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
+            // The lock masks IRQs. Only hold it while draining the RX FIFO, and echo outside of it,
+            // so that more urgent IRQs can cut in while waiting for TX FIFO space.
+            loop {
+                let mut buf = ['\0'; Self::RX_FIFO_DEPTH];
+                let num_read = self.inner.lock(|inner| {
+                    let mut n = 0;
+                    while n < buf.len() {
+                        match inner.read_char_converting(BlockingMode::NonBlocking) {
+                            None => break,
+                            Some(c) => buf[n] = c,
+                        }
+                        n += 1;
+                    }
+
+                    n
+                });
+
+                if num_read == 0 {
+                    break;
+                }
+
+                for c in &buf[..num_read] {
+                    console::interface::Write::write_char(self, *c);
                 }
             }
-        });
+        }
 
-        Ok(())
+        Ok(IRQReturn::Handled)
     }
 }
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Hardware Random Number Generator Driver.
+//!
+//! The BCM2837 and the BCM2711 come with different RNG blocks. Only what is needed to draw a few
+//! words during early boot is supported, so the driver is not registered with the driver manager.
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    memory::{Address, Virtual},
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How often the FIFO is polled before giving up.
+const MAX_POLLS: usize = 1_000_000;
+
+// RNG registers.
+//
+// Descriptions taken from the Linux `bcm2835-rng` and `iproc-rng200` drivers, since the
+// peripheral datasheets do not document the RNG.
+#[cfg(feature = "bsp_rpi3")]
+register_bitfields! {
+    u32,
+
+    /// Control Register
+    CTRL [
+        /// Random bit generator enable.
+        RBGEN OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Status Register
+    STATUS [
+        /// Number of words that are available in the FIFO.
+        NUM_WORDS OFFSET(24) NUMBITS(8) [],
+
+        /// Number of initial numbers that are discarded after enabling the generator.
+        WARMUP_COUNT OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+#[cfg(feature = "bsp_rpi3")]
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTRL: ReadWrite<u32, CTRL::Register>),
+        (0x04 => STATUS: ReadWrite<u32, STATUS::Register>),
+        (0x08 => DATA: ReadOnly<u32>),
+        (0x0C => @END),
+    }
+}
+
+#[cfg(feature = "bsp_rpi4")]
+register_bitfields! {
+    u32,
+
+    /// Control Register
+    RNG_CTRL [
+        /// Random bit generator enable.
+        RBGEN OFFSET(0) NUMBITS(13) [
+            Disable = 0,
+            Enable = 1
+        ]
+    ],
+
+    /// FIFO Count Register
+    RNG_FIFO_COUNT [
+        /// Number of words that are available in the FIFO.
+        COUNT OFFSET(0) NUMBITS(8) []
+    ]
+}
+
+#[cfg(feature = "bsp_rpi4")]
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => RNG_CTRL: ReadWrite<u32, RNG_CTRL::Register>),
+        (0x04 => _reserved1),
+        (0x20 => RNG_FIFO_DATA: ReadOnly<u32>),
+        (0x24 => RNG_FIFO_COUNT: ReadOnly<u32, RNG_FIFO_COUNT::Register>),
+        (0x28 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the RNG HW.
+pub struct RNG {
+    registers: Registers,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl RNG {
+    #[cfg(feature = "bsp_rpi3")]
+    fn enable(&self) {
+        if self.registers.CTRL.is_set(CTRL::RBGEN) {
+            return;
+        }
+
+        self.registers
+            .STATUS
+            .write(STATUS::WARMUP_COUNT.val(0x40000));
+        self.registers.CTRL.write(CTRL::RBGEN::SET);
+    }
+
+    #[cfg(feature = "bsp_rpi4")]
+    fn enable(&self) {
+        self.registers.RNG_CTRL.write(RNG_CTRL::RBGEN::Enable);
+    }
+
+    #[cfg(feature = "bsp_rpi3")]
+    fn try_read_u32(&self) -> Option<u32> {
+        (0..MAX_POLLS)
+            .any(|_| self.registers.STATUS.read(STATUS::NUM_WORDS) > 0)
+            .then(|| self.registers.DATA.get())
+    }
+
+    #[cfg(feature = "bsp_rpi4")]
+    fn try_read_u32(&self) -> Option<u32> {
+        (0..MAX_POLLS)
+            .any(|_| self.registers.RNG_FIFO_COUNT.read(RNG_FIFO_COUNT::COUNT) > 0)
+            .then(|| self.registers.RNG_FIFO_DATA.get())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RNG {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// Draw 64 random bits.
+    ///
+    /// Enables the generator if needed. Returns `None` if it does not deliver in time.
+    pub fn try_read_u64(&self) -> Option<u64> {
+        self.enable();
+
+        let high = self.try_read_u32()?;
+        let low = self.try_read_u32()?;
+
+        Some((u64::from(high) << 32) | u64::from(low))
+    }
+}
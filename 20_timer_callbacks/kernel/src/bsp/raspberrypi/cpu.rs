@@ -4,6 +4,11 @@
 
 //! BSP Processor code.
 
+use crate::{
+    cpu::smp::EnableMethod,
+    memory::{self, Address, Physical, Virtual},
+};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -12,3 +17,44 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0;
+
+/// The number of processor cores.
+pub const NUM_CORES: usize = 4;
+
+/// How the secondary cores are released if the firmware's device tree does not tell.
+///
+/// The stock firmware parks them in a spin table. Setups booting through the ARM Trusted Firmware
+/// use [`EnableMethod::Psci`] instead, which their device tree names.
+pub const SECONDARY_CORE_ENABLE_METHOD: EnableMethod = EnableMethod::SpinTable;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The physical address of a core's spin table entry if the firmware's device tree does not tell.
+///
+/// The stock firmware's spin table lives at physical `0xd8`, one 64 bit entry per core.
+pub const fn default_spin_table_entry_addr(core_id: usize) -> Address<Physical> {
+    const SPIN_TABLE_START: usize = 0xd8;
+
+    Address::new(SPIN_TABLE_START + core_id * 8)
+}
+
+/// The virtual address of the spin table entry at `phys_addr`.
+///
+/// Only entries in the region that is mapped as the boot core's stack, which starts at physical
+/// zero, are accessible. Returns `None` for entries outside of it or that are not aligned.
+pub fn spin_table_entry_virt_addr(phys_addr: Address<Physical>) -> Option<Address<Virtual>> {
+    const ENTRY_SIZE: usize = 8;
+
+    let virt_region = super::memory::mmu::virt_boot_core_stack_region();
+    let phys_start =
+        memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_region.start_addr()).ok()?;
+
+    let offset = phys_addr.as_usize().checked_sub(phys_start.as_usize())?;
+    if offset % ENTRY_SIZE != 0 || offset.checked_add(ENTRY_SIZE)? > virt_region.size() {
+        return None;
+    }
+
+    Some(virt_region.start_addr() + offset)
+}
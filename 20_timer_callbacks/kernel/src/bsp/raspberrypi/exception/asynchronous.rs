@@ -21,6 +21,12 @@ pub mod irq_map {
     /// The non-secure physical timer IRQ number.
     pub const ARM_NS_PHYSICAL_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
 
+    /// The virtual timer IRQ number.
+    pub const ARM_VIRTUAL_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(3));
+
+    /// The IRQ number of the system timer's compare channel 1.
+    pub const SYSTEM_TIMER_1: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(1));
+
     pub(in crate::bsp) const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
 }
 
@@ -32,5 +38,8 @@ pub mod irq_map {
     /// The non-secure physical timer IRQ number.
     pub const ARM_NS_PHYSICAL_TIMER: IRQNumber = IRQNumber::new(30);
 
+    /// The virtual timer IRQ number.
+    pub const ARM_VIRTUAL_TIMER: IRQNumber = IRQNumber::new(27);
+
     pub(in crate::bsp) const PL011_UART: IRQNumber = IRQNumber::new(153);
 }
@@ -19,20 +19,34 @@
 //! |                                       | code_start @ 0x8_0000 == boot_core_stack_end_exclusive
 //! | .text                                 |
 //! | .rodata                               |
-//! | .got                                  |
+//! | .rela.dyn                             |
 //! | .kernel_symbols                       |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       | data_start == code_end_exclusive
 //! | .data                                 |
+//! | .got                                  |
 //! | .bss                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | heap_start == data_end_exclusive
+//! |                                       | user_code_start == data_end_exclusive
+//! | .user_text                            |
+//! | .user_programs                        |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | user_stacks_start == user_code_end_exclusive
+//! | .user_stacks                          |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | heap_start == user_stacks_end_exclusive
 //! | .heap                                 |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       | heap_end_exclusive
+//! | Physical frame pool                   |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | DRAM_END
 //! |                                       |
 //!
 //!
@@ -42,19 +56,33 @@
 //! The virtual memory layout is as follows:
 //!
 //! +---------------------------------------+
-//! |                                       | code_start @ __kernel_virt_start_addr
+//! |                                       | __kernel_virt_start_addr
+//! | Unmapped, size of the KASLR offset    |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | code_start @ __kernel_virt_start_addr + KASLR offset
 //! | .text                                 |
 //! | .rodata                               |
-//! | .got                                  |
+//! | .rela.dyn                             |
 //! | .kernel_symbols                       |
 //! |                                       |
 //! +---------------------------------------+
 //! |                                       | data_start == code_end_exclusive
 //! | .data                                 |
+//! | .got                                  |
 //! | .bss                                  |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | heap_start == data_end_exclusive
+//! |                                       | user_code_start == data_end_exclusive
+//! | .user_text                            |
+//! | .user_programs                        |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | user_stacks_start == user_code_end_exclusive
+//! | .user_stacks                          |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | heap_start == user_stacks_end_exclusive
 //! | .heap                                 |
 //! |                                       |
 //! +---------------------------------------+
@@ -73,10 +101,23 @@
 //! |                                       |                                | direction
 //! +---------------------------------------+
 //! |                                       | boot_core_stack_end_exclusive
+//! | Unmapped guard page                   |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       |
+//! | VA region for VMAs, stacks and heap   |
 //! |                                       |
+//! +---------------------------------------+
+//! |                                       |
+//! | Unmapped guard page                   |
+//! |                                       |
+//! +---------------------------------------+ u64::MAX
 pub mod mmu;
 
-use crate::memory::{mmu::PageAddress, Address, Physical, Virtual};
+use crate::{
+    bsp::device_driver,
+    memory::{mmu::PageAddress, Address, Bus, Physical, Virtual},
+};
 use core::cell::UnsafeCell;
 
 //--------------------------------------------------------------------------------------------------
@@ -91,6 +132,12 @@ extern "Rust" {
     static __data_start: UnsafeCell<()>;
     static __data_end_exclusive: UnsafeCell<()>;
 
+    static __user_code_start: UnsafeCell<()>;
+    static __user_code_end_exclusive: UnsafeCell<()>;
+
+    static __user_stacks_start: UnsafeCell<()>;
+    static __user_stacks_end_exclusive: UnsafeCell<()>;
+
     static __heap_start: UnsafeCell<()>;
     static __heap_end_exclusive: UnsafeCell<()>;
 
@@ -118,6 +165,8 @@ pub(super) mod map {
         pub const PERIPHERAL_IC_START: Address<Physical> = Address::new(0x3F00_B200);
         pub const PERIPHERAL_IC_SIZE:  usize             =              0x24;
 
+        pub const RNG_START:           Address<Physical> = Address::new(0x3F10_4000);
+
         pub const GPIO_START:          Address<Physical> = Address::new(0x3F20_0000);
         pub const GPIO_SIZE:           usize             =              0xA0;
 
@@ -135,6 +184,8 @@ pub(super) mod map {
     pub mod mmio {
         use super::*;
 
+        pub const RNG_START:        Address<Physical> = Address::new(0xFE10_4000);
+
         pub const GPIO_START:       Address<Physical> = Address::new(0xFE20_0000);
         pub const GPIO_SIZE:        usize             =              0xA0;
 
@@ -150,6 +201,36 @@ pub(super) mod map {
         pub const END:              Address<Physical> = Address::new(0xFF85_0000);
     }
 
+    /// Start of the memory that the firmware reserves for the VideoCore.
+    ///
+    /// Assumes the firmware's default split, which reserves the memory directly below 1 GiB.
+    pub const VIDEOCORE_START: Address<Physical> = Address::new(0x3C00_0000);
+
+    /// End of the DRAM that is available to the ARM cores.
+    #[cfg(feature = "bsp_rpi3")]
+    pub const DRAM_END: Address<Physical> = VIDEOCORE_START;
+
+    /// End of the memory that the firmware reserves for the VideoCore.
+    #[cfg(feature = "bsp_rpi4")]
+    pub const VIDEOCORE_END: Address<Physical> = Address::new(0x4000_0000);
+
+    /// End of the DRAM that is available to the ARM cores.
+    ///
+    /// Assumes a board with at least 2 GiB. The VideoCore's memory is a hole below it.
+    #[cfg(feature = "bsp_rpi4")]
+    pub const DRAM_END: Address<Physical> = Address::new(0x8000_0000);
+
+    /// End of the DRAM that bus masters can reach through [`BUS_DRAM_OFFSET`].
+    ///
+    /// The alias covers only the first GiB, of which the VideoCore's part is off limits.
+    pub const BUS_DRAM_END: Address<Physical> = VIDEOCORE_START;
+
+    /// Where bus masters like the DMA engines see DRAM.
+    ///
+    /// This is the VideoCore's alias that bypasses its L2 cache. The ARM cores don't use that
+    /// cache, so it is the only alias which is coherent with what they see.
+    pub const BUS_DRAM_OFFSET: usize = 0xC000_0000;
+
     pub const END: Address<Physical> = mmio::END;
 }
 
@@ -193,6 +274,38 @@ fn data_size() -> usize {
     unsafe { (__data_end_exclusive.get() as usize) - (__data_start.get() as usize) }
 }
 
+/// Start page address of the user code segment.
+#[inline(always)]
+fn virt_user_code_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __user_code_start.get() as usize })
+}
+
+/// Size of the user code segment.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn user_code_size() -> usize {
+    unsafe { (__user_code_end_exclusive.get() as usize) - (__user_code_start.get() as usize) }
+}
+
+/// Start page address of the user stacks segment.
+#[inline(always)]
+fn virt_user_stacks_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __user_stacks_start.get() as usize })
+}
+
+/// Size of the user stacks segment.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn user_stacks_size() -> usize {
+    unsafe { (__user_stacks_end_exclusive.get() as usize) - (__user_stacks_start.get() as usize) }
+}
+
 /// Start page address of the heap segment.
 #[inline(always)]
 fn virt_heap_start() -> PageAddress<Virtual> {
@@ -252,3 +365,39 @@ fn boot_core_stack_size() -> usize {
 pub fn phys_addr_space_end_exclusive_addr() -> PageAddress<Physical> {
     PageAddress::from(map::END)
 }
+
+/// Exclusive end address of the DRAM that is available to the ARM cores.
+#[inline(always)]
+pub fn phys_dram_end_exclusive_addr() -> Address<Physical> {
+    map::DRAM_END
+}
+
+/// Translate the start of `size` bytes of physical DRAM to the address that bus masters use for it.
+///
+/// All of the bytes must be visible to bus masters.
+pub fn phys_to_bus_addr(
+    phys_addr: Address<Physical>,
+    size: usize,
+) -> Result<Address<Bus>, &'static str> {
+    let end_exclusive = phys_addr
+        .as_usize()
+        .checked_add(size)
+        .ok_or("Size overflows the address space")?;
+
+    if end_exclusive > map::BUS_DRAM_END.as_usize() {
+        return Err("Address range is not in bus-visible DRAM");
+    }
+
+    Ok(Address::new(phys_addr.as_usize() + map::BUS_DRAM_OFFSET))
+}
+
+/// Draw entropy from the hardware RNG while the MMU is still turned off.
+///
+/// # Safety
+///
+/// - The RNG is accessed at its physical address, so the MMU must be off.
+pub unsafe fn early_hw_rng_entropy() -> Option<u64> {
+    let rng = device_driver::RNG::new(Address::new(map::mmio::RNG_START.as_usize()));
+
+    rng.try_read_u64()
+}
@@ -12,7 +12,7 @@ use crate::{
         },
         Physical, Virtual,
     },
-    synchronization::InitStateLock,
+    synchronization::RwSpinLock,
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -22,31 +22,58 @@ use crate::{
 type KernelTranslationTable =
     <KernelVirtAddrSpace as AssociatedTranslationTable>::TableStartFromTop;
 
+/// The size of the translation granule. Defaults to 64 KiB, the `granule_4k` and `granule_16k`
+/// features select smaller pages.
+const KERNEL_GRANULE_SIZE: usize = if cfg!(feature = "granule_4k") {
+    4 * 1024
+} else if cfg!(feature = "granule_16k") {
+    16 * 1024
+} else {
+    64 * 1024
+};
+
+#[cfg(all(feature = "granule_4k", feature = "granule_16k"))]
+compile_error!("The granule_4k and granule_16k features are mutually exclusive");
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 /// The translation granule chosen by this BSP. This will be used everywhere else in the kernel to
 /// derive respective data structures and their sizes. For example, the `crate::memory::mmu::Page`.
-pub type KernelGranule = TranslationGranule<{ 64 * 1024 }>;
+pub type KernelGranule = TranslationGranule<{ KERNEL_GRANULE_SIZE }>;
 
 /// The kernel's virtual address space defined by this BSP.
 pub type KernelVirtAddrSpace = AddressSpace<{ kernel_virt_addr_space_size() }>;
 
+/// The virtual address space of user address spaces, starting at address zero.
+pub type UserVirtAddrSpace = AddressSpace<{ 1024 * 1024 * 1024 }>;
+
+/// The translation table type of user address spaces.
+pub type UserTranslationTable =
+    <UserVirtAddrSpace as AssociatedTranslationTable>::DynamicTableStartFromBottom;
+
+/// Upper bound for the random offset by which the kernel binary is slid up in its address space.
+///
+/// A multiple of the largest translation granule, so that each of them can be used.
+pub const KASLR_MAX_OFFSET: usize = 256 * 1024 * 1024;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
 /// The kernel translation tables.
 ///
-/// It is mandatory that InitStateLock is transparent.
+/// It is mandatory that the tables are placed at the start of the RwSpinLock, because the
+/// "translation table tool" patches them in at the address of this symbol. There is a unit test
+/// that checks this property.
 ///
-/// That is, `size_of(InitStateLock<KernelTranslationTable>) == size_of(KernelTranslationTable)`.
-/// There is a unit tests that checks this porperty.
+/// In contrast to the other kernel mappings, demand paging modifies the tables at runtime, so they
+/// need a real lock.
 #[link_section = ".data"]
 #[no_mangle]
-static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
-    InitStateLock::new(KernelTranslationTable::new_for_precompute());
+static KERNEL_TABLES: RwSpinLock<KernelTranslationTable> =
+    RwSpinLock::new(KernelTranslationTable::new_for_precompute());
 
 /// This value is needed during early boot for MMU setup.
 ///
@@ -122,6 +149,26 @@ pub fn virt_code_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// The user code pages.
+pub fn virt_user_code_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::user_code_size());
+
+    let start_page_addr = super::virt_user_code_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The user stack pages.
+pub fn virt_user_stacks_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::user_stacks_size());
+
+    let start_page_addr = super::virt_user_stacks_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
 /// The heap pages.
 pub fn virt_heap_region() -> MemoryRegion<Virtual> {
     let num_pages = size_to_num_pages(super::heap_size());
@@ -142,8 +189,20 @@ pub fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// Slide the precomputed kernel mappings up by the given offset.
+///
+/// # Safety
+///
+/// - Must only be called by the boot core with the MMU turned off, before anything else uses the
+///   tables. The lock is bypassed, because it can't be used before the MMU is on.
+pub unsafe fn kernel_slide_precomputed_tables(offset: usize) -> Result<(), &'static str> {
+    let tables = &mut *KERNEL_TABLES.data_ptr();
+
+    tables.slide(offset >> KernelGranule::SHIFT)
+}
+
 /// Return a reference to the kernel's translation tables.
-pub fn kernel_translation_tables() -> &'static InitStateLock<KernelTranslationTable> {
+pub fn kernel_translation_tables() -> &'static RwSpinLock<KernelTranslationTable> {
     &KERNEL_TABLES
 }
 
@@ -157,6 +216,47 @@ pub fn virt_mmio_remap_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// The physical DRAM that is not used by the kernel binary, its heap or the boot core stack.
+///
+/// The heap is the last section of the kernel binary in physical memory.
+pub fn phys_frame_pool_region() -> MemoryRegion<Physical> {
+    let start_page_addr = kernel_virt_to_phys_region(virt_heap_region()).end_exclusive_page_addr();
+    let end_exclusive_page_addr = PageAddress::from(super::map::DRAM_END);
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
+/// The part of the frame pool that the ARM cores must not use.
+#[cfg(feature = "bsp_rpi3")]
+pub fn phys_frame_pool_reserved_region() -> Option<MemoryRegion<Physical>> {
+    None
+}
+
+/// The part of the frame pool that the ARM cores must not use.
+///
+/// This is the VideoCore's memory, which sits in the middle of the DRAM.
+#[cfg(feature = "bsp_rpi4")]
+pub fn phys_frame_pool_reserved_region() -> Option<MemoryRegion<Physical>> {
+    Some(MemoryRegion::new(
+        PageAddress::from(super::map::VIDEOCORE_START),
+        PageAddress::from(super::map::VIDEOCORE_END),
+    ))
+}
+
+/// The virtual pages for demand paged VMAs, guarded kernel stacks and heap growth.
+///
+/// This is all of the kernel's address space above the boot core stack. An unmapped guard page is
+/// left at either end.
+pub fn virt_vma_region() -> MemoryRegion<Virtual> {
+    let start_page_addr = virt_boot_core_stack_region()
+        .end_exclusive_page_addr()
+        .checked_offset(1)
+        .unwrap();
+    let end_exclusive_page_addr = PageAddress::from(usize::MAX & !KernelGranule::MASK);
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
 /// Add mapping records for the kernel binary.
 ///
 /// The actual translation table entries for the kernel binary are generated using the offline
@@ -179,6 +279,22 @@ pub fn kernel_add_mapping_records_for_precomputed() {
         &kernel_page_attributes(virt_data_region.start_page_addr()),
     );
 
+    let virt_user_code_region = virt_user_code_region();
+    generic_mmu::kernel_add_mapping_record(
+        "User code and RO data",
+        &virt_user_code_region,
+        &kernel_virt_to_phys_region(virt_user_code_region),
+        &kernel_page_attributes(virt_user_code_region.start_page_addr()),
+    );
+
+    let virt_user_stacks_region = virt_user_stacks_region();
+    generic_mmu::kernel_add_mapping_record(
+        "User stacks",
+        &virt_user_stacks_region,
+        &kernel_virt_to_phys_region(virt_user_stacks_region),
+        &kernel_page_attributes(virt_user_stacks_region.start_page_addr()),
+    );
+
     let virt_heap_region = virt_heap_region();
     generic_mmu::kernel_add_mapping_record(
         "Kernel heap",
@@ -8,7 +8,253 @@
 #[path = "../_arch/aarch64/cpu/smp.rs"]
 mod arch_smp;
 
+use crate::{
+    bsp,
+    device_tree::DeviceTree,
+    exception,
+    memory::{
+        mmu::{self, MemoryRegion},
+        Address, Physical, Virtual,
+    },
+    state, time,
+};
+use core::{
+    hint,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SECONDARY_STACK_SIZE: usize = 64 * 1024;
+
+/// How long to wait for a released core to report in.
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(1);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The mechanism that is used to release the secondary cores.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum EnableMethod {
+    /// Write the entry address into the firmware's spin table and send an event.
+    SpinTable,
+
+    /// Use the PSCI `CPU_ON` call of the firmware.
+    Psci,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_FALSE: AtomicBool = AtomicBool::new(false);
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_ZERO: AtomicUsize = AtomicUsize::new(0);
+
+static CORE_ONLINE: [AtomicBool; bsp::cpu::NUM_CORES] = [ATOMIC_FALSE; bsp::cpu::NUM_CORES];
+
+/// The start addresses of the secondary cores' stacks.
+static STACK_START: [AtomicUsize; bsp::cpu::NUM_CORES] = [ATOMIC_ZERO; bsp::cpu::NUM_CORES];
+
+/// The function that secondary cores execute once the kernel is in `MultiCoreMain`.
+static SECONDARY_ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+/// Written once by the boot core while the MMU is off. Only plain loads and stores are used,
+/// because atomic read-modify-write operations need the MMU.
+static ENABLE_METHOD: AtomicU8 = AtomicU8::new(bsp::cpu::SECONDARY_CORE_ENABLE_METHOD as u8);
+
+/// The physical addresses of the cores' spin table entries, as named by the firmware's device tree.
+/// Zero if it does not name one. Written like [`ENABLE_METHOD`].
+static SPIN_TABLE_ENTRY: [AtomicUsize; bsp::cpu::NUM_CORES] = [ATOMIC_ZERO; bsp::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl EnableMethod {
+    /// Parse the `enable-method` property of a device tree cpu node.
+    fn from_device_tree(device_tree: &DeviceTree) -> Option<Self> {
+        // All cores share the method, so looking at the first secondary core is enough.
+        let property = device_tree.property("/cpus/cpu@1", "enable-method")?;
+
+        if property.is_str("spin-table") {
+            Some(Self::SpinTable)
+        } else if property.is_str("psci") {
+            Some(Self::Psci)
+        } else {
+            None
+        }
+    }
+}
+
+/// Read the `cpu-release-addr` property of a device tree cpu node, which holds two cells.
+fn spin_table_entry_from_device_tree(device_tree: &DeviceTree, core_id: usize) -> Option<usize> {
+    // Avoid formatting, which is not usable before the kernel relocated itself.
+    let mut path = *b"/cpus/cpu@0";
+    path[path.len() - 1] += u8::try_from(core_id).ok().filter(|&x| x < 10)?;
+    let path = core::str::from_utf8(&path).ok()?;
+
+    let property = device_tree.property(path, "cpu-release-addr")?;
+    if property.len() != 8 {
+        return None;
+    }
+
+    let addr = (u64::from(property.cell(0)?) << 32) | u64::from(property.cell(1)?);
+
+    // Zero stands for a missing property.
+    usize::try_from(addr).ok().filter(|&x| x != 0)
+}
+
+/// Allocate a guarded stack for a secondary core. It is never freed.
+fn alloc_stack() -> Result<MemoryRegion<Virtual>, &'static str> {
+    let num_pages = SECONDARY_STACK_SIZE / bsp::memory::mmu::KernelGranule::SIZE;
+
+    mmu::kernel_alloc_stack(NonZeroUsize::new(num_pages).unwrap())
+}
+
+/// Release a secondary core and wait until it has reached `kernel_init_secondary()`.
+fn start_core(core_id: usize) -> Result<(), &'static str> {
+    let stack = alloc_stack()?;
+    STACK_START[core_id].store(stack.start_addr().as_usize(), Ordering::Relaxed);
+
+    unsafe { arch_smp::start_core(core_id, enable_method(), stack)? };
+
+    let deadline = time::time_manager().uptime() + ONLINE_TIMEOUT;
+    while !CORE_ONLINE[core_id].load(Ordering::Acquire) {
+        if time::time_manager().uptime() > deadline {
+            return Err("Timeout waiting for core to come online");
+        }
+        hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+/// The kernel entry of secondary cores.
+///
+/// Virtual memory is already enabled when this function runs.
+///
+/// # Safety
+///
+/// - Must only be entered through `_start_secondary`.
+#[no_mangle]
+unsafe fn kernel_init_secondary() -> ! {
+    exception::handling_init();
+
+    CORE_ONLINE[core_id::<usize>()].store(true, Ordering::Release);
+
+    // Do not touch any shared kernel state before the boot core gave the go.
+    while !state::state_manager().is_multi_core_main() {
+        hint::spin_loop();
+    }
+
+    // Prepare the banked parts of the interrupt controller and the core's own timer. IRQs stay
+    // masked until `entry` unmasks them.
+    exception::asynchronous::irq_manager().secondary_core_init();
+    time::secondary_core_init();
+
+    let entry: fn() -> ! = core::mem::transmute(SECONDARY_ENTRY.load(Ordering::Relaxed));
+    entry()
+}
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_smp::core_id;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Choose how the secondary cores are released.
+///
+/// The method and the spin table entries named by the firmware's device tree are used, if there
+/// are any. The BSP's defaults are kept otherwise.
+///
+/// # Safety
+///
+/// - Must only be called by the boot core with the MMU turned off.
+pub unsafe fn early_init(device_tree: Option<&DeviceTree>) {
+    let device_tree = match device_tree {
+        None => return,
+        Some(x) => x,
+    };
+
+    if let Some(method) = EnableMethod::from_device_tree(device_tree) {
+        ENABLE_METHOD.store(method as u8, Ordering::Relaxed);
+    }
+
+    for (core_id, entry) in SPIN_TABLE_ENTRY.iter().enumerate() {
+        if let Some(addr) = spin_table_entry_from_device_tree(device_tree, core_id) {
+            entry.store(addr, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The mechanism that is used to release the secondary cores.
+pub fn enable_method() -> EnableMethod {
+    if ENABLE_METHOD.load(Ordering::Relaxed) == EnableMethod::Psci as u8 {
+        EnableMethod::Psci
+    } else {
+        EnableMethod::SpinTable
+    }
+}
+
+/// The physical address of a core's spin table entry.
+///
+/// The `cpu-release-addr` named by the firmware's device tree is used, if there is one. The BSP's
+/// default otherwise.
+pub fn spin_table_entry_addr(core_id: usize) -> Option<Address<Physical>> {
+    let addr = SPIN_TABLE_ENTRY.get(core_id)?.load(Ordering::Relaxed);
+
+    if addr == 0 {
+        Some(bsp::cpu::default_spin_table_entry_addr(core_id))
+    } else {
+        Some(Address::new(addr))
+    }
+}
+
+/// Boot all secondary cores and transition the kernel to `MultiCoreMain`.
+///
+/// The cores run with IRQs masked and execute `entry` once all of them are online. Their private
+/// IRQs, like the timer, are enabled in the interrupt controller already, so `entry` may unmask
+/// IRQs. Kernel threads are only scheduled on the boot core. Cores that fail
+/// to come online are reported in the error, but the kernel stays in `SingleCoreMain` then.
+pub fn start_secondary_cores(entry: fn() -> !) -> Result<(), &'static str> {
+    if !state::state_manager().is_single_core_main() {
+        return Err("Secondary cores can only be started from SingleCoreMain");
+    }
+
+    SECONDARY_ENTRY.store(entry as usize, Ordering::Relaxed);
+
+    let boot_core_id = bsp::cpu::BOOT_CORE_ID as usize;
+    for core_id in (0..bsp::cpu::NUM_CORES).filter(|&i| i != boot_core_id) {
+        start_core(core_id)?;
+    }
+
+    state::state_manager().transition_to_multi_core_main();
+
+    Ok(())
+}
+
+/// The number of cores that are executing kernel code.
+pub fn num_cores_online() -> usize {
+    1 + CORE_ONLINE
+        .iter()
+        .filter(|online| online.load(Ordering::Relaxed))
+        .count()
+}
+
+/// Checks if the address is part of the executing secondary core's stack.
+pub fn is_current_secondary_stack_addr(addr: Address<Virtual>) -> bool {
+    let start = STACK_START[core_id::<usize>()].load(Ordering::Relaxed);
+
+    start != 0 && (start..start + SECONDARY_STACK_SIZE).contains(&addr.as_usize())
+}
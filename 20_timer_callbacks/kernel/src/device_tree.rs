@@ -0,0 +1,462 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Flattened device tree.
+//!
+//! The firmware hands over the address of a device tree blob in `x0`. The blob lives in DRAM that
+//! the kernel later hands out as free frames, so it is only read during early boot, while the MMU
+//! is still off. Whatever the kernel needs from it is copied into statics by the respective
+//! subsystem.
+//!
+//! The reader is deliberately minimal: It looks up single properties by node path. Since it runs
+//! before the kernel relocated itself, it works on raw addresses, never panics and does not use
+//! anything that needs relocations, like tables of pointers.
+//!
+//! # Resources
+//!
+//! - <https://github.com/devicetree-org/devicetree-specification/releases>
+
+use crate::{
+    bsp, common,
+    memory::{Address, Physical},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+/// The first version that has the `size_dt_struct` header field.
+const MIN_VERSION: u32 = 17;
+
+const TOKEN_BEGIN_NODE: u32 = 1;
+const TOKEN_END_NODE: u32 = 2;
+const TOKEN_PROP: u32 = 3;
+const TOKEN_NOP: u32 = 4;
+const TOKEN_END: u32 = 9;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A validated device tree blob.
+#[derive(Copy, Clone)]
+pub struct DeviceTree {
+    struct_start: usize,
+    struct_end_exclusive: usize,
+    strings_start: usize,
+    strings_end_exclusive: usize,
+}
+
+/// The value of a property.
+#[derive(Copy, Clone)]
+pub struct Property {
+    start: usize,
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+#[inline(always)]
+fn read_u8(addr: usize) -> u8 {
+    unsafe { (addr as *const u8).read_volatile() }
+}
+
+/// Read a big-endian word. Only aligned loads are used, because unaligned ones fault while the MMU
+/// is off.
+#[inline(always)]
+fn read_be_u32(addr: usize) -> u32 {
+    u32::from_be(unsafe { (addr as *const u32).read_volatile() })
+}
+
+/// Compare the NUL-terminated string at `addr` with `expected`.
+///
+/// If `ignore_unit_address` is set, a unit address (`@...`) in the string is ignored.
+fn str_matches(
+    mut addr: usize,
+    end_exclusive: usize,
+    expected: &[u8],
+    ignore_unit_address: bool,
+) -> bool {
+    for &e in expected {
+        if addr >= end_exclusive || read_u8(addr) != e {
+            return false;
+        }
+        addr += 1;
+    }
+
+    if addr >= end_exclusive {
+        return false;
+    }
+
+    match read_u8(addr) {
+        0 => true,
+        b'@' => ignore_unit_address,
+        _ => false,
+    }
+}
+
+/// Split off the next component of a path like `/cpus/cpu@1`.
+fn next_component(path: &[u8]) -> (&[u8], &[u8]) {
+    let path = match path.split_first() {
+        Some((b'/', rest)) => rest,
+        _ => path,
+    };
+
+    match path.iter().position(|&c| c == b'/') {
+        None => (path, &[]),
+        Some(i) => path.split_at(i),
+    }
+}
+
+impl DeviceTree {
+    /// Validate the blob at `addr`, which must be readable up to `mem_end_exclusive`.
+    ///
+    /// # Safety
+    ///
+    /// - The memory from `addr` up to `mem_end_exclusive` must be readable.
+    unsafe fn from_raw(addr: usize, mem_end_exclusive: usize) -> Option<Self> {
+        if addr == 0
+            || !common::is_aligned(addr, 4)
+            || addr.checked_add(HEADER_SIZE)? > mem_end_exclusive
+        {
+            return None;
+        }
+
+        let header = |offset: usize| read_be_u32(addr + offset) as usize;
+        if header(0) != MAGIC as usize || header(20) < MIN_VERSION as usize {
+            return None;
+        }
+
+        let total_size = header(4);
+        if total_size < HEADER_SIZE || addr.checked_add(total_size)? > mem_end_exclusive {
+            return None;
+        }
+
+        let struct_start = addr + header(8);
+        let struct_end_exclusive = struct_start.checked_add(header(36))?;
+        let strings_start = addr + header(12);
+        let strings_end_exclusive = strings_start.checked_add(header(32))?;
+
+        let end_exclusive = addr + total_size;
+        if !common::is_aligned(struct_start, 4)
+            || struct_end_exclusive > end_exclusive
+            || strings_end_exclusive > end_exclusive
+        {
+            return None;
+        }
+
+        Some(Self {
+            struct_start,
+            struct_end_exclusive,
+            strings_start,
+            strings_end_exclusive,
+        })
+    }
+
+    /// Read the structure block word at `addr`, if it is in bounds.
+    fn struct_word(&self, addr: usize) -> Option<u32> {
+        if addr + 4 > self.struct_end_exclusive {
+            return None;
+        }
+
+        Some(read_be_u32(addr))
+    }
+
+    /// Skip a NUL-terminated string in the structure block, including the padding after it.
+    fn skip_struct_str(&self, mut addr: usize) -> Option<usize> {
+        while addr < self.struct_end_exclusive {
+            if read_u8(addr) == 0 {
+                return Some(common::align_up(addr + 1, 4));
+            }
+            addr += 1;
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DeviceTree {
+    /// Validate the blob at the physical address that the firmware handed over.
+    ///
+    /// Returns `None` if there is no valid blob, which is the case if the kernel was not started by
+    /// the firmware directly, or if the firmware was told not to provide a device tree.
+    ///
+    /// # Safety
+    ///
+    /// - The MMU must be off.
+    pub unsafe fn new(phys_addr: Address<Physical>) -> Option<Self> {
+        Self::from_raw(
+            phys_addr.as_usize(),
+            bsp::memory::phys_dram_end_exclusive_addr().as_usize(),
+        )
+    }
+
+    /// Look up a property of the node at `path`.
+    ///
+    /// A path component without unit address matches nodes with any unit address, so `/memory`
+    /// finds `/memory@0`.
+    pub fn property(&self, path: &str, name: &str) -> Option<Property> {
+        let path = path.as_bytes();
+
+        // The number of nodes enclosing `addr`, how many of them are on the path, and the part of
+        // the path that is still to be matched.
+        let mut depth = 0;
+        let mut matched = 0;
+        let mut remaining = path;
+
+        let mut addr = self.struct_start;
+        loop {
+            let token = self.struct_word(addr)?;
+            addr += 4;
+
+            match token {
+                TOKEN_BEGIN_NODE => {
+                    if depth == 0 {
+                        // The root node has an empty name.
+                        matched = 1;
+                    } else if depth == matched {
+                        let (component, rest) = next_component(remaining);
+                        let ignore_unit_address = !component.contains(&b'@');
+
+                        if !component.is_empty()
+                            && str_matches(
+                                addr,
+                                self.struct_end_exclusive,
+                                component,
+                                ignore_unit_address,
+                            )
+                        {
+                            matched += 1;
+                            remaining = rest;
+                        }
+                    }
+
+                    depth += 1;
+                    addr = self.skip_struct_str(addr)?;
+                }
+                TOKEN_END_NODE => {
+                    if depth == matched {
+                        // Leaving a matched node. Nodes with the same name are not expected, so
+                        // there is no need to restore the remaining path.
+                        return None;
+                    }
+
+                    depth -= 1;
+                }
+                TOKEN_PROP => {
+                    let len = self.struct_word(addr)? as usize;
+                    let name_offset = self.struct_word(addr + 4)? as usize;
+                    let start = addr + 8;
+                    addr = common::align_up(start.checked_add(len)?, 4);
+
+                    if addr > self.struct_end_exclusive {
+                        return None;
+                    }
+
+                    if depth == matched
+                        && next_component(remaining).0.is_empty()
+                        && str_matches(
+                            self.strings_start + name_offset,
+                            self.strings_end_exclusive,
+                            name.as_bytes(),
+                            false,
+                        )
+                    {
+                        return Some(Property { start, len });
+                    }
+                }
+                TOKEN_NOP => (),
+                TOKEN_END => return None,
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl Property {
+    /// The size of the value in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks if the value is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks if the value is the given string.
+    pub fn is_str(&self, expected: &str) -> bool {
+        self.len == expected.len() + 1
+            && str_matches(
+                self.start,
+                self.start + self.len,
+                expected.as_bytes(),
+                false,
+            )
+    }
+
+    /// Read the big-endian cell at `index`.
+    pub fn cell(&self, index: usize) -> Option<u32> {
+        let offset = index.checked_mul(4)?;
+        if offset.checked_add(4)? > self.len {
+            return None;
+        }
+
+        Some(read_be_u32(self.start + offset))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use test_macros::kernel_test;
+
+    /// Assembles a blob in memory, one big-endian word at a time.
+    struct Builder {
+        structs: Vec<u32>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Self {
+                structs: Vec::new(),
+                strings: Vec::new(),
+            }
+        }
+
+        fn push_bytes(words: &mut Vec<u32>, bytes: &[u8]) {
+            for chunk in bytes.chunks(4) {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                words.push(u32::from_ne_bytes(word));
+            }
+        }
+
+        fn begin_node(&mut self, name: &str) -> &mut Self {
+            self.structs.push(TOKEN_BEGIN_NODE.to_be());
+
+            let mut name = name.as_bytes().to_vec();
+            name.push(0);
+            Self::push_bytes(&mut self.structs, &name);
+
+            self
+        }
+
+        fn end_node(&mut self) -> &mut Self {
+            self.structs.push(TOKEN_END_NODE.to_be());
+            self
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            self.structs.push(TOKEN_PROP.to_be());
+            self.structs.push((value.len() as u32).to_be());
+            self.structs.push((self.strings.len() as u32).to_be());
+            Self::push_bytes(&mut self.structs, value);
+
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+
+            self
+        }
+
+        fn build(&mut self) -> Vec<u32> {
+            self.structs.push(TOKEN_END.to_be());
+
+            let struct_offset = HEADER_SIZE;
+            let struct_size = self.structs.len() * 4;
+            let strings_offset = struct_offset + struct_size;
+            let total_size = strings_offset + self.strings.len();
+
+            let mut blob: Vec<u32> = [
+                MAGIC,
+                total_size as u32,
+                struct_offset as u32,
+                strings_offset as u32,
+                0,
+                MIN_VERSION,
+                16,
+                0,
+                self.strings.len() as u32,
+                struct_size as u32,
+            ]
+            .iter()
+            .map(|x| x.to_be())
+            .collect();
+
+            blob.extend_from_slice(&self.structs);
+            Self::push_bytes(&mut blob, &self.strings);
+
+            blob
+        }
+    }
+
+    fn parse(blob: &[u32]) -> Option<DeviceTree> {
+        let addr = blob.as_ptr() as usize;
+
+        unsafe { DeviceTree::from_raw(addr, addr + blob.len() * 4) }
+    }
+
+    /// Properties are found by path, with and without unit address.
+    #[kernel_test]
+    fn property_lookup_by_path() {
+        let blob = Builder::new()
+            .begin_node("")
+            .begin_node("cpus")
+            .begin_node("cpu@0")
+            .prop("enable-method", b"spin-table\0")
+            .end_node()
+            .begin_node("cpu@1")
+            .prop("enable-method", b"psci\0")
+            .end_node()
+            .end_node()
+            .begin_node("memory@0")
+            .prop("reg", &[0, 0, 0, 0, 0x3b, 0x40, 0, 0])
+            .end_node()
+            .end_node()
+            .build();
+        let dt = parse(&blob).unwrap();
+
+        assert!(dt
+            .property("/cpus/cpu@0", "enable-method")
+            .unwrap()
+            .is_str("spin-table"));
+        assert!(dt
+            .property("/cpus/cpu@1", "enable-method")
+            .unwrap()
+            .is_str("psci"));
+        assert!(dt.property("/cpus/cpu@2", "enable-method").is_none());
+        assert!(dt.property("/cpus", "enable-method").is_none());
+
+        let reg = dt.property("/memory", "reg").unwrap();
+        assert_eq!(reg.len(), 8);
+        assert_eq!(reg.cell(1), Some(0x3b40_0000));
+        assert_eq!(reg.cell(2), None);
+    }
+
+    /// Anything without the magic number is rejected.
+    #[kernel_test]
+    fn invalid_blob_is_rejected() {
+        let mut blob = Builder::new().begin_node("").end_node().build();
+        assert!(parse(&blob).is_some());
+
+        blob[0] = 0;
+        assert!(parse(&blob).is_none());
+        assert!(parse(&blob[..4]).is_none());
+    }
+}
@@ -6,7 +6,7 @@
 
 use crate::{
     exception, info,
-    synchronization::{interface::ReadWriteEx, InitStateLock},
+    synchronization::{interface::ReadWriteEx, RwSpinLock},
 };
 use alloc::vec::Vec;
 use core::fmt;
@@ -69,7 +69,7 @@ pub struct DriverManager<T>
 where
     T: 'static,
 {
-    descriptors: InitStateLock<Vec<DeviceDriverDescriptor<T>>>,
+    descriptors: RwSpinLock<Vec<DeviceDriverDescriptor<T>>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -109,7 +109,7 @@ where
     /// Create an instance.
     pub const fn new() -> Self {
         Self {
-            descriptors: InitStateLock::new(Vec::new()),
+            descriptors: RwSpinLock::new(Vec::new()),
         }
     }
 
@@ -7,17 +7,28 @@
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/exception/asynchronous.rs"]
 mod arch_asynchronous;
+mod handler_table;
+mod irq_stats;
 mod null_irq_manager;
 
-use crate::{bsp, synchronization};
-use core::marker::PhantomData;
+pub mod deferred;
+pub mod fiq;
+
+use crate::{bsp, cpu, synchronization};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub use handler_table::IRQHandlerTable;
+pub use irq_stats::IRQStats;
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_asynchronous::{
-    is_local_irq_masked, local_irq_mask, local_irq_mask_save, local_irq_restore, local_irq_unmask,
-    print_state,
+    is_local_irq_masked, local_fiq_mask, local_fiq_unmask, local_irq_mask, local_irq_mask_save,
+    local_irq_restore, local_irq_unmask, print_state,
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -27,6 +38,27 @@ pub use arch_asynchronous::{
 /// Interrupt number as defined by the BSP.
 pub type IRQNumber = bsp::exception::asynchronous::IRQNumber;
 
+/// Interrupt priority.
+///
+/// Only honored by interrupt controllers that support priorities.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[allow(missing_docs)]
+pub enum IRQPriority {
+    High,
+    Normal,
+    Low,
+}
+
+/// What an IRQ handler made of an interrupt.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IRQReturn {
+    /// The interrupt came from the handler's device and was dealt with.
+    Handled,
+
+    /// The interrupt did not come from the handler's device. Only expected on shared lines.
+    NotHandled,
+}
+
 /// Interrupt descriptor.
 #[derive(Copy, Clone)]
 pub struct IRQHandlerDescriptor<T>
@@ -41,6 +73,15 @@ where
 
     /// Reference to handler trait object.
     handler: &'static (dyn interface::IRQHandler + Sync),
+
+    /// The priority of the IRQ.
+    priority: IRQPriority,
+
+    /// Whether the handler may be preempted by IRQs of higher priority.
+    nesting: bool,
+
+    /// Whether other handlers may be registered for the same IRQ number.
+    sharing: bool,
 }
 
 /// IRQContext token.
@@ -61,7 +102,9 @@ pub mod interface {
     /// Implemented by types that handle IRQs.
     pub trait IRQHandler {
         /// Called when the corresponding interrupt is asserted.
-        fn handle(&self) -> Result<(), &'static str>;
+        ///
+        /// Work that can wait should be handed to [`super::deferred::defer()`].
+        fn handle(&self) -> Result<super::IRQReturn, &'static str>;
     }
 
     /// IRQ management functions.
@@ -78,14 +121,44 @@ pub mod interface {
             irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
         ) -> Result<(), &'static str>;
 
+        /// Remove the handler that was registered with the same name and handler as
+        /// `irq_handler_descriptor`.
+        ///
+        /// Once this function returns, the handler does not run on any core anymore and will not
+        /// be called again. The interrupt is disabled when its last handler is removed.
+        ///
+        /// Must not be called from IRQ context, because it waits for running handlers to finish.
+        /// Handlers run while the handler table is read-locked, also when they allow nesting.
+        fn unregister_handler(
+            &self,
+            irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
+        ) -> Result<(), &'static str>;
+
         /// Enable an interrupt in the controller.
+        ///
+        /// Private interrupts, like the timer, are enabled for the executing core only.
         fn enable(&self, irq_number: &Self::IRQNumberType);
 
+        /// Disable an interrupt in the controller.
+        ///
+        /// Private interrupts are disabled for the executing core only. An interrupt that was
+        /// already signaled to a core might still be handled afterwards.
+        fn disable(&self, irq_number: &Self::IRQNumberType);
+
+        /// Prepare the executing secondary core for taking interrupts.
+        ///
+        /// Private interrupts are enabled per core, so the ones that have handlers are enabled for
+        /// the executing core as well. Shared interrupts stay with the boot core. Called once by
+        /// each secondary core while bringing it up, with IRQs masked.
+        fn secondary_core_init(&self) {}
+
         /// Handle pending interrupts.
         ///
         /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
         /// this means that the respective CPU core has disabled exception handling.
-        /// This function can therefore not be preempted and runs start to finish.
+        /// This function can therefore not be preempted by other IRQs and runs start to finish,
+        /// unless it calls a handler that allows nesting through [`super::exec_nested()`]. The FIQ
+        /// is unmasked again by the vector, so it can preempt this function.
         ///
         /// Takes an IRQContext token to ensure it can only be called from IRQ context.
         #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -94,8 +167,49 @@ pub mod interface {
             ic: &super::IRQContext<'irq_context>,
         );
 
+        /// Route an interrupt to the FIQ vector instead of the IRQ vector, and enable it.
+        ///
+        /// The controller records the interrupt, and refuses regular handlers for it afterwards.
+        /// Called by [`super::fiq::register_handler()`].
+        fn route_to_fiq(&self, _irq_number: &Self::IRQNumberType) -> Result<(), &'static str> {
+            Err("FIQs are not supported")
+        }
+
+        /// Handle a pending FIQ by calling the handler of `descriptor`.
+        ///
+        /// This function is called directly from the CPU's FIQ exception vector, with IRQs and
+        /// FIQs masked. The IRQContext token stands for FIQ context as well.
+        #[allow(clippy::trivially_copy_pass_by_ref)]
+        fn handle_pending_fiq<'irq_context>(
+            &'irq_context self,
+            _ic: &super::IRQContext<'irq_context>,
+            _descriptor: &super::IRQHandlerDescriptor<Self::IRQNumberType>,
+        ) {
+        }
+
+        /// Raise an interrupt from software on the executing core.
+        fn raise_software_irq(
+            &self,
+            _irq_number: &Self::IRQNumberType,
+        ) -> Result<(), &'static str> {
+            Err("Software IRQs are not supported")
+        }
+
         /// Print list of registered handlers.
         fn print_handler(&self) {}
+
+        /// Print invocation counts and handler durations of registered IRQs.
+        fn print_stats(&self) {}
+
+        /// The number of times an interrupt was handled, if the controller counts it.
+        fn irq_count(&self, _irq_number: &Self::IRQNumberType) -> Option<u64> {
+            None
+        }
+
+        /// The number of spurious interrupts, if the controller counts them.
+        fn num_spurious(&self) -> Option<u64> {
+            None
+        }
     }
 }
 
@@ -107,6 +221,12 @@ static CUR_IRQ_MANAGER: InitStateLock<
     &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
 > = InitStateLock::new(&null_irq_manager::NULL_IRQ_MANAGER);
 
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_ZERO: AtomicUsize = AtomicUsize::new(0);
+
+/// Per core, the number of IRQ handlers that currently run with IRQs unmasked.
+static NESTING_LEVELS: [AtomicUsize; bsp::cpu::NUM_CORES] = [ATOMIC_ZERO; bsp::cpu::NUM_CORES];
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -116,7 +236,7 @@ impl<T> IRQHandlerDescriptor<T>
 where
     T: Copy,
 {
-    /// Create an instance.
+    /// Create an instance with normal priority, which allows neither nesting nor sharing.
     pub const fn new(
         number: T,
         name: &'static str,
@@ -126,6 +246,48 @@ where
             number,
             name,
             handler,
+            priority: IRQPriority::Normal,
+            nesting: false,
+            sharing: false,
+        }
+    }
+
+    /// Return a copy with the given priority.
+    pub const fn with_priority(self, priority: IRQPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Return a copy whose handler may be preempted by IRQs of higher priority.
+    ///
+    /// Controllers without priorities hold back only this IRQ while the handler runs, so any other
+    /// IRQ can preempt it.
+    pub const fn with_nesting(self) -> Self {
+        Self {
+            nesting: true,
+            ..self
+        }
+    }
+
+    /// Return a copy that can share its IRQ number with other handlers that allow sharing.
+    pub const fn with_sharing(self) -> Self {
+        Self {
+            sharing: true,
+            ..self
+        }
+    }
+
+    /// Return a copy for a different type of IRQ number.
+    pub fn with_number<U>(self, number: U) -> IRQHandlerDescriptor<U>
+    where
+        U: Copy,
+    {
+        IRQHandlerDescriptor {
+            number,
+            name: self.name,
+            handler: self.handler,
+            priority: self.priority,
+            nesting: self.nesting,
+            sharing: self.sharing,
         }
     }
 
@@ -143,6 +305,21 @@ where
     pub const fn handler(&self) -> &'static (dyn interface::IRQHandler + Sync) {
         self.handler
     }
+
+    /// Return the priority.
+    pub const fn priority(&self) -> IRQPriority {
+        self.priority
+    }
+
+    /// Return whether the handler may be preempted by IRQs of higher priority.
+    pub const fn allows_nesting(&self) -> bool {
+        self.nesting
+    }
+
+    /// Return whether other handlers may be registered for the same IRQ number.
+    pub const fn allows_sharing(&self) -> bool {
+        self.sharing
+    }
 }
 
 impl<'irq_context> IRQContext<'irq_context> {
@@ -175,6 +352,36 @@ pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
     ret
 }
 
+/// Executes the provided closure with IRQs unmasked, so that it can be preempted by IRQs of higher
+/// priority.
+///
+/// The interrupt controller must hold back the IRQ that is being handled, and those of lower or
+/// equal priority, until handling is marked complete. This is the case after the IRQ has been
+/// acknowledged on controllers that support priorities. Controllers without priorities must disable
+/// the IRQ for the duration instead.
+///
+/// Deferred work runs through this function as well, after handling was marked complete. FIQs are
+/// left as the IRQ vector set them, which is unmasked unless the interrupted context masked them.
+pub fn exec_nested<T>(_ic: &IRQContext, f: impl FnOnce() -> T) -> T {
+    let level = &NESTING_LEVELS[cpu::smp::core_id::<usize>()];
+
+    level.fetch_add(1, Ordering::Relaxed);
+    local_irq_unmask();
+
+    let ret = f();
+
+    local_irq_mask();
+    level.fetch_sub(1, Ordering::Relaxed);
+
+    ret
+}
+
+/// Returns true if the IRQ that is being handled preempted the handler of another one, or deferred
+/// work.
+pub fn is_nested(_ic: &IRQContext) -> bool {
+    NESTING_LEVELS[cpu::smp::core_id::<usize>()].load(Ordering::Relaxed) != 0
+}
+
 /// Register a new IRQ manager.
 pub fn register_irq_manager(
     new_manager: &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
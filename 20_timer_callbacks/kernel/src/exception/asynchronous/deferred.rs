@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Deferred work.
+//!
+//! IRQ handlers run with IRQs masked, so everything they do delays all other interrupts. Work that
+//! does not need to happen right away can be handed to [`defer()`] instead. It runs at the end of
+//! IRQ handling, after the interrupt controller was told that handling is complete, with IRQs
+//! unmasked.
+//!
+//! Each core has its own queue. Work runs on the core that deferred it, the next time it exits an
+//! IRQ, in the order it was deferred there. Work deferred on different cores is not ordered.
+
+use super::IRQContext;
+use crate::{
+    bsp, cpu,
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
+};
+use alloc::{boxed::Box, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A deferred work item.
+pub type DeferredWork = Box<dyn FnOnce() + Send>;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: IRQSafeSpinLock<Vec<DeferredWork>> = IRQSafeSpinLock::new(Vec::new());
+
+// A thread can migrate between looking up its core's queue and locking it, so the queues are
+// locked although each belongs to a single core.
+static QUEUES: [IRQSafeSpinLock<Vec<DeferredWork>>; bsp::cpu::NUM_CORES] =
+    [EMPTY_QUEUE; bsp::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The queue of the executing core.
+fn local_queue() -> &'static IRQSafeSpinLock<Vec<DeferredWork>> {
+    &QUEUES[cpu::smp::core_id::<usize>()]
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Queue work to run at the end of IRQ handling on the executing core.
+///
+/// If called outside of IRQ context, the work runs at the end of the core's next IRQ.
+pub fn defer(work: DeferredWork) {
+    local_queue().lock(|queue| queue.push(work));
+}
+
+/// Run deferred work until the executing core's queue is empty.
+///
+/// Must be called at the end of IRQ handling, after the interrupt controller was told that handling
+/// is complete. The IRQContext token ensures this.
+///
+/// IRQs that arrive while the work runs count as nested. They leave their deferred work, and the
+/// reschedule, to the IRQ they preempted.
+pub fn run_pending(ic: &IRQContext) {
+    if super::is_nested(ic) {
+        return;
+    }
+
+    loop {
+        let batch = local_queue().lock(core::mem::take);
+        if batch.is_empty() {
+            return;
+        }
+
+        super::exec_nested(ic, || {
+            for work in batch {
+                work();
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use test_macros::kernel_test;
+
+    /// Work runs only when the queue is processed, in the order it was deferred, and with IRQs
+    /// unmasked.
+    #[kernel_test]
+    fn work_runs_in_order_with_irqs_unmasked() {
+        static SEQ: AtomicUsize = AtomicUsize::new(1);
+        static FIRST: AtomicUsize = AtomicUsize::new(0);
+        static SECOND: AtomicUsize = AtomicUsize::new(0);
+        static UNMASKED: AtomicUsize = AtomicUsize::new(0);
+
+        for x in [&FIRST, &SECOND] {
+            defer(Box::new(move || {
+                x.store(SEQ.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+
+                // Returns true if IRQs are unmasked, see 04_exception_irq_sanity.
+                if super::super::is_local_irq_masked() {
+                    UNMASKED.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+        assert_eq!(FIRST.load(Ordering::Relaxed), 0);
+
+        super::super::exec_with_irq_masked(|| {
+            let ic = unsafe { &IRQContext::new() };
+            run_pending(ic);
+        });
+
+        assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+        assert_eq!(SECOND.load(Ordering::Relaxed), 2);
+        assert_eq!(UNMASKED.load(Ordering::Relaxed), 2);
+        assert!(local_queue().lock(|queue| queue.is_empty()));
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Fast interrupt requests.
+//!
+//! Exactly one interrupt source can be routed as FIQ. Its handler is called straight from the FIQ
+//! vector, bypassing IRQ dispatch. There are no statistics, no deferred work and no rescheduling on
+//! this path. The IRQ vector unmasks FIQs, so the FIQ preempts IRQ handlers and deferred work as
+//! well.
+//!
+//! IRQ-safe locks do not mask FIQs. A FIQ handler therefore must neither take locks nor allocate,
+//! and should share data with the rest of the kernel through atomics only.
+
+use super::{irq_manager, IRQContext, IRQHandlerDescriptor, IRQNumber};
+use crate::synchronization::{interface::ReadWriteEx, InitStateLock};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static FIQ_HANDLER: InitStateLock<Option<IRQHandlerDescriptor<IRQNumber>>> =
+    InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Route the interrupt of `descriptor` as FIQ and register the handler for it.
+///
+/// Only possible once, and only during kernel init with IRQs masked. The priority, nesting and
+/// sharing settings of the descriptor are ignored.
+pub fn register_handler(descriptor: IRQHandlerDescriptor<IRQNumber>) -> Result<(), &'static str> {
+    FIQ_HANDLER.write(|slot| {
+        if slot.is_some() {
+            return Err("FIQ handler already registered");
+        }
+
+        // Set before routing, so that the first FIQ finds its handler.
+        *slot = Some(descriptor);
+
+        irq_manager()
+            .route_to_fiq(&descriptor.number())
+            .map_err(|x| {
+                *slot = None;
+                x
+            })
+    })
+}
+
+/// Returns true if a FIQ handler was registered.
+pub fn is_registered() -> bool {
+    FIQ_HANDLER.read(|slot| slot.is_some())
+}
+
+/// Handle a pending FIQ.
+///
+/// Called from the FIQ vector.
+pub fn handle(ic: &IRQContext) {
+    match FIQ_HANDLER.read(|slot| *slot) {
+        None => panic!("FIQ without a registered handler"),
+        Some(descriptor) => irq_manager().handle_pending_fiq(ic, &descriptor),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception::asynchronous::{interface::IRQHandler, IRQReturn};
+    use test_macros::kernel_test;
+
+    struct TestHandler;
+
+    impl IRQHandler for TestHandler {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            Ok(IRQReturn::Handled)
+        }
+    }
+
+    static TEST_HANDLER: TestHandler = TestHandler;
+
+    /// A failed routing leaves no handler behind.
+    #[kernel_test]
+    fn failed_routing_is_undone() {
+        use crate::bsp::exception::asynchronous::irq_map;
+
+        // Unit tests run without an interrupt controller driver, and the null IRQ manager does not
+        // support FIQs.
+        let descriptor =
+            IRQHandlerDescriptor::new(irq_map::ARM_NS_PHYSICAL_TIMER, "Test", &TEST_HANDLER);
+
+        assert!(register_handler(descriptor).is_err());
+        assert!(!is_registered());
+    }
+}
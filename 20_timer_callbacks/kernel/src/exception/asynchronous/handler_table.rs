@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ handler table.
+//!
+//! Every IRQ number has a chain of handlers. A chain holds more than one handler only if all of
+//! them allow sharing the line, in which case each handler is called and reports whether the
+//! interrupt came from its device.
+
+use super::{IRQHandlerDescriptor, IRQReturn};
+use alloc::vec::Vec;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Registered IRQ handlers, indexed by IRQ number.
+pub struct IRQHandlerTable<T>
+where
+    T: Copy,
+{
+    chains: Vec<Vec<IRQHandlerDescriptor<T>>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Handlers are identified by their name and the address of their data.
+///
+/// The address alone is not enough, because zero-sized handlers may all share the same one.
+fn is_same_handler<T>(a: &IRQHandlerDescriptor<T>, b: &IRQHandlerDescriptor<T>) -> bool
+where
+    T: Copy,
+{
+    let addr = |x: &IRQHandlerDescriptor<T>| x.handler() as *const _ as *const ();
+
+    a.name() == b.name() && core::ptr::eq(addr(a), addr(b))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> IRQHandlerTable<T>
+where
+    T: Copy,
+{
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self { chains: Vec::new() }
+    }
+
+    /// Make room for the IRQ numbers `0..num_irqs`.
+    pub fn resize(&mut self, num_irqs: usize) {
+        self.chains.resize_with(num_irqs, Vec::new);
+    }
+
+    /// Add a handler to the chain of `irq_number`.
+    pub fn add(
+        &mut self,
+        irq_number: usize,
+        descriptor: IRQHandlerDescriptor<T>,
+    ) -> Result<(), &'static str> {
+        let chain = self
+            .chains
+            .get_mut(irq_number)
+            .ok_or("IRQ number out of range")?;
+
+        if let Some(first) = chain.first() {
+            if !(first.allows_sharing() && descriptor.allows_sharing()) {
+                return Err("IRQ handler already registered");
+            }
+
+            if first.priority() != descriptor.priority() {
+                return Err("Shared IRQ handlers must have the same priority");
+            }
+
+            if chain.iter().any(|x| is_same_handler(x, &descriptor)) {
+                return Err("Handler already registered for this IRQ");
+            }
+        }
+
+        chain.push(descriptor);
+        Ok(())
+    }
+
+    /// Remove the handler that was added with the same name and handler as `descriptor` from the
+    /// chain of `irq_number`.
+    ///
+    /// Returns whether the chain is empty afterwards.
+    pub fn remove(
+        &mut self,
+        irq_number: usize,
+        descriptor: &IRQHandlerDescriptor<T>,
+    ) -> Result<bool, &'static str> {
+        let chain = self
+            .chains
+            .get_mut(irq_number)
+            .ok_or("IRQ number out of range")?;
+
+        let index = chain
+            .iter()
+            .position(|x| is_same_handler(x, descriptor))
+            .ok_or("Handler not registered for this IRQ")?;
+
+        chain.remove(index);
+        Ok(chain.is_empty())
+    }
+
+    /// The handlers of `irq_number`, in the order they were registered.
+    pub fn chain(&self, irq_number: usize) -> &[IRQHandlerDescriptor<T>] {
+        self.chains.get(irq_number).map_or(&[], |x| x.as_slice())
+    }
+
+    /// Call each handler of `irq_number` through `call`. Panics if a handler fails.
+    ///
+    /// Returns [`IRQReturn::Handled`] if any handler claimed the interrupt.
+    pub fn dispatch(
+        &self,
+        irq_number: usize,
+        mut call: impl FnMut(&IRQHandlerDescriptor<T>) -> Result<IRQReturn, &'static str>,
+    ) -> IRQReturn {
+        let mut ret = IRQReturn::NotHandled;
+
+        for descriptor in self.chain(irq_number) {
+            if call(descriptor).expect("Error handling IRQ") == IRQReturn::Handled {
+                ret = IRQReturn::Handled;
+            }
+        }
+
+        ret
+    }
+
+    /// Iterate over the IRQ numbers that have handlers, and their chains.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[IRQHandlerDescriptor<T>])> {
+        self.chains
+            .iter()
+            .enumerate()
+            .filter(|(_, chain)| !chain.is_empty())
+            .map(|(i, chain)| (i, chain.as_slice()))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception::asynchronous::{interface, IRQPriority};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use test_macros::kernel_test;
+
+    struct TestHandler {
+        calls: AtomicUsize,
+        ret: IRQReturn,
+    }
+
+    impl interface::IRQHandler for TestHandler {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(self.ret)
+        }
+    }
+
+    static A: TestHandler = TestHandler {
+        calls: AtomicUsize::new(0),
+        ret: IRQReturn::NotHandled,
+    };
+    static B: TestHandler = TestHandler {
+        calls: AtomicUsize::new(0),
+        ret: IRQReturn::Handled,
+    };
+
+    fn new_table() -> IRQHandlerTable<usize> {
+        let mut table = IRQHandlerTable::new();
+        table.resize(4);
+
+        table
+    }
+
+    /// Only handlers that all allow sharing can be chained.
+    #[kernel_test]
+    fn sharing_requires_consent() {
+        let mut table = new_table();
+        let a = IRQHandlerDescriptor::new(1, "A", &A);
+        let b = IRQHandlerDescriptor::new(1, "B", &B);
+
+        assert!(table.add(1, a).is_ok());
+        assert!(table.add(1, b.with_sharing()).is_err());
+
+        let mut table = new_table();
+        assert!(table.add(1, a.with_sharing()).is_ok());
+        assert!(table.add(1, b).is_err());
+        assert!(table.add(1, a.with_sharing()).is_err());
+        assert!(table
+            .add(1, b.with_sharing().with_priority(IRQPriority::High))
+            .is_err());
+        assert!(table.add(1, b.with_sharing()).is_ok());
+
+        assert!(table.add(4, a).is_err());
+        assert_eq!(table.chain(1).len(), 2);
+        assert_eq!(table.iter().count(), 1);
+    }
+
+    /// All handlers of a chain are called, and the chain shrinks as they are removed.
+    #[kernel_test]
+    fn shared_handlers_are_dispatched_and_removed() {
+        let mut table = new_table();
+        let a = IRQHandlerDescriptor::new(2, "A", &A).with_sharing();
+        let b = IRQHandlerDescriptor::new(2, "B", &B).with_sharing();
+        table.add(2, a).unwrap();
+        table.add(2, b).unwrap();
+
+        let calls = || {
+            (
+                A.calls.load(Ordering::Relaxed),
+                B.calls.load(Ordering::Relaxed),
+            )
+        };
+        let (a_calls, b_calls) = calls();
+
+        let dispatch = |table: &IRQHandlerTable<usize>| table.dispatch(2, |x| x.handler().handle());
+
+        assert_eq!(dispatch(&table), IRQReturn::Handled);
+        assert_eq!(calls(), (a_calls + 1, b_calls + 1));
+
+        assert_eq!(table.remove(2, &b), Ok(false));
+        assert!(table.remove(2, &b).is_err());
+        assert_eq!(dispatch(&table), IRQReturn::NotHandled);
+        assert_eq!(calls(), (a_calls + 2, b_calls + 1));
+
+        assert_eq!(table.remove(2, &a), Ok(true));
+        assert_eq!(dispatch(&table), IRQReturn::NotHandled);
+        assert_eq!(calls(), (a_calls + 2, b_calls + 1));
+    }
+
+    /// Zero-sized handlers may share an address, so they are told apart by their name.
+    #[kernel_test]
+    fn zero_sized_handlers_are_told_apart_by_name() {
+        struct Nop;
+
+        impl interface::IRQHandler for Nop {
+            fn handle(&self) -> Result<IRQReturn, &'static str> {
+                Ok(IRQReturn::NotHandled)
+            }
+        }
+
+        static X: Nop = Nop;
+        static Y: Nop = Nop;
+
+        let mut table = new_table();
+        let x = IRQHandlerDescriptor::new(3, "X", &X).with_sharing();
+        let y = IRQHandlerDescriptor::new(3, "Y", &Y).with_sharing();
+        table.add(3, x).unwrap();
+        table.add(3, y).unwrap();
+
+        assert_eq!(table.remove(3, &y), Ok(false));
+        assert_eq!(table.chain(3)[0].name(), "X");
+        assert!(table.remove(3, &y).is_err());
+        assert_eq!(table.remove(3, &x), Ok(true));
+    }
+}
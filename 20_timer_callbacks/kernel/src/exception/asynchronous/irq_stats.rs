@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ statistics.
+//!
+//! Interrupt controller drivers keep one [`IRQStats`] per IRQ number and run handlers through
+//! [`IRQStats::measure()`]. Durations are taken from the architectural timer, and include the time
+//! spent in nested IRQs.
+
+use super::IRQHandlerDescriptor;
+use crate::{info, time};
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Invocation count and handler durations of a single IRQ.
+pub struct IRQStats {
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    min_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl IRQStats {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            min_ns: AtomicU64::new(u64::MAX),
+            max_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Account for one invocation of a handler that took `duration`.
+    pub fn record(&self, duration: Duration) {
+        let ns = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.min_ns.fetch_min(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    /// Run a handler and account for it.
+    pub fn measure<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = time::time_manager().uptime();
+        let ret = f();
+        self.record(time::time_manager().uptime().saturating_sub(start));
+
+        ret
+    }
+
+    /// The number of invocations.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The shortest handler duration, if there was any invocation.
+    pub fn min(&self) -> Option<Duration> {
+        (self.count() != 0).then(|| Duration::from_nanos(self.min_ns.load(Ordering::Relaxed)))
+    }
+
+    /// The average handler duration, if there was any invocation.
+    pub fn avg(&self) -> Option<Duration> {
+        let count = self.count();
+
+        (count != 0).then(|| Duration::from_nanos(self.total_ns.load(Ordering::Relaxed) / count))
+    }
+
+    /// The longest handler duration, if there was any invocation.
+    pub fn max(&self) -> Option<Duration> {
+        (self.count() != 0).then(|| Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)))
+    }
+
+    /// Print the header of the statistics table.
+    pub fn print_header() {
+        info!(
+            "      {:<16} {:>10} {:>12} {:>12} {:>12}   {}",
+            "IRQ", "Count", "Min [ns]", "Avg [ns]", "Max [ns]", "Handler"
+        );
+    }
+
+    /// Print a row of the statistics table.
+    pub fn print<T>(&self, number: &dyn fmt::Display, chain: &[IRQHandlerDescriptor<T>])
+    where
+        T: Copy,
+    {
+        let ns = |x: Option<Duration>| x.map_or(0, |x| x.as_nanos());
+        let names: Vec<&str> = chain.iter().map(|x| x.name()).collect();
+
+        info!(
+            "      {:<16} {:>10} {:>12} {:>12} {:>12}   {}",
+            number,
+            self.count(),
+            ns(self.min()),
+            ns(self.avg()),
+            ns(self.max()),
+            names.join(", ")
+        );
+    }
+
+    /// Print the row for IRQs that were signaled without any source being pending.
+    pub fn print_spurious(count: u64) {
+        info!("      {:<16} {:>10}", "Spurious", count);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Min, average and max are derived from the recorded durations.
+    #[kernel_test]
+    fn durations_are_accounted() {
+        let stats = IRQStats::new();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.min(), None);
+        assert_eq!(stats.avg(), None);
+        assert_eq!(stats.max(), None);
+
+        for us in [30, 10, 20] {
+            stats.record(Duration::from_micros(us));
+        }
+
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.min(), Some(Duration::from_micros(10)));
+        assert_eq!(stats.avg(), Some(Duration::from_micros(20)));
+        assert_eq!(stats.max(), Some(Duration::from_micros(30)));
+
+        assert_eq!(stats.measure(|| 42), 42);
+        assert_eq!(stats.count(), 4);
+    }
+}
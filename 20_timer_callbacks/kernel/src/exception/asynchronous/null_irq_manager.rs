@@ -32,10 +32,21 @@ impl interface::IRQManager for NullIRQManager {
         panic!("No IRQ Manager registered yet");
     }
 
+    fn unregister_handler(
+        &self,
+        _descriptor: IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        panic!("No IRQ Manager registered yet");
+    }
+
     fn enable(&self, _irq_number: &Self::IRQNumberType) {
         panic!("No IRQ Manager registered yet");
     }
 
+    fn disable(&self, _irq_number: &Self::IRQNumberType) {
+        panic!("No IRQ Manager registered yet");
+    }
+
     fn handle_pending_irqs<'irq_context>(&'irq_context self, _ic: &IRQContext<'irq_context>) {
         panic!("No IRQ Manager registered yet");
     }
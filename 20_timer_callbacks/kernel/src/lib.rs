@@ -134,19 +134,22 @@
 extern crate alloc;
 
 mod panic_wait;
-mod synchronization;
 
 pub mod backtrace;
 pub mod bsp;
 pub mod common;
 pub mod console;
 pub mod cpu;
+pub mod device_tree;
 pub mod driver;
 pub mod exception;
 pub mod memory;
 pub mod print;
+pub mod process;
 pub mod state;
 pub mod symbols;
+pub mod synchronization;
+pub mod task;
 pub mod time;
 
 //--------------------------------------------------------------------------------------------------
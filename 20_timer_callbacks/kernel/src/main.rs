@@ -15,7 +15,7 @@
 
 extern crate alloc;
 
-use libkernel::{bsp, cpu, driver, exception, info, memory, state, time};
+use libkernel::{bsp, cpu, driver, exception, info, memory, state, task, time, warn};
 
 /// Early init code.
 ///
@@ -45,8 +45,19 @@ unsafe fn kernel_init() -> ! {
 
     bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
 
+    // Check the live kernel translation tables against the mapping policy.
+    if let Err(x) = memory::mmu::kernel_audit_mappings() {
+        panic!("Error auditing kernel mappings: {}", x);
+    }
+
+    // Initialize the task subsystem. This turns the current flow of execution into a thread.
+    if let Err(x) = task::init() {
+        panic!("Error initializing task subsystem: {}", x);
+    }
+
     // Unmask interrupts on the boot CPU core.
     exception::asynchronous::local_irq_unmask();
+    exception::asynchronous::local_fiq_unmask();
 
     // Announce conclusion of the kernel_init() phase.
     state::state_manager().transition_to_single_core_main();
@@ -66,6 +77,11 @@ fn kernel_main() -> ! {
     info!("MMU online:");
     memory::mmu::kernel_print_mappings();
 
+    info!("Live kernel translation tables:");
+    if let Err(x) = memory::mmu::kernel_print_page_tables() {
+        warn!("Page table dump: {}", x);
+    }
+
     let (_, privilege_level) = exception::current_privilege_level();
     info!("Current privilege level: {}", privilege_level);
 
@@ -86,10 +102,29 @@ fn kernel_main() -> ! {
     info!("Kernel heap:");
     memory::heap_alloc::kernel_heap_allocator().print_usage();
 
+    info!("Physical frames:");
+    memory::frame_alloc::kernel_frame_allocator().print_usage();
+
+    info!("Threads:");
+    task::print_threads();
+
+    // Secondary cores idle for now.
+    match cpu::smp::start_secondary_cores(cpu::wait_forever) {
+        Err(x) => warn!("Error starting secondary cores: {}", x),
+        Ok(()) => info!("Cores online: {}", cpu::smp::num_cores_online()),
+    }
+
     time::time_manager().set_timeout_once(Duration::from_secs(5), Box::new(|| info!("Once 5")));
     time::time_manager().set_timeout_once(Duration::from_secs(3), Box::new(|| info!("Once 2")));
     time::time_manager()
         .set_timeout_periodic(Duration::from_secs(1), Box::new(|| info!("Periodic 1 sec")));
+    time::time_manager().set_timeout_once(
+        Duration::from_secs(10),
+        Box::new(|| {
+            info!("IRQ statistics:");
+            exception::asynchronous::irq_manager().print_stats();
+        }),
+    );
 
     info!("Echoing input now");
     cpu::wait_forever();
@@ -4,10 +4,14 @@
 
 //! Memory Management.
 
+pub mod cache;
+pub mod dma;
+pub mod frame_alloc;
 pub mod heap_alloc;
+pub mod kaslr;
 pub mod mmu;
 
-use crate::{bsp, common};
+use crate::{bsp, common, cpu, exception, task};
 use core::{
     fmt,
     marker::PhantomData,
@@ -29,6 +33,11 @@ pub enum Physical {}
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Ord, Eq)]
 pub enum Virtual {}
 
+/// Zero-sized type to mark an address as seen by bus masters other than the CPU cores, e.g. DMA
+/// engines.
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Ord, Eq)]
+pub enum Bus {}
+
 /// Generic address type.
 #[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Ord, Eq)]
 pub struct Address<ATYPE: AddressType> {
@@ -42,6 +51,7 @@ pub struct Address<ATYPE: AddressType> {
 
 impl AddressType for Physical {}
 impl AddressType for Virtual {}
+impl AddressType for Bus {}
 
 impl<ATYPE: AddressType> Address<ATYPE> {
     /// Create an instance.
@@ -121,9 +131,13 @@ impl<ATYPE: AddressType> Sub<Address<ATYPE>> for Address<ATYPE> {
 }
 
 impl Address<Virtual> {
-    /// Checks if the address is part of the boot core stack region.
+    /// Checks if the address is part of the boot core stack region, the current thread's stack or
+    /// the executing secondary core's stack.
     pub fn is_valid_stack_addr(&self) -> bool {
         bsp::memory::mmu::virt_boot_core_stack_region().contains(*self)
+            || task::is_current_stack_addr(*self)
+            || cpu::smp::is_current_secondary_stack_addr(*self)
+            || exception::is_emergency_stack_addr(*self)
     }
 
     /// Checks if the address is part of the kernel code region.
@@ -161,10 +175,24 @@ impl fmt::Display for Address<Virtual> {
     }
 }
 
+impl fmt::Display for Address<Bus> {
+    // Bus addresses are 32 bit wide.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let q2: u16 = ((self.value >> 16) & 0xffff) as u16;
+        let q1: u16 = (self.value & 0xffff) as u16;
+
+        write!(f, "0x")?;
+        write!(f, "{:04x}_", q2)?;
+        write!(f, "{:04x}", q1)
+    }
+}
+
 /// Initialize the memory subsystem.
 pub fn init() {
     mmu::kernel_init_mmio_va_allocator();
+    mmu::kernel_init_vma_va_allocator();
     heap_alloc::kernel_init_heap_allocator();
+    frame_alloc::kernel_init_frame_allocator();
 }
 
 //--------------------------------------------------------------------------------------------------
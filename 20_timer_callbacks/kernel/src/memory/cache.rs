@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cache maintenance.
+//!
+//! Data cache maintenance operates up to the point where all observers, including bus masters that
+//! don't snoop the caches, see the same copy of memory. It is needed for buffers that are shared
+//! with such bus masters, or with cores that run with caching disabled.
+//!
+//! After code was written to memory, the instruction cache must be synchronized with the data cache
+//! before the code may be executed.
+//!
+//! All functions take an arbitrary range of virtual addresses. Maintenance happens on whole cache
+//! lines, so bytes before and after the range that share a line with it are affected as well.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/memory/cache.rs"]
+mod arch_cache;
+
+use crate::{
+    common,
+    memory::{Address, Virtual},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The start addresses of all lines that intersect the range.
+///
+/// The range may end at the very top of the address space.
+fn line_addrs(
+    start: Address<Virtual>,
+    size: usize,
+    line_size: usize,
+) -> impl Iterator<Item = Address<Virtual>> {
+    let lines = (size != 0).then(|| {
+        let first = common::align_down(start.as_usize(), line_size);
+        let last = common::align_down((start + (size - 1)).as_usize(), line_size);
+
+        (first..=last).step_by(line_size)
+    });
+
+    lines.into_iter().flatten().map(Address::new)
+}
+
+/// Whether the line at `line_addr` holds bytes outside of the range.
+fn line_is_partial(
+    line_addr: Address<Virtual>,
+    start: Address<Virtual>,
+    size: usize,
+    line_size: usize,
+) -> bool {
+    line_addr < start || (line_addr + (line_size - 1)) > (start + (size - 1))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Write dirty data cache lines of the range back to memory.
+///
+/// Use before a bus master reads a buffer that the CPU has written.
+pub fn clean_dcache_range(start: Address<Virtual>, size: usize) {
+    arch_cache::clean_dcache_range(start, size)
+}
+
+/// Discard the data cache lines of the range, without writing them back.
+///
+/// Use after a bus master has written to a buffer, before the CPU reads it. Lines that are only
+/// partially covered by the range are cleaned before they are discarded, so that data next to the
+/// range survives.
+///
+/// # Safety
+///
+/// - Pending CPU writes to the range are lost.
+pub unsafe fn invalidate_dcache_range(start: Address<Virtual>, size: usize) {
+    arch_cache::invalidate_dcache_range(start, size)
+}
+
+/// Write dirty data cache lines of the range back to memory and discard them.
+pub fn clean_invalidate_dcache_range(start: Address<Virtual>, size: usize) {
+    arch_cache::clean_invalidate_dcache_range(start, size)
+}
+
+/// Make instructions that were written to the range visible to instruction fetches of all cores.
+pub fn sync_icache_range(start: Address<Virtual>, size: usize) {
+    arch_cache::sync_icache_range(start, size)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use test_macros::kernel_test;
+
+    const LINE: usize = 64;
+
+    fn lines(start: usize, size: usize) -> Vec<usize> {
+        line_addrs(Address::new(start), size, LINE)
+            .map(Address::as_usize)
+            .collect()
+    }
+
+    /// Ranges are widened to whole lines.
+    #[kernel_test]
+    fn line_addrs_cover_range() {
+        assert_eq!(lines(0x1000, 0), []);
+        assert_eq!(lines(0x1010, 0), []);
+
+        assert_eq!(lines(0x1000, 1), [0x1000]);
+        assert_eq!(lines(0x1000, LINE), [0x1000]);
+        assert_eq!(lines(0x1000, LINE + 1), [0x1000, 0x1040]);
+        assert_eq!(lines(0x103f, 1), [0x1000]);
+        assert_eq!(lines(0x103f, 2), [0x1000, 0x1040]);
+        assert_eq!(lines(0x1010, 2 * LINE), [0x1000, 0x1040, 0x1080]);
+    }
+
+    /// Ranges that end at the top of the address space don't overflow.
+    #[kernel_test]
+    fn line_addrs_at_top_of_address_space() {
+        let last_line = usize::MAX - (LINE - 1);
+
+        assert_eq!(lines(last_line, LINE), [last_line]);
+        assert_eq!(lines(last_line - 1, 2), [last_line - LINE, last_line]);
+        assert_eq!(lines(usize::MAX, 1), [last_line]);
+    }
+
+    /// Only lines that stick out of the range are partial.
+    #[kernel_test]
+    fn partial_lines() {
+        let partial = |line: usize, start: usize, size: usize| {
+            line_is_partial(Address::new(line), Address::new(start), size, LINE)
+        };
+
+        assert!(!partial(0x1000, 0x1000, LINE));
+        assert!(!partial(0x1040, 0x1000, 3 * LINE));
+
+        assert!(partial(0x1000, 0x1001, LINE));
+        assert!(partial(0x1040, 0x1001, LINE));
+        assert!(partial(0x1000, 0x1000, LINE - 1));
+
+        let last_line = usize::MAX - (LINE - 1);
+        assert!(!partial(last_line, last_line, LINE));
+        assert!(partial(last_line, last_line + 1, LINE - 1));
+    }
+
+    /// Maintenance keeps the contents of cacheable memory, including data next to the range.
+    #[kernel_test]
+    fn maintenance_preserves_data() {
+        let mut buf = [0_u8; 4 * LINE];
+        for (i, x) in buf.iter_mut().enumerate() {
+            *x = i as u8;
+        }
+
+        let start = Address::new(buf.as_ptr() as usize + 3);
+        let size = 2 * LINE;
+
+        clean_dcache_range(start, size);
+        unsafe { invalidate_dcache_range(start, size) };
+        clean_invalidate_dcache_range(start, size);
+        sync_icache_range(start, size);
+
+        for (i, x) in buf.iter().enumerate() {
+            assert_eq!(*x, i as u8);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Memory for direct memory access by devices.
+//!
+//! A [`DmaBuffer`] is backed by physically contiguous frames, which are mapped non-cacheable. CPU
+//! accesses therefore go straight to memory, and no cache maintenance is needed when ownership of
+//! the buffer moves between the CPU and a bus master.
+
+use crate::{
+    bsp, common,
+    memory::{
+        cache,
+        mmu::{self, AccessPermissions, AttributeFields, MemAttributes, MemoryRegion},
+        Address, Bus, Virtual,
+    },
+    warn,
+};
+use core::{marker::PhantomData, mem::size_of, num::NonZeroUsize};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A typed buffer that is shared with bus masters.
+///
+/// The buffer is freed when the handle is dropped.
+pub struct DmaBuffer<T> {
+    virt_region: MemoryRegion<Virtual>,
+    bus_addr: Address<Bus>,
+    _data: PhantomData<T>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> DmaBuffer<T> {
+    /// Allocate a buffer and move `value` into it.
+    pub fn new(value: T) -> Result<Self, &'static str> {
+        let size = size_of::<T>();
+        if size == 0 {
+            return Err("Zero-sized DMA buffer");
+        }
+
+        let num_pages = common::align_up(size, bsp::memory::mmu::KernelGranule::SIZE)
+            >> bsp::memory::mmu::KernelGranule::SHIFT;
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::NonCacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+
+        let virt_region =
+            mmu::kernel_alloc_contiguous(NonZeroUsize::new(num_pages).unwrap(), &attr)?;
+
+        let bus_addr = match mmu::try_kernel_virt_addr_to_phys_addr(virt_region.start_addr())
+            .and_then(|phys_addr| bsp::memory::phys_to_bus_addr(phys_addr, virt_region.size()))
+        {
+            Ok(x) => x,
+            Err(x) => {
+                if let Err(y) = unsafe { mmu::kernel_free_contiguous(&virt_region) } {
+                    warn!("Failed to free DMA buffer: {}", y);
+                }
+                return Err(x);
+            }
+        };
+
+        // The frames might have been mapped cacheable by a previous owner. Lines that are still in
+        // the cache must not be evicted on top of what the device writes later.
+        cache::clean_invalidate_dcache_range(virt_region.start_addr(), virt_region.size());
+
+        let buffer = Self {
+            virt_region,
+            bus_addr,
+            _data: PhantomData,
+        };
+        unsafe { buffer.as_ptr().write_volatile(value) };
+
+        Ok(buffer)
+    }
+
+    /// The address through which the CPU accesses the buffer.
+    pub fn virt_addr(&self) -> Address<Virtual> {
+        self.virt_region.start_addr()
+    }
+
+    /// The address that must be programmed into devices.
+    pub fn bus_addr(&self) -> Address<Bus> {
+        self.bus_addr
+    }
+
+    /// Raw pointer to the contents.
+    ///
+    /// Devices may change the contents at any time, so they should only be accessed with volatile
+    /// operations.
+    pub fn as_ptr(&self) -> *mut T {
+        self.virt_addr().as_usize() as *mut T
+    }
+
+    /// Volatile read of the contents.
+    pub fn read(&self) -> T
+    where
+        T: Copy,
+    {
+        unsafe { self.as_ptr().read_volatile() }
+    }
+
+    /// Volatile write of the contents. The old contents are dropped.
+    pub fn write(&mut self, value: T) {
+        unsafe {
+            self.as_ptr().drop_in_place();
+            self.as_ptr().write_volatile(value);
+        }
+    }
+}
+
+impl<T> Drop for DmaBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.as_ptr().drop_in_place();
+
+            if let Err(x) = mmu::kernel_free_contiguous(&self.virt_region) {
+                warn!("Failed to free DMA buffer: {}", x);
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::frame_alloc;
+    use test_macros::kernel_test;
+
+    /// Buffers are mapped non-cacheable and carry the bus address of their frames.
+    #[kernel_test]
+    fn buffer_is_uncached_and_has_bus_addr() {
+        let buffer = DmaBuffer::new([0x5a_u8; 100]).unwrap();
+        let virt_page_addr = buffer.virt_region.start_page_addr();
+
+        let attr = mmu::try_kernel_page_attributes(virt_page_addr).unwrap();
+        assert_eq!(attr.mem_attributes, MemAttributes::NonCacheableDRAM);
+        assert!(!attr.is_executable());
+
+        let phys_addr = mmu::try_kernel_virt_addr_to_phys_addr(buffer.virt_addr()).unwrap();
+        assert_eq!(
+            bsp::memory::phys_to_bus_addr(phys_addr, buffer.virt_region.size()),
+            Ok(buffer.bus_addr())
+        );
+        assert_eq!(buffer.read(), [0x5a; 100]);
+    }
+
+    /// Contents written through the handle can be read back.
+    #[kernel_test]
+    fn write_then_read() {
+        let mut buffer = DmaBuffer::new(0_u32).unwrap();
+
+        buffer.write(0xdead_beef);
+        assert_eq!(buffer.read(), 0xdead_beef);
+        assert_eq!(
+            unsafe { (buffer.virt_addr().as_usize() as *const u32).read_volatile() },
+            0xdead_beef
+        );
+    }
+
+    /// Dropping a buffer returns its frames.
+    #[kernel_test]
+    fn drop_frees_frames() {
+        let used_before = frame_alloc::kernel_frame_allocator().usage().0;
+
+        let buffer = DmaBuffer::new(0_u64).unwrap();
+        assert_eq!(
+            frame_alloc::kernel_frame_allocator().usage().0,
+            used_before + 1
+        );
+
+        drop(buffer);
+        assert_eq!(frame_alloc::kernel_frame_allocator().usage().0, used_before);
+    }
+}
@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Physical page frame allocation.
+//!
+//! Manages the DRAM that is not occupied by the kernel binary, the boot core stack and the kernel
+//! heap. Frames have the size of a [`KernelGranule`](crate::bsp::memory::mmu::KernelGranule) page.
+
+use crate::{
+    bsp, common, info,
+    memory::{mmu::MemoryRegion, Physical},
+    synchronization,
+    synchronization::IRQSafeSpinLock,
+    warn,
+};
+use alloc::{vec, vec::Vec};
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// One bit per frame. A set bit marks an allocated frame.
+struct FrameBitmap {
+    pool: Option<MemoryRegion<Physical>>,
+    used: Vec<u64>,
+    num_used: usize,
+    num_reserved: usize,
+    reserved: Option<MemoryRegion<Physical>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A physical frame allocator that can be lazily initialized.
+pub struct FrameAllocator {
+    inner: IRQSafeSpinLock<FrameBitmap>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KERNEL_FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FrameBitmap {
+    const fn new() -> Self {
+        Self {
+            pool: None,
+            used: Vec::new(),
+            num_used: 0,
+            num_reserved: 0,
+            reserved: None,
+        }
+    }
+
+    fn init(&mut self, pool: MemoryRegion<Physical>) {
+        if self.pool.is_some() {
+            warn!("Already initialized");
+            return;
+        }
+
+        self.used = vec![0; pool.num_pages().div_ceil(64)];
+        self.pool = Some(pool);
+    }
+
+    /// Take frames out of the pool for good, e.g. because they belong to another agent.
+    fn reserve(&mut self, region: MemoryRegion<Physical>) {
+        let Some(pool) = self.pool else {
+            return;
+        };
+
+        // Clamp to the pool. Everything outside of it is not handed out anyways.
+        let start = region.start_addr().max(pool.start_addr());
+        let end_exclusive = region
+            .end_exclusive_page_addr()
+            .into_inner()
+            .min(pool.end_exclusive_page_addr().into_inner());
+        if start >= end_exclusive {
+            return;
+        }
+
+        let first = (start.as_usize() - pool.start_addr().as_usize())
+            >> bsp::memory::mmu::KernelGranule::SHIFT;
+        let num_frames =
+            (end_exclusive.as_usize() - start.as_usize()) >> bsp::memory::mmu::KernelGranule::SHIFT;
+
+        self.set_used(first..first + num_frames, true);
+        self.num_reserved += num_frames;
+        self.reserved = Some(region);
+    }
+
+    fn num_frames(&self) -> usize {
+        self.pool.map_or(0, |pool| pool.num_pages())
+    }
+
+    fn num_free(&self) -> usize {
+        self.num_frames() - self.num_reserved - self.num_used
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.used[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_used(&mut self, frames: core::ops::Range<usize>, used: bool) {
+        for frame in frames {
+            if used {
+                self.used[frame / 64] |= 1 << (frame % 64);
+            } else {
+                self.used[frame / 64] &= !(1 << (frame % 64));
+            }
+        }
+    }
+
+    fn alloc(
+        &mut self,
+        num_frames: NonZeroUsize,
+        align: usize,
+    ) -> Result<MemoryRegion<Physical>, &'static str> {
+        let pool = self.pool.ok_or("Frame allocator not initialized")?;
+        let num_frames: usize = num_frames.into();
+
+        if !align.is_power_of_two() {
+            return Err("Alignment is not a power of two");
+        }
+        let align = align.max(bsp::memory::mmu::KernelGranule::SIZE);
+
+        // Index of the first frame that satisfies the alignment.
+        let pool_start = pool.start_addr().as_usize();
+        let mut frame = (common::align_up(pool_start, align) - pool_start)
+            >> bsp::memory::mmu::KernelGranule::SHIFT;
+        let stride = align >> bsp::memory::mmu::KernelGranule::SHIFT;
+
+        while frame + num_frames <= self.num_frames() {
+            match (frame..frame + num_frames).rev().find(|&i| self.is_used(i)) {
+                None => {
+                    self.set_used(frame..frame + num_frames, true);
+                    self.num_used += num_frames;
+
+                    let start = pool
+                        .start_page_addr()
+                        .checked_offset(frame as isize)
+                        .unwrap();
+                    let end_exclusive = start.checked_offset(num_frames as isize).unwrap();
+
+                    return Ok(MemoryRegion::new(start, end_exclusive));
+                }
+                // Continue with the next aligned frame after the used one.
+                Some(used) => frame += common::align_up(used + 1 - frame, stride),
+            }
+        }
+
+        Err("Out of physical frames")
+    }
+
+    fn free(&mut self, region: &MemoryRegion<Physical>) -> Result<(), &'static str> {
+        let pool = self.pool.ok_or("Frame allocator not initialized")?;
+
+        if region.num_pages() == 0
+            || !pool.contains(region.start_addr())
+            || !pool.contains(region.end_inclusive_page_addr().into_inner())
+        {
+            return Err("Region is not part of the frame pool");
+        }
+
+        if self.reserved.map_or(false, |reserved| {
+            reserved.overlaps(region) || region.overlaps(&reserved)
+        }) {
+            return Err("Region is reserved");
+        }
+
+        let offset = region.start_addr().as_usize() - pool.start_addr().as_usize();
+        let first = offset >> bsp::memory::mmu::KernelGranule::SHIFT;
+        let frames = first..first + region.num_pages();
+
+        if frames.clone().any(|i| !self.is_used(i)) {
+            return Err("Frame is not allocated");
+        }
+
+        self.set_used(frames, false);
+        self.num_used -= region.num_pages();
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+/// Return a reference to the kernel's physical frame allocator.
+pub fn kernel_frame_allocator() -> &'static FrameAllocator {
+    &KERNEL_FRAME_ALLOCATOR
+}
+
+impl FrameAllocator {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(FrameBitmap::new()),
+        }
+    }
+
+    /// Allocate `num_frames` physically contiguous frames.
+    ///
+    /// The start address is aligned to `align` bytes, which must be a power of two. Alignments
+    /// smaller than a frame are rounded up.
+    pub fn alloc_frames(
+        &self,
+        num_frames: NonZeroUsize,
+        align: usize,
+    ) -> Result<MemoryRegion<Physical>, &'static str> {
+        self.inner.lock(|inner| inner.alloc(num_frames, align))
+    }
+
+    /// Return frames to the allocator.
+    ///
+    /// # Safety
+    ///
+    /// - The frames must not be in use anymore, e.g. through a live mapping.
+    pub unsafe fn free_frames(&self, region: &MemoryRegion<Physical>) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.free(region))
+    }
+
+    /// The number of used and free frames.
+    pub fn usage(&self) -> (usize, usize) {
+        self.inner.lock(|inner| (inner.num_used, inner.num_free()))
+    }
+
+    /// Print the current frame usage.
+    pub fn print_usage(&self) {
+        let (used, free) = self.usage();
+
+        for (name, frames) in [("Used", used), ("Free", free)] {
+            let size = frames * bsp::memory::mmu::KernelGranule::SIZE;
+            let (size_h, size_unit) = common::size_human_readable_ceil(size);
+
+            info!(
+                "      {}: {} Frames ({} {})",
+                name, frames, size_h, size_unit
+            );
+        }
+    }
+}
+
+/// Query the BSP for the usable DRAM and initialize the kernel's frame allocator with it.
+pub fn kernel_init_frame_allocator() {
+    static INIT_DONE: AtomicBool = AtomicBool::new(false);
+    if INIT_DONE.load(Ordering::Relaxed) {
+        warn!("Already initialized");
+        return;
+    }
+
+    let region = bsp::memory::mmu::phys_frame_pool_region();
+    let reserved = bsp::memory::mmu::phys_frame_pool_reserved_region();
+
+    KERNEL_FRAME_ALLOCATOR.inner.lock(|inner| {
+        inner.init(region);
+
+        if let Some(reserved) = reserved {
+            inner.reserve(reserved);
+        }
+    });
+
+    INIT_DONE.store(true, Ordering::Relaxed);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn frames(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    /// Allocations honor the alignment and do not overlap.
+    #[kernel_test]
+    fn frame_alloc_alignment_and_overlap() {
+        let allocator = kernel_frame_allocator();
+        let (used_before, _) = allocator.usage();
+
+        let a = allocator.alloc_frames(frames(1), 0).unwrap_err();
+        assert_eq!(a, "Alignment is not a power of two");
+
+        let a = allocator.alloc_frames(frames(1), 1).unwrap();
+        let b = allocator.alloc_frames(frames(3), 1024 * 1024).unwrap();
+
+        assert_eq!(b.num_pages(), 3);
+        assert_eq!(b.start_addr().as_usize() % (1024 * 1024), 0);
+        assert!(!a.overlaps(&b) && !b.overlaps(&a));
+        assert_eq!(allocator.usage().0, used_before + 4);
+
+        unsafe {
+            assert_eq!(allocator.free_frames(&a), Ok(()));
+            assert_eq!(allocator.free_frames(&a), Err("Frame is not allocated"));
+            assert_eq!(allocator.free_frames(&b), Ok(()));
+        }
+        assert_eq!(allocator.usage().0, used_before);
+    }
+
+    /// Freed frames are handed out again.
+    #[kernel_test]
+    fn frame_alloc_reuses_freed_frames() {
+        let allocator = kernel_frame_allocator();
+
+        let a = allocator.alloc_frames(frames(2), 1).unwrap();
+        unsafe { allocator.free_frames(&a).unwrap() };
+
+        let b = allocator.alloc_frames(frames(2), 1).unwrap();
+        assert_eq!(a.start_addr(), b.start_addr());
+        unsafe { allocator.free_frames(&b).unwrap() };
+    }
+}
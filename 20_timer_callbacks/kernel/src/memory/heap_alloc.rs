@@ -3,17 +3,73 @@
 // Copyright (c) 2022-2023 Andre Richter <andre.o.richter@gmail.com>
 
 //! Heap allocation.
+//!
+//! Small allocations are served from slab caches, one per power-of-two size class. A slab is a
+//! chunk of memory that is carved out of the linked-list heap and split into objects of the same
+//! size. Free objects are kept in a singly linked list, so allocating and freeing them is O(1).
+//! Allocations that are too big for the largest size class go to the linked-list heap directly.
+//!
+//! The linked-list heap grows on demand, see [`growable`]. If an allocation can not be served even
+//! after growing, an optional low-memory callback gets a chance to release memory before the
+//! allocation is retried once.
+//!
+//! With the `heap_tracking` feature, live allocations are recorded for leak hunting. See
+//! [`dump_live_allocations`].
+
+mod growable;
+mod tracking;
 
 use crate::{
     backtrace, bsp, common, debug, info,
-    memory::{Address, Virtual},
+    memory::{mmu, Address, Virtual},
     synchronization,
-    synchronization::IRQSafeNullLock,
+    synchronization::IRQSafeSpinLock,
     warn,
 };
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::sync::atomic::{AtomicBool, Ordering};
-use linked_list_allocator::Heap as LinkedListHeap;
+use core::{
+    num::NonZeroUsize,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use growable::GrowableHeap;
+
+pub use growable::GrowthStats;
+pub use tracking::{
+    dump_live_allocations, dump_live_allocations_since, heap_tracking_mark, HeapTrackingMark,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The size of the virtual address window that the heap can grow into.
+const GROWTH_WINDOW_SIZE: usize = 256 * 1024 * 1024;
+
+/// The object sizes of the slab caches.
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// The size of a slab. Slabs are aligned to their size, so every object is aligned to its size.
+const SLAB_SIZE: usize = 16 * 1024;
+
+/// A free object. The link to the next free object is stored in the object itself.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// A cache of equally sized objects.
+struct SlabCache {
+    object_size: usize,
+    free_list: Option<NonNull<FreeObject>>,
+    num_slabs: usize,
+    num_used: usize,
+}
+
+struct HeapAllocatorInner {
+    heap: GrowableHeap,
+    caches: [SlabCache; SIZE_CLASSES.len()],
+    low_memory_callback: Option<fn(Layout)>,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -21,7 +77,23 @@ use linked_list_allocator::Heap as LinkedListHeap;
 
 /// A heap allocator that can be lazyily initialized.
 pub struct HeapAllocator {
-    inner: IRQSafeNullLock<LinkedListHeap>,
+    inner: IRQSafeSpinLock<HeapAllocatorInner>,
+}
+
+/// Usage statistics of a slab cache.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SlabCacheStats {
+    /// The size of the cache's objects.
+    pub object_size: usize,
+
+    /// The number of slabs that were carved out of the heap.
+    pub num_slabs: usize,
+
+    /// The number of allocated objects.
+    pub num_used: usize,
+
+    /// The number of free objects.
+    pub num_free: usize,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -35,6 +107,121 @@ static KERNEL_HEAP_ALLOCATOR: HeapAllocator = HeapAllocator::new();
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+// The free lists only point into the heap, which is owned by the allocator.
+unsafe impl Send for HeapAllocatorInner {}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            free_list: None,
+            num_slabs: 0,
+            num_used: 0,
+        }
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        SLAB_SIZE / self.object_size
+    }
+
+    fn stats(&self) -> SlabCacheStats {
+        SlabCacheStats {
+            object_size: self.object_size,
+            num_slabs: self.num_slabs,
+            num_used: self.num_used,
+            num_free: self.num_slabs * self.objects_per_slab() - self.num_used,
+        }
+    }
+
+    /// Carve a new slab out of the heap and put all of its objects on the free list.
+    fn grow(&mut self, heap: &mut GrowableHeap) -> Option<()> {
+        let layout = Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap();
+        let slab = heap.alloc(layout)?.as_ptr();
+
+        for i in (0..self.objects_per_slab()).rev() {
+            let object = unsafe { slab.add(i * self.object_size) } as *mut FreeObject;
+
+            unsafe {
+                object.write(FreeObject {
+                    next: self.free_list,
+                })
+            };
+            self.free_list = NonNull::new(object);
+        }
+        self.num_slabs += 1;
+
+        Some(())
+    }
+
+    fn alloc(&mut self, heap: &mut GrowableHeap) -> Option<NonNull<u8>> {
+        if self.free_list.is_none() {
+            self.grow(heap)?;
+        }
+
+        let object = self.free_list?;
+        self.free_list = unsafe { object.as_ref().next };
+        self.num_used += 1;
+
+        Some(object.cast())
+    }
+
+    /// # Safety
+    ///
+    /// - The object must have been allocated from this cache.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>) {
+        let object = ptr.cast::<FreeObject>();
+
+        object.as_ptr().write(FreeObject {
+            next: self.free_list,
+        });
+        self.free_list = Some(object);
+        self.num_used -= 1;
+    }
+}
+
+impl HeapAllocatorInner {
+    const fn new() -> Self {
+        Self {
+            heap: GrowableHeap::new(),
+            caches: [
+                SlabCache::new(SIZE_CLASSES[0]),
+                SlabCache::new(SIZE_CLASSES[1]),
+                SlabCache::new(SIZE_CLASSES[2]),
+                SlabCache::new(SIZE_CLASSES[3]),
+                SlabCache::new(SIZE_CLASSES[4]),
+                SlabCache::new(SIZE_CLASSES[5]),
+                SlabCache::new(SIZE_CLASSES[6]),
+                SlabCache::new(SIZE_CLASSES[7]),
+            ],
+            low_memory_callback: None,
+        }
+    }
+
+    /// The index of the slab cache that serves the layout, if any.
+    fn cache_index(layout: &Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+
+        SIZE_CLASSES.iter().position(|&class| size <= class)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        match Self::cache_index(&layout) {
+            Some(i) => self.caches[i].alloc(&mut self.heap),
+            None => self.heap.alloc(layout),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// - The pointer must have been allocated with the same layout.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match Self::cache_index(&layout) {
+            Some(i) => self.caches[i].dealloc(ptr),
+            None => self.heap.dealloc(ptr, layout),
+        }
+    }
+}
+
 #[inline(always)]
 fn debug_print_alloc_dealloc(operation: &'static str, ptr: *mut u8, layout: Layout) {
     let size = layout.size();
@@ -76,15 +263,40 @@ impl HeapAllocator {
     /// Create an instance.
     pub const fn new() -> Self {
         Self {
-            inner: IRQSafeNullLock::new(LinkedListHeap::empty()),
+            inner: IRQSafeSpinLock::new(HeapAllocatorInner::new()),
         }
     }
 
+    /// Usage statistics of the slab caches, ordered by object size.
+    pub fn slab_cache_stats(&self) -> [SlabCacheStats; SIZE_CLASSES.len()] {
+        self.inner
+            .lock(|inner| core::array::from_fn(|i| inner.caches[i].stats()))
+    }
+
+    /// Usage of the window that the heap grows into.
+    pub fn growth_stats(&self) -> GrowthStats {
+        self.inner.lock(|inner| inner.heap.growth_stats())
+    }
+
+    /// Set the maximum number of bytes that the heap may grow by.
+    pub fn set_growth_limit(&self, limit: usize) {
+        self.inner.lock(|inner| inner.heap.set_growth_limit(limit))
+    }
+
+    /// Set a function that is called when an allocation can not be served.
+    ///
+    /// The callback may release memory, e.g. by dropping caches. The allocation is retried once
+    /// after it returned. The callback must not allocate itself.
+    pub fn set_low_memory_callback(&self, callback: fn(Layout)) {
+        self.inner
+            .lock(|inner| inner.low_memory_callback = Some(callback))
+    }
+
     /// Print the current heap usage.
+    ///
+    /// Memory of slabs counts as used, regardless of how many of their objects are allocated.
     pub fn print_usage(&self) {
-        let (used, free) = KERNEL_HEAP_ALLOCATOR
-            .inner
-            .lock(|inner| (inner.used(), inner.free()));
+        let (used, free) = self.inner.lock(|inner| inner.heap.usage());
 
         if used >= 1024 {
             let (used_h, used_unit) = common::size_human_readable_ceil(used);
@@ -99,14 +311,34 @@ impl HeapAllocator {
         } else {
             info!("      Free: {} Byte", free);
         }
+
+        let growth = self.growth_stats();
+        let (mapped_h, mapped_unit) = common::size_human_readable_ceil(growth.mapped);
+        let (window_h, window_unit) = common::size_human_readable_ceil(growth.window_size);
+        info!(
+            "      Grown: {} {} of {} {}",
+            mapped_h, mapped_unit, window_h, window_unit
+        );
+
+        for stats in self.slab_cache_stats() {
+            info!(
+                "      Slab {:>4} Byte: {} Slabs, {} Used, {} Free",
+                stats.object_size, stats.num_slabs, stats.num_used, stats.num_free
+            );
+        }
     }
 }
 
 unsafe impl GlobalAlloc for HeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let result = KERNEL_HEAP_ALLOCATOR
-            .inner
-            .lock(|inner| inner.allocate_first_fit(layout).ok());
+        let mut result = self.inner.lock(|inner| inner.alloc(layout));
+
+        if result.is_none() {
+            if let Some(callback) = self.inner.lock(|inner| inner.low_memory_callback) {
+                callback(layout);
+                result = self.inner.lock(|inner| inner.alloc(layout));
+            }
+        }
 
         match result {
             None => core::ptr::null_mut(),
@@ -114,6 +346,7 @@ unsafe impl GlobalAlloc for HeapAllocator {
                 let ptr = allocation.as_ptr();
 
                 debug_print_alloc_dealloc("Allocation", ptr, layout);
+                tracking::record_alloc(ptr, layout.size());
 
                 ptr
             }
@@ -121,15 +354,19 @@ unsafe impl GlobalAlloc for HeapAllocator {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        KERNEL_HEAP_ALLOCATOR
-            .inner
-            .lock(|inner| inner.deallocate(core::ptr::NonNull::new_unchecked(ptr), layout));
-
+        // Forget the allocation while the address is still owned. Once freed, another core can get
+        // it back and record it anew.
+        tracking::record_dealloc(ptr);
         debug_print_alloc_dealloc("Free", ptr, layout);
+
+        self.inner
+            .lock(|inner| inner.dealloc(NonNull::new_unchecked(ptr), layout));
     }
 }
 
 /// Query the BSP for the heap region and initialize the kernel's heap allocator with it.
+///
+/// Also reserves the window that the heap grows into, so the VMA allocator must be initialized.
 pub fn kernel_init_heap_allocator() {
     static INIT_DONE: AtomicBool = AtomicBool::new(false);
     if INIT_DONE.load(Ordering::Relaxed) {
@@ -139,9 +376,82 @@ pub fn kernel_init_heap_allocator() {
 
     let region = bsp::memory::mmu::virt_heap_region();
 
-    KERNEL_HEAP_ALLOCATOR.inner.lock(|inner| unsafe {
-        inner.init(region.start_addr().as_usize() as *mut u8, region.size())
+    // Reserve the window outside of the heap lock, because the VMA allocator uses the heap.
+    let window_pages =
+        NonZeroUsize::new(GROWTH_WINDOW_SIZE / bsp::memory::mmu::KernelGranule::SIZE).unwrap();
+    let window = mmu::kernel_reserve_va(window_pages);
+
+    KERNEL_HEAP_ALLOCATOR.inner.lock(|inner| {
+        unsafe { inner.heap.init(region) };
+
+        match window {
+            Ok(window) => inner.heap.init_growth_window(window),
+            Err(x) => warn!("Heap can not grow: {}", x),
+        }
     });
 
     INIT_DONE.store(true, Ordering::Relaxed);
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Layouts are sorted into the smallest fitting size class.
+    #[kernel_test]
+    fn size_class_selection() {
+        let index = |size, align| {
+            HeapAllocatorInner::cache_index(&Layout::from_size_align(size, align).unwrap())
+        };
+
+        assert_eq!(index(1, 1), Some(0));
+        assert_eq!(index(17, 8), Some(1));
+        assert_eq!(index(8, 256), Some(4));
+        assert_eq!(index(2048, 8), Some(7));
+        assert_eq!(index(2049, 8), None);
+    }
+
+    /// Allocations that do not fit into the initial region are served from the growth window.
+    #[kernel_test]
+    fn heap_grows_on_demand() {
+        let allocator = kernel_heap_allocator();
+        let layout =
+            Layout::from_size_align(bsp::memory::mmu::virt_heap_region().size(), 8).unwrap();
+        let mapped_before = allocator.growth_stats().mapped;
+
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(allocator.growth_stats().mapped > mapped_before);
+
+        unsafe {
+            ptr.write_volatile(1);
+            ptr.add(layout.size() - 1).write_volatile(2);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    /// Small objects come from a slab cache and are reused after being freed.
+    #[kernel_test]
+    fn slab_objects_are_reused() {
+        let allocator = kernel_heap_allocator();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let used_before = allocator.slab_cache_stats()[3].num_used;
+
+        let a = unsafe { allocator.alloc(layout) };
+        assert!(!a.is_null());
+        assert_eq!(a as usize % 128, 0);
+        assert_eq!(allocator.slab_cache_stats()[3].num_used, used_before + 1);
+
+        unsafe { allocator.dealloc(a, layout) };
+        assert_eq!(allocator.slab_cache_stats()[3].num_used, used_before);
+
+        let b = unsafe { allocator.alloc(layout) };
+        assert_eq!(a, b);
+        unsafe { allocator.dealloc(b, layout) };
+    }
+}
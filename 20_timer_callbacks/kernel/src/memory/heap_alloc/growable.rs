@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! The backing memory of the kernel heap.
+//!
+//! The heap starts out with the region that the linker script reserves. Once that region is
+//! exhausted, the heap grows into a large window of kernel virtual address space. Physical frames
+//! are mapped into the window on demand, up to a configurable limit.
+
+use crate::{
+    bsp, common,
+    memory::{
+        mmu::{self, AccessPermissions, AttributeFields, MemAttributes, MemoryRegion},
+        Virtual,
+    },
+};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+use linked_list_allocator::Heap as LinkedListHeap;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The minimum number of bytes the heap grows by.
+const GROWTH_STEP: usize = 256 * 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A linked-list heap that can grow into a reserved window of virtual addresses.
+pub struct GrowableHeap {
+    initial: LinkedListHeap,
+    window: Option<MemoryRegion<Virtual>>,
+    growth: LinkedListHeap,
+
+    /// The number of bytes that are mapped at the start of the window.
+    mapped: usize,
+
+    /// The maximum for `mapped`.
+    limit: usize,
+}
+
+/// Usage of the growth window.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct GrowthStats {
+    /// Bytes that are mapped into the window.
+    pub mapped: usize,
+
+    /// The configured hard cap for `mapped`.
+    pub limit: usize,
+
+    /// The size of the window.
+    pub window_size: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl GrowableHeap {
+    /// Map enough additional memory into the window so that `layout` fits.
+    fn grow(&mut self, layout: &Layout) -> Result<(), &'static str> {
+        let window = self.window.ok_or("Heap growth not initialized")?;
+
+        // The new memory might not be usable together with the current free tail, so ask for
+        // enough to satisfy the request on its own.
+        let needed = layout
+            .size()
+            .checked_add(layout.align())
+            .ok_or("Allocation too large")?
+            .max(GROWTH_STEP);
+        let size = common::align_up(needed, bsp::memory::mmu::KernelGranule::SIZE);
+
+        if self.mapped + size > self.limit.min(window.size()) {
+            return Err("Heap growth limit reached");
+        }
+
+        let start_page_addr = window
+            .start_page_addr()
+            .checked_offset((self.mapped / bsp::memory::mmu::KernelGranule::SIZE) as isize)
+            .unwrap();
+        let end_exclusive_page_addr = start_page_addr
+            .checked_offset((size / bsp::memory::mmu::KernelGranule::SIZE) as isize)
+            .unwrap();
+        let virt_region = MemoryRegion::new(start_page_addr, end_exclusive_page_addr);
+
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        // The window is contiguous, so the frames behind it need not be.
+        unsafe { mmu::kernel_map_fresh_pages(&virt_region, &attr)? };
+
+        unsafe {
+            if self.mapped == 0 {
+                self.growth
+                    .init(virt_region.start_addr().as_usize() as *mut u8, size);
+            } else {
+                self.growth.extend(size);
+            }
+        }
+        self.mapped += size;
+
+        Ok(())
+    }
+
+    fn is_growth_addr(&self, ptr: NonNull<u8>) -> bool {
+        let addr = ptr.as_ptr() as usize;
+
+        self.window.map_or(false, |window| {
+            (window.start_addr().as_usize()..window.start_addr().as_usize() + self.mapped)
+                .contains(&addr)
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl GrowableHeap {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            initial: LinkedListHeap::empty(),
+            window: None,
+            growth: LinkedListHeap::empty(),
+            mapped: 0,
+            limit: usize::MAX,
+        }
+    }
+
+    /// Hand the initial heap region to the allocator.
+    ///
+    /// # Safety
+    ///
+    /// - The region must be mapped and must not be used for anything else.
+    pub unsafe fn init(&mut self, region: MemoryRegion<Virtual>) {
+        self.initial
+            .init(region.start_addr().as_usize() as *mut u8, region.size());
+    }
+
+    /// Set the window of reserved, unmapped virtual addresses that the heap grows into.
+    pub fn init_growth_window(&mut self, window: MemoryRegion<Virtual>) {
+        self.window = Some(window);
+    }
+
+    /// Set the maximum number of bytes that may be mapped into the growth window.
+    ///
+    /// Memory that is already mapped is kept, even if it exceeds the new limit.
+    pub fn set_growth_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Usage of the growth window.
+    pub fn growth_stats(&self) -> GrowthStats {
+        GrowthStats {
+            mapped: self.mapped,
+            limit: self.limit,
+            window_size: self.window.map_or(0, |window| window.size()),
+        }
+    }
+
+    /// The number of used and free bytes, summed over the initial region and the growth window.
+    pub fn usage(&self) -> (usize, usize) {
+        (
+            self.initial.used() + self.growth.used(),
+            self.initial.free() + self.growth.free(),
+        )
+    }
+
+    /// Allocate, growing the heap if needed.
+    pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        if let Ok(ptr) = self.initial.allocate_first_fit(layout) {
+            return Some(ptr);
+        }
+
+        if self.mapped > 0 {
+            if let Ok(ptr) = self.growth.allocate_first_fit(layout) {
+                return Some(ptr);
+            }
+        }
+
+        self.grow(&layout).ok()?;
+        self.growth.allocate_first_fit(layout).ok()
+    }
+
+    /// Return memory to the heap it was allocated from.
+    ///
+    /// # Safety
+    ///
+    /// - The pointer must have been allocated with the same layout.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_growth_addr(ptr) {
+            self.growth.deallocate(ptr, layout);
+        } else {
+            self.initial.deallocate(ptr, layout);
+        }
+    }
+}
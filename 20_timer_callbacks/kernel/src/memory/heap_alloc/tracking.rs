@@ -0,0 +1,335 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Tracking of live heap allocations.
+//!
+//! Only active with the `heap_tracking` feature. Each live allocation is recorded together with
+//! its size, a timestamp and a short backtrace. The records are kept in a static table, because the
+//! tracker must not allocate from the heap it is tracking.
+//!
+//! Dumping copies the records out of the table first, so that grouping and printing them does not
+//! happen with the tracker locked and IRQs masked.
+
+use crate::{
+    backtrace, info,
+    memory::{Address, Virtual},
+    symbols,
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
+    time,
+};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The maximum number of live allocations that can be tracked.
+const MAX_TRACKED: usize = if cfg!(feature = "heap_tracking") {
+    1024
+} else {
+    0
+};
+
+/// The number of return addresses that are recorded per allocation.
+const TRACE_DEPTH: usize = 12;
+
+/// Symbols of functions that belong to the allocation machinery itself. The first frame outside of
+/// them is the call site of an allocation.
+const ALLOCATOR_SYMBOL_PREFIXES: [&str; 7] = [
+    "alloc::",
+    "<alloc::",
+    "__rust_",
+    "__rg_",
+    "libkernel::backtrace::",
+    "libkernel::memory::heap_alloc::",
+    "<libkernel::memory::heap_alloc::",
+];
+
+#[derive(Copy, Clone)]
+struct LiveAllocation {
+    addr: usize,
+    size: usize,
+    seq: u64,
+    timestamp: Duration,
+    trace: [Address<Virtual>; TRACE_DEPTH],
+}
+
+struct Tracker<const N: usize> {
+    entries: [Option<LiveAllocation>; N],
+    next_seq: u64,
+
+    /// Allocations that could not be recorded because the table was full.
+    num_untracked: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A point in the sequence of allocations. Allocations made after it can be dumped separately.
+#[derive(Copy, Clone, Debug)]
+pub struct HeapTrackingMark(u64);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static TRACKER: IRQSafeSpinLock<Tracker<MAX_TRACKED>> = IRQSafeSpinLock::new(Tracker::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl LiveAllocation {
+    /// The first return address outside of the allocator.
+    fn call_site(&self) -> Option<Address<Virtual>> {
+        self.trace
+            .iter()
+            .take_while(|addr| addr.as_usize() != 0)
+            .copied()
+            .find(|&addr| match symbols::lookup_symbol(addr) {
+                None => true,
+                Some(sym) => !ALLOCATOR_SYMBOL_PREFIXES
+                    .iter()
+                    .any(|prefix| sym.name().starts_with(prefix)),
+            })
+    }
+}
+
+impl<const N: usize> Tracker<N> {
+    const fn new() -> Self {
+        Self {
+            entries: [None; N],
+            next_seq: 0,
+            num_untracked: 0,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        addr: usize,
+        size: usize,
+        timestamp: Duration,
+    ) -> Option<&mut LiveAllocation> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            None => {
+                self.num_untracked += 1;
+                None
+            }
+            Some(slot) => Some(slot.insert(LiveAllocation {
+                addr,
+                size,
+                seq,
+                timestamp,
+                trace: [Address::new(0); TRACE_DEPTH],
+            })),
+        }
+    }
+
+    fn remove(&mut self, addr: usize) {
+        if let Some(slot) = self
+            .entries
+            .iter_mut()
+            .find(|entry| matches!(entry, Some(e) if e.addr == addr))
+        {
+            *slot = None;
+        }
+    }
+
+    fn live_since(&self, mark: HeapTrackingMark) -> impl Iterator<Item = &LiveAllocation> {
+        self.entries
+            .iter()
+            .flatten()
+            .filter(move |entry| entry.seq >= mark.0)
+    }
+
+    /// Copy the allocations made after `mark` into `out`, as far as its capacity allows.
+    fn copy_live_since(&self, mark: HeapTrackingMark, out: &mut Vec<LiveAllocation>) {
+        let out_addr = out.as_ptr() as usize;
+
+        for entry in self.live_since(mark) {
+            if out.len() == out.capacity() {
+                break;
+            }
+
+            // The buffer itself is not interesting.
+            if entry.addr != out_addr {
+                out.push(*entry);
+            }
+        }
+    }
+}
+
+/// Print one line per call site and return the number of live allocations.
+fn print_grouped(live: &[LiveAllocation], num_untracked: usize) -> usize {
+    let num_bytes: usize = live.iter().map(|entry| entry.size).sum();
+
+    info!(
+        "Live heap allocations: {} ({} Byte), untracked: {}",
+        live.len(),
+        num_bytes,
+        num_untracked
+    );
+    if live.is_empty() {
+        return 0;
+    }
+
+    // Resolve each call site once, then sort so that equal call sites are adjacent.
+    let mut sites: Vec<(Option<Address<Virtual>>, usize, Duration)> = live
+        .iter()
+        .map(|entry| (entry.call_site(), entry.size, entry.timestamp))
+        .collect();
+    sites.sort_unstable_by_key(|(call_site, _, _)| *call_site);
+
+    info!("      -------------------------------------------------------------------------");
+    info!("          Count       Bytes      Oldest   Call site");
+    info!("      -------------------------------------------------------------------------");
+
+    let mut rest = &sites[..];
+    while let Some(&(call_site, _, _)) = rest.first() {
+        let len = rest
+            .iter()
+            .position(|(other, _, _)| *other != call_site)
+            .unwrap_or(rest.len());
+        let (group, tail) = rest.split_at(len);
+        rest = tail;
+
+        let bytes: usize = group.iter().map(|(_, size, _)| size).sum();
+        let oldest = group.iter().map(|(_, _, t)| *t).min().unwrap();
+
+        let name = call_site
+            .and_then(symbols::lookup_symbol)
+            .map_or("Symbol not found", |sym| sym.name());
+
+        info!(
+            "      {:>9} {:>11} {:>4}.{:06}   {:016x} | {}",
+            group.len(),
+            bytes,
+            oldest.as_secs(),
+            oldest.subsec_micros(),
+            call_site.map_or(0, |addr| addr.as_usize()),
+            name
+        );
+    }
+    info!("      -------------------------------------------------------------------------");
+
+    live.len()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Record a new allocation.
+pub fn record_alloc(ptr: *mut u8, size: usize) {
+    if !cfg!(feature = "heap_tracking") {
+        return;
+    }
+
+    let timestamp = time::time_manager().uptime();
+    let mut trace = [Address::new(0); TRACE_DEPTH];
+    backtrace::capture(&mut trace);
+
+    TRACKER.lock(|tracker| {
+        if let Some(entry) = tracker.insert(ptr as usize, size, timestamp) {
+            entry.trace = trace;
+        }
+    });
+}
+
+/// Forget a freed allocation.
+pub fn record_dealloc(ptr: *mut u8) {
+    if !cfg!(feature = "heap_tracking") {
+        return;
+    }
+
+    TRACKER.lock(|tracker| tracker.remove(ptr as usize));
+}
+
+/// Mark the current point in the sequence of allocations.
+pub fn heap_tracking_mark() -> HeapTrackingMark {
+    TRACKER.lock(|tracker| HeapTrackingMark(tracker.next_seq))
+}
+
+/// Print the live allocations grouped by call site, and return their number.
+///
+/// Returns `None` if the kernel was built without the `heap_tracking` feature.
+pub fn dump_live_allocations() -> Option<usize> {
+    dump_live_allocations_since(HeapTrackingMark(0))
+}
+
+/// Like [`dump_live_allocations`], but only considers allocations made after `mark`.
+pub fn dump_live_allocations_since(mark: HeapTrackingMark) -> Option<usize> {
+    if !cfg!(feature = "heap_tracking") {
+        info!("Heap tracking is disabled");
+        return None;
+    }
+
+    // Allocate the buffer before taking the lock, because allocating locks the tracker as well.
+    let mut live = Vec::with_capacity(MAX_TRACKED);
+    let num_untracked = TRACKER.lock(|tracker| {
+        tracker.copy_live_since(mark, &mut live);
+        tracker.num_untracked
+    });
+
+    Some(print_grouped(&live, num_untracked))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use test_macros::kernel_test;
+
+    /// Only allocations after the mark are considered, and freed ones are forgotten.
+    #[kernel_test]
+    fn tracker_insert_remove_since() {
+        let mut tracker: Tracker<2> = Tracker::new();
+        let t = Duration::ZERO;
+
+        assert!(tracker.insert(0x1000, 16, t).is_some());
+        let mark = HeapTrackingMark(tracker.next_seq);
+        assert!(tracker.insert(0x2000, 32, t).is_some());
+        assert!(tracker.insert(0x3000, 64, t).is_none());
+        assert_eq!(tracker.num_untracked, 1);
+
+        assert_eq!(tracker.live_since(HeapTrackingMark(0)).count(), 2);
+        assert_eq!(tracker.live_since(mark).count(), 1);
+
+        tracker.remove(0x2000);
+        assert_eq!(tracker.live_since(mark).count(), 0);
+        assert!(tracker.insert(0x4000, 8, t).is_some());
+    }
+
+    /// A scenario that frees what it allocates leaves no live allocations behind.
+    #[kernel_test]
+    fn scenario_leaves_no_live_allocations() {
+        let mark = heap_tracking_mark();
+
+        let kept = {
+            let boxes: Vec<Box<u64>> = (0..8).map(Box::new).collect();
+            let kept = Box::new(boxes.iter().map(|b| **b).sum::<u64>());
+            drop(boxes);
+
+            kept
+        };
+
+        if !cfg!(feature = "heap_tracking") {
+            assert_eq!(dump_live_allocations_since(mark), None);
+            return;
+        }
+
+        assert_eq!(dump_live_allocations_since(mark), Some(1));
+        drop(kept);
+        assert_eq!(dump_live_allocations_since(mark), Some(0));
+    }
+}
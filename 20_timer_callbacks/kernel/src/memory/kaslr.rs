@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel address space layout randomization.
+//!
+//! The kernel is linked as a position independent executable at the start of its virtual address
+//! space. Before the MMU is turned on, the boot core picks a random, granule-aligned offset,
+//! applies the binary's dynamic relocations and slides the precomputed translation tables. From
+//! then on, the kernel executes [`offset()`] bytes above its link address.
+//!
+//! If anything goes wrong on the way, the offset stays zero. The linker applies the relocations
+//! for this case already, so the binary is valid as-is.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/memory/kaslr.rs"]
+mod arch_kaslr;
+
+use crate::{
+    bsp::{
+        self,
+        memory::mmu::{KernelGranule, KernelVirtAddrSpace},
+    },
+    memory::{Address, Physical},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The virtual address that the kernel binary is linked to.
+const LINK_VIRT_START: usize = usize::MAX - KernelVirtAddrSpace::SIZE + 1;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Written once by the boot core while the MMU is off. Only plain loads and stores are used,
+/// because atomic read-modify-write operations need the MMU.
+static KASLR_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Spread the bits of weak entropy sources, like the timer counter, over the whole word.
+///
+/// This is the finalizer of `splitmix64`.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    x ^ (x >> 31)
+}
+
+/// Turn entropy into a granule-aligned offset below [`bsp::memory::mmu::KASLR_MAX_OFFSET`].
+fn offset_from_entropy(entropy: u64) -> usize {
+    let num_slots = bsp::memory::mmu::KASLR_MAX_OFFSET >> KernelGranule::SHIFT;
+
+    ((mix(entropy) as usize) % num_slots) << KernelGranule::SHIFT
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The offset between the kernel's link addresses and the addresses it executes from.
+pub fn offset() -> usize {
+    KASLR_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Randomize the kernel's virtual base address.
+///
+/// Entropy is taken from the timer counter, the hardware RNG and the seed that a chainloader may
+/// provide.
+///
+/// # Safety
+///
+/// - Must only be called once by the boot core with the MMU turned off.
+/// - The addresses must be the physical ones of the respective linker symbols.
+/// - The code must neither panic nor use locks, and may access memory only through the given
+///   addresses or PC-relative.
+pub unsafe fn init(
+    phys_code_start: Address<Physical>,
+    phys_rela_start: Address<Physical>,
+    phys_rela_end_exclusive: Address<Physical>,
+    chainloader_seed: u64,
+) {
+    let entropy = arch_kaslr::timer_entropy()
+        ^ bsp::memory::early_hw_rng_entropy().unwrap_or(0)
+        ^ chainloader_seed;
+    let offset = offset_from_entropy(entropy);
+
+    if arch_kaslr::check_relocations(phys_rela_start, phys_rela_end_exclusive).is_err() {
+        return;
+    }
+
+    if bsp::memory::mmu::kernel_slide_precomputed_tables(offset).is_err() {
+        return;
+    }
+
+    arch_kaslr::apply_relocations(
+        phys_code_start,
+        phys_rela_start,
+        phys_rela_end_exclusive,
+        LINK_VIRT_START,
+        offset,
+    );
+    KASLR_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Offsets are granule-aligned and bounded.
+    #[kernel_test]
+    fn offset_is_aligned_and_bounded() {
+        for entropy in [0, 1, 0x8000_0000, u64::MAX, offset() as u64] {
+            let offset = offset_from_entropy(entropy);
+
+            assert!(crate::common::is_aligned(offset, KernelGranule::SIZE));
+            assert!(offset < bsp::memory::mmu::KASLR_MAX_OFFSET);
+        }
+
+        assert!(crate::common::is_aligned(offset(), KernelGranule::SIZE));
+        assert!(offset() < bsp::memory::mmu::KASLR_MAX_OFFSET);
+    }
+
+    /// The kernel binary runs at its link address plus the offset.
+    #[kernel_test]
+    fn code_starts_at_offset() {
+        let code_start = bsp::memory::mmu::virt_code_region().start_addr().as_usize();
+
+        assert_eq!(code_start, LINK_VIRT_START + offset());
+    }
+}
@@ -8,15 +8,20 @@
 #[path = "../_arch/aarch64/memory/mmu.rs"]
 mod arch_mmu;
 
+pub mod address_space;
+
 mod mapping_record;
 mod page_alloc;
+mod ptdump;
 mod translation_table;
 mod types;
+mod vma;
 
 use crate::{
     bsp,
     memory::{Address, Physical, Virtual},
     synchronization::{self, interface::Mutex},
+    warn,
 };
 use core::{fmt, num::NonZeroUsize};
 
@@ -52,6 +57,36 @@ pub mod interface {
 
         /// Returns true if the MMU is enabled, false otherwise.
         fn is_enabled(&self) -> bool;
+
+        /// The physical base address of the tables that the hardware walks for the kernel's
+        /// address space.
+        fn kernel_tables_phys_base_addr(&self) -> Address<Physical>;
+
+        /// Install translation tables for the lower half of the virtual address space, whose TLB
+        /// entries are tagged with `asid`.
+        ///
+        /// # Safety
+        ///
+        /// - Changes the HW's global state.
+        /// - The tables must stay valid for as long as they are installed.
+        unsafe fn set_user_translation_tables(
+            &self,
+            phys_tables_base_addr: Address<Physical>,
+            asid: u16,
+        );
+
+        /// Disable translations for the lower half of the virtual address space.
+        ///
+        /// # Safety
+        ///
+        /// - Changes the HW's global state.
+        unsafe fn clear_user_translation_tables(&self);
+
+        /// Invalidate the TLB entries for a page that are tagged with `asid`, on all cores.
+        fn invalidate_tlb_page(&self, asid: u16, virt_page_addr: PageAddress<Virtual>);
+
+        /// Invalidate all TLB entries that are tagged with `asid`, on all cores.
+        fn invalidate_tlb_asid(&self, asid: u16);
     }
 }
 
@@ -72,6 +107,13 @@ pub trait AssociatedTranslationTable {
     ///
     /// [AS_SIZE - 1, 0]
     type TableStartFromBottom;
+
+    /// A translation table whose address range is:
+    ///
+    /// [AS_SIZE - 1, 0]
+    ///
+    /// and whose tables are allocated at runtime.
+    type DynamicTableStartFromBottom;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -103,14 +145,12 @@ unsafe fn kernel_map_at_unchecked(
     Ok(())
 }
 
-/// Try to translate a kernel virtual address to a physical address.
-///
-/// Will only succeed if there exists a valid mapping for the input address.
-fn try_kernel_virt_addr_to_phys_addr(
-    virt_addr: Address<Virtual>,
-) -> Result<Address<Physical>, &'static str> {
-    bsp::memory::mmu::kernel_translation_tables()
-        .read(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
+/// Invalidate the TLB entries of a kernel region on all cores.
+fn kernel_invalidate_tlb(virt_region: &MemoryRegion<Virtual>) {
+    // Kernel pages are global, so any ASID matches.
+    for virt_page_addr in virt_region.into_iter() {
+        arch_mmu::mmu().invalidate_tlb_page(0, virt_page_addr);
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -168,6 +208,99 @@ pub fn kernel_init_mmio_va_allocator() {
     page_alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.init(region));
 }
 
+/// Query the BSP for the reserved virtual addresses for demand paged VMAs and initialize the
+/// kernel's VMA allocator with it.
+pub fn kernel_init_vma_va_allocator() {
+    vma::kernel_init_va_allocator();
+}
+
+/// Reserve `num_pages` of kernel virtual address space, which will be backed by physical frames on
+/// first access.
+pub fn kernel_register_vma(
+    name: &'static str,
+    num_pages: NonZeroUsize,
+    attr: &AttributeFields,
+) -> Result<MemoryRegion<Virtual>, &'static str> {
+    vma::kernel_register(name, num_pages, attr)
+}
+
+/// The name of the VMA that contains the address, if any.
+pub fn kernel_find_vma(addr: Address<Virtual>) -> Option<&'static str> {
+    vma::kernel_find(addr)
+}
+
+/// Try to resolve a translation fault at the given address.
+///
+/// Succeeds if the address belongs to a VMA. Execution can then resume at the faulting instruction.
+pub fn kernel_handle_translation_fault(addr: Address<Virtual>) -> Result<(), &'static str> {
+    vma::kernel_handle_translation_fault(addr)
+}
+
+/// Reserve `num_pages` of kernel virtual address space without backing it.
+pub fn kernel_reserve_va(num_pages: NonZeroUsize) -> Result<MemoryRegion<Virtual>, &'static str> {
+    vma::kernel_reserve(num_pages)
+}
+
+/// Back a region that was reserved with [`kernel_reserve_va`] with fresh physical frames.
+///
+/// # Safety
+///
+/// - The region must not be mapped yet.
+pub unsafe fn kernel_map_fresh_frames(
+    virt_region: &MemoryRegion<Virtual>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    vma::kernel_map_fresh_frames(virt_region, attr)
+}
+
+/// Back a region that was reserved with [`kernel_reserve_va`] with fresh physical frames, which
+/// need not be contiguous.
+///
+/// # Safety
+///
+/// - The region must not be mapped yet.
+pub unsafe fn kernel_map_fresh_pages(
+    virt_region: &MemoryRegion<Virtual>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    vma::kernel_map_fresh_pages(virt_region, attr)
+}
+
+/// Allocate a kernel stack of `num_pages` with an unmapped guard page below it.
+pub fn kernel_alloc_stack(num_pages: NonZeroUsize) -> Result<MemoryRegion<Virtual>, &'static str> {
+    vma::kernel_alloc_stack(num_pages)
+}
+
+/// Free a kernel stack that was allocated with [`kernel_alloc_stack`].
+///
+/// # Safety
+///
+/// - The stack must not be in use anymore.
+pub unsafe fn kernel_free_stack(virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+    vma::kernel_free_stack(virt_region)
+}
+
+/// Map `num_pages` of physically contiguous frames, e.g. for memory that is shared with devices.
+///
+/// The frames are not zeroed.
+pub fn kernel_alloc_contiguous(
+    num_pages: NonZeroUsize,
+    attr: &AttributeFields,
+) -> Result<MemoryRegion<Virtual>, &'static str> {
+    vma::kernel_alloc_contiguous(num_pages, attr)
+}
+
+/// Free a region that was allocated with [`kernel_alloc_contiguous`].
+///
+/// # Safety
+///
+/// - The region must not be in use anymore.
+pub unsafe fn kernel_free_contiguous(
+    virt_region: &MemoryRegion<Virtual>,
+) -> Result<(), &'static str> {
+    vma::kernel_free_contiguous(virt_region)
+}
+
 /// Add an entry to the mapping info record.
 pub fn kernel_add_mapping_record(
     name: &'static str,
@@ -214,7 +347,8 @@ pub unsafe fn kernel_map_mmio(
             &AttributeFields {
                 mem_attributes: MemAttributes::Device,
                 acc_perms: AccessPermissions::ReadWrite,
-                execute_never: true,
+                privileged_execute_never: true,
+                user_execute_never: true,
             },
         )?;
 
@@ -224,6 +358,84 @@ pub unsafe fn kernel_map_mmio(
     Ok(virt_addr + offset_into_start_page)
 }
 
+/// Release a mapping that was created with [`kernel_map_mmio`].
+///
+/// Shared mappings are only unmapped once the last user released them. The virtual addresses are
+/// then handed back to the MMIO VA allocator.
+///
+/// # Safety
+///
+/// - The caller must not access the mapping through `virt_addr` anymore.
+pub unsafe fn kernel_unmap_mmio(
+    name: &'static str,
+    virt_addr: Address<Virtual>,
+) -> Result<(), &'static str> {
+    let virt_start_addr = virt_addr.align_down_page();
+    let (phys_start_page_addr, attr) =
+        mapping_record::kernel_lookup(PageAddress::from(virt_start_addr))
+            .ok_or("No MMIO mapping at this address")?;
+
+    let virt_region = match mapping_record::kernel_release_mmio_user(virt_start_addr, name)? {
+        None => return Ok(()),
+        Some(x) => x,
+    };
+
+    if let Err(x) =
+        bsp::memory::mmu::kernel_translation_tables().write(|tables| tables.unmap_at(&virt_region))
+    {
+        // The mapping is still live, so put its record back.
+        let phys_region = MemoryRegion::new(
+            phys_start_page_addr,
+            phys_start_page_addr
+                .checked_offset(virt_region.num_pages() as isize)
+                .unwrap(),
+        );
+        kernel_add_mapping_record(name, &virt_region, &phys_region, &attr);
+
+        return Err(x);
+    }
+    kernel_invalidate_tlb(&virt_region);
+
+    page_alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.free(virt_region))
+}
+
+/// Unmap a region from the kernel's translation tables.
+///
+/// Fails without changing anything if any page of the region is not mapped.
+///
+/// # Safety
+///
+/// - The region must not be accessed anymore, e.g. through references into it.
+/// - Backing memory and virtual addresses are not freed. That is up to the owner of the mapping.
+pub unsafe fn kernel_unmap(virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+    bsp::memory::mmu::kernel_translation_tables().write(|tables| tables.unmap_at(virt_region))?;
+    kernel_invalidate_tlb(virt_region);
+
+    mapping_record::kernel_remove(virt_region);
+
+    Ok(())
+}
+
+/// Change the attributes of a mapped region in the kernel's translation tables.
+///
+/// Changing the memory type is not supported.
+///
+/// # Safety
+///
+/// - Revoking permissions breaks code that still relies on them.
+pub unsafe fn kernel_protect(
+    virt_region: &MemoryRegion<Virtual>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| tables.protect_at(virt_region, attr))?;
+    kernel_invalidate_tlb(virt_region);
+
+    mapping_record::kernel_protect(virt_region, attr);
+
+    Ok(())
+}
+
 /// Try to translate a kernel virtual page address to a physical page address.
 ///
 /// Will only succeed if there exists a valid mapping for the input page.
@@ -234,6 +446,16 @@ pub fn try_kernel_virt_page_addr_to_phys_page_addr(
         .read(|tables| tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr))
 }
 
+/// Try to translate a kernel virtual address to a physical address.
+///
+/// Will only succeed if there exists a valid mapping for the input address.
+pub fn try_kernel_virt_addr_to_phys_addr(
+    virt_addr: Address<Virtual>,
+) -> Result<Address<Physical>, &'static str> {
+    bsp::memory::mmu::kernel_translation_tables()
+        .read(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
+}
+
 /// Try to get the attributes of a kernel page.
 ///
 /// Will only succeed if there exists a valid mapping for the input page.
@@ -244,11 +466,64 @@ pub fn try_kernel_page_attributes(
         .read(|tables| tables.try_page_attributes(virt_page_addr))
 }
 
+/// Walk the live kernel translation tables and check every mapped page against the mapping policy.
+/// Additionally, pages of the kernel and user code regions must not be writable.
+///
+/// Blocks and pages are checked per descriptor. Each violating descriptor's range is printed.
+pub fn kernel_audit_mappings() -> Result<(), &'static str> {
+    let code_regions = [
+        bsp::memory::mmu::virt_code_region(),
+        bsp::memory::mmu::virt_user_code_region(),
+    ];
+    let phys_root_addr = arch_mmu::mmu().kernel_tables_phys_base_addr();
+    let mut num_violations = 0;
+
+    bsp::memory::mmu::kernel_translation_tables().read(|tables| {
+        tables.walk(phys_root_addr, |virt_page_addr, _, num_pages, attr| {
+            let start = virt_page_addr.into_inner();
+            let end_inclusive = start + (num_pages * bsp::memory::mmu::KernelGranule::SIZE - 1);
+
+            let result = attr.and_then(|attr| {
+                attr.check_policy()?;
+
+                let is_code = code_regions.iter().any(|region| {
+                    region.start_addr() <= end_inclusive
+                        && start <= region.end_inclusive_page_addr().into_inner()
+                });
+                if is_code && attr.acc_perms.is_writable() {
+                    return Err("Code page is writable");
+                }
+
+                Ok(())
+            });
+
+            if let Err(x) = result {
+                warn!("Mapping audit: {}..{}: {}", start, end_inclusive, x);
+                num_violations += 1;
+            }
+        })
+    })?;
+
+    if num_violations > 0 {
+        return Err("Kernel mappings violate the mapping policy");
+    }
+
+    Ok(())
+}
+
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print_mappings() {
     mapping_record::kernel_print()
 }
 
+/// Human-readable print of the live kernel translation tables.
+///
+/// Pages that are contiguous and share attributes are merged into one line. Disagreements with the
+/// mapping record are flagged, and make the function return an error.
+pub fn kernel_print_page_tables() -> Result<(), &'static str> {
+    ptdump::kernel_print()
+}
+
 /// Enable the MMU and data + instruction caching.
 ///
 /// # Safety
@@ -260,3 +535,92 @@ pub unsafe fn enable_mmu_and_caching(
 ) -> Result<(), MMUEnableError> {
     arch_mmu::mmu().enable_mmu_and_caching(phys_tables_base_addr)
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::frame_alloc;
+    use test_macros::kernel_test;
+
+    /// A shared MMIO mapping is only unmapped once its last user released it.
+    #[kernel_test]
+    fn mmio_unmap_after_last_user() {
+        let frames = frame_alloc::kernel_frame_allocator()
+            .alloc_frames(NonZeroUsize::new(1).unwrap(), 1)
+            .unwrap();
+        let descriptor = MMIODescriptor::new(frames.start_addr() + 0x10, 0x20);
+
+        let virt_a = unsafe { kernel_map_mmio("a", &descriptor).unwrap() };
+        let virt_b = unsafe { kernel_map_mmio("b", &descriptor).unwrap() };
+        assert_eq!(virt_a, virt_b);
+
+        let page = PageAddress::from(virt_a.align_down_page());
+        unsafe { assert_eq!(kernel_unmap_mmio("a", virt_a), Ok(())) };
+        assert_eq!(
+            try_kernel_virt_page_addr_to_phys_page_addr(page),
+            Ok(frames.start_page_addr())
+        );
+
+        unsafe { assert_eq!(kernel_unmap_mmio("b", virt_b), Ok(())) };
+        assert!(try_kernel_virt_page_addr_to_phys_page_addr(page).is_err());
+        unsafe {
+            assert_eq!(
+                kernel_unmap_mmio("b", virt_b),
+                Err("No MMIO mapping at this address")
+            )
+        };
+
+        // The virtual addresses are handed out again.
+        let virt_c = unsafe { kernel_map_mmio("c", &descriptor).unwrap() };
+        assert_eq!(virt_c, virt_a);
+        unsafe { kernel_unmap_mmio("c", virt_c).unwrap() };
+
+        unsafe {
+            frame_alloc::kernel_frame_allocator()
+                .free_frames(&frames)
+                .unwrap()
+        };
+    }
+
+    /// Protecting a range updates the page attributes and rejects memory type changes.
+    #[kernel_test]
+    fn protect_changes_attributes() {
+        let rw = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        let ro = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnly,
+            ..rw
+        };
+        let dev = AttributeFields {
+            mem_attributes: MemAttributes::Device,
+            ..rw
+        };
+
+        let stack = kernel_alloc_stack(NonZeroUsize::new(2).unwrap()).unwrap();
+        let second_page = stack.start_page_addr().checked_offset(1).unwrap();
+        let second = MemoryRegion::new(second_page, stack.end_exclusive_page_addr());
+
+        unsafe {
+            assert_eq!(kernel_protect(&second, &ro), Ok(()));
+            assert_eq!(
+                kernel_protect(&second, &dev),
+                Err("Changing the memory type of a mapping is not supported")
+            );
+        }
+        assert_eq!(try_kernel_page_attributes(stack.start_page_addr()), Ok(rw));
+        assert_eq!(try_kernel_page_attributes(second_page), Ok(ro));
+
+        unsafe {
+            kernel_protect(&second, &rw).unwrap();
+            kernel_free_stack(&stack).unwrap();
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Address spaces for the lower half of the virtual address space.
+//!
+//! Not to be confused with [`super::AddressSpace`], which only describes the size of an address
+//! space.
+//!
+//! Each [`AddressSpace`] has its own, dynamically allocated translation tables and an ASID. The
+//! active address space is installed in TTBR0, while the kernel keeps running from the upper half.
+//! Because its TLB entries are tagged with the ASID, switching address spaces does not require
+//! flushing the TLB.
+
+use super::{
+    arch_mmu, interface::MMU, translation_table::interface::TranslationTable, AttributeFields,
+    MemoryRegion, PageAddress,
+};
+use crate::{
+    bsp, cpu,
+    memory::{Address, Physical, Virtual},
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
+};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// ASID zero is reserved for "no address space".
+const NO_ASID: u16 = 0;
+
+/// Keeps track of the ASIDs in use.
+struct AsidAllocator {
+    used: [u64; arch_mmu::NUM_ASIDS / 64],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An address space for the lower half of the virtual address space.
+pub struct AddressSpace {
+    asid: u16,
+    tables: IRQSafeSpinLock<bsp::memory::mmu::UserTranslationTable>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static ASID_ALLOCATOR: IRQSafeSpinLock<AsidAllocator> = IRQSafeSpinLock::new(AsidAllocator::new());
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_NO_ASID: AtomicU16 = AtomicU16::new(NO_ASID);
+
+/// Per core, the ASID of the address space that is currently installed in TTBR0.
+static ACTIVE_ASIDS: [AtomicU16; bsp::cpu::NUM_CORES] = [ATOMIC_NO_ASID; bsp::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl AsidAllocator {
+    const fn new() -> Self {
+        let mut used = [0; arch_mmu::NUM_ASIDS / 64];
+        used[0] = 1 << NO_ASID;
+
+        Self { used }
+    }
+
+    fn alloc(&mut self) -> Result<u16, &'static str> {
+        let asid = (0..arch_mmu::NUM_ASIDS)
+            .find(|&i| self.used[i / 64] & (1 << (i % 64)) == 0)
+            .ok_or("Out of ASIDs")?;
+
+        self.used[asid / 64] |= 1 << (asid % 64);
+
+        Ok(asid as u16)
+    }
+
+    fn free(&mut self, asid: u16) {
+        let asid = asid as usize;
+
+        self.used[asid / 64] &= !(1 << (asid % 64));
+    }
+}
+
+/// The ASID that is installed in TTBR0 of the executing core.
+fn active_asid() -> &'static AtomicU16 {
+    &ACTIVE_ASIDS[cpu::smp::core_id::<usize>()]
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl AddressSpace {
+    /// Create an empty address space.
+    pub fn new() -> Result<Self, &'static str> {
+        let asid = ASID_ALLOCATOR.lock(|allocator| allocator.alloc())?;
+        let tables = match bsp::memory::mmu::UserTranslationTable::new(asid) {
+            Err(x) => {
+                ASID_ALLOCATOR.lock(|allocator| allocator.free(asid));
+                return Err(x);
+            }
+            Ok(x) => x,
+        };
+
+        Ok(Self {
+            asid,
+            tables: IRQSafeSpinLock::new(tables),
+        })
+    }
+
+    /// The ASID of this address space.
+    pub fn asid(&self) -> u16 {
+        self.asid
+    }
+
+    /// Map the given virtual memory region to the given physical memory region.
+    ///
+    /// # Safety
+    ///
+    /// - See `map_at()` of the translation table interface.
+    pub unsafe fn map_at(
+        &self,
+        virt_region: &MemoryRegion<Virtual>,
+        phys_region: &MemoryRegion<Physical>,
+        attr: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        self.tables
+            .lock(|tables| tables.map_at(virt_region, phys_region, attr))
+    }
+
+    /// Unmap the given virtual memory region.
+    ///
+    /// # Safety
+    ///
+    /// - See `unmap_at()` of the translation table interface.
+    pub unsafe fn unmap(&self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        self.tables.lock(|tables| tables.unmap_at(virt_region))
+    }
+
+    /// Try to translate a virtual page address to a physical page address.
+    ///
+    /// Will only succeed if there exists a valid mapping for the input page.
+    pub fn try_virt_page_addr_to_phys_page_addr(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<PageAddress<Physical>, &'static str> {
+        self.tables
+            .lock(|tables| tables.try_virt_page_addr_to_phys_page_addr(virt_page_addr))
+    }
+
+    /// Try to get the attributes of a page.
+    ///
+    /// Will only succeed if there exists a valid mapping for the input page.
+    pub fn try_page_attributes(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Result<AttributeFields, &'static str> {
+        self.tables
+            .lock(|tables| tables.try_page_attributes(virt_page_addr))
+    }
+
+    /// Try to translate a virtual address to a physical address.
+    ///
+    /// Will only succeed if there exists a valid mapping for the input address.
+    pub fn try_virt_addr_to_phys_addr(
+        &self,
+        virt_addr: Address<Virtual>,
+    ) -> Result<Address<Physical>, &'static str> {
+        self.tables
+            .lock(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
+    }
+
+    /// Switch the executing core to this address space.
+    ///
+    /// # Safety
+    ///
+    /// - The address space must not be dropped while it is active on another core.
+    /// - The caller must not migrate to another core in between, e.g. by running with IRQs masked.
+    pub unsafe fn activate(&self) {
+        if active_asid().swap(self.asid, Ordering::Relaxed) == self.asid {
+            return;
+        }
+
+        let phys_tables_base_addr = self.tables.lock(|tables| tables.phys_base_address());
+        arch_mmu::mmu().set_user_translation_tables(phys_tables_base_addr, self.asid);
+    }
+
+    /// Switch the executing core to having no lower half address space at all.
+    ///
+    /// # Safety
+    ///
+    /// - Any references into the lower half become invalid.
+    /// - The caller must not migrate to another core in between, e.g. by running with IRQs masked.
+    pub unsafe fn deactivate() {
+        if active_asid().swap(NO_ASID, Ordering::Relaxed) == NO_ASID {
+            return;
+        }
+
+        arch_mmu::mmu().clear_user_translation_tables();
+    }
+
+    /// Checks if this address space is the active one on the executing core.
+    pub fn is_active(&self) -> bool {
+        active_asid().load(Ordering::Relaxed) == self.asid
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { Self::deactivate() };
+        }
+
+        // Stale entries must be gone before the ASID can be handed out again.
+        arch_mmu::mmu().invalidate_tlb_asid(self.asid);
+        ASID_ALLOCATOR.lock(|allocator| allocator.free(self.asid));
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mmu::{AccessPermissions, MemAttributes};
+    use alloc::{
+        alloc::{alloc_zeroed, dealloc, Layout},
+        vec::Vec,
+    };
+    use test_macros::kernel_test;
+
+    const ATTR: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        privileged_execute_never: true,
+        user_execute_never: true,
+    };
+
+    fn page_layout() -> Layout {
+        let size = bsp::memory::mmu::KernelGranule::SIZE;
+
+        Layout::from_size_align(size, size).unwrap()
+    }
+
+    /// Allocate a page from the kernel heap and return its virtual and physical region.
+    fn alloc_page() -> (MemoryRegion<Virtual>, MemoryRegion<Physical>) {
+        let virt_addr = Address::<Virtual>::new(unsafe { alloc_zeroed(page_layout()) } as usize);
+        let phys_addr = crate::memory::mmu::try_kernel_virt_addr_to_phys_addr(virt_addr).unwrap();
+
+        let virt_page_addr = PageAddress::from(virt_addr);
+        let phys_page_addr = PageAddress::from(phys_addr);
+
+        (
+            MemoryRegion::new(virt_page_addr, virt_page_addr.checked_offset(1).unwrap()),
+            MemoryRegion::new(phys_page_addr, phys_page_addr.checked_offset(1).unwrap()),
+        )
+    }
+
+    fn free_page(virt_region: MemoryRegion<Virtual>) {
+        unsafe {
+            dealloc(
+                virt_region.start_addr().as_usize() as *mut u8,
+                page_layout(),
+            )
+        }
+    }
+
+    /// A lower half region consisting of a single page.
+    fn user_region(page: isize) -> MemoryRegion<Virtual> {
+        let start = PageAddress::from(0).checked_offset(page).unwrap();
+
+        MemoryRegion::new(start, start.checked_offset(1).unwrap())
+    }
+
+    /// Map, translate and unmap.
+    #[kernel_test]
+    fn address_space_map_translate_unmap() {
+        let space = AddressSpace::new().unwrap();
+        let (kernel_virt, phys) = alloc_page();
+        let virt = user_region(16);
+
+        unsafe { assert_eq!(space.map_at(&virt, &phys, &ATTR), Ok(())) };
+        unsafe {
+            assert_eq!(
+                space.map_at(&virt, &phys, &ATTR),
+                Err("Virtual page is already mapped")
+            )
+        };
+
+        assert_eq!(
+            space.try_virt_page_addr_to_phys_page_addr(virt.start_page_addr()),
+            Ok(phys.start_page_addr())
+        );
+        assert_eq!(space.try_page_attributes(virt.start_page_addr()), Ok(ATTR));
+        assert_eq!(
+            space.try_virt_addr_to_phys_addr(virt.start_addr() + 0x100),
+            Ok(phys.start_addr() + 0x100)
+        );
+
+        unsafe { assert_eq!(space.unmap(&virt), Ok(())) };
+        unsafe { assert_eq!(space.unmap(&virt), Err("Page marked invalid")) };
+        assert_eq!(
+            space.try_page_attributes(virt.start_page_addr()),
+            Err("Page marked invalid")
+        );
+
+        free_page(kernel_virt);
+    }
+
+    /// The same virtual address resolves differently depending on the active address space.
+    #[kernel_test]
+    fn address_space_activation() {
+        let space_a = AddressSpace::new().unwrap();
+        let space_b = AddressSpace::new().unwrap();
+        assert_ne!(space_a.asid(), space_b.asid());
+
+        let (kernel_virt_a, phys_a) = alloc_page();
+        let (kernel_virt_b, phys_b) = alloc_page();
+        let virt = user_region(1);
+
+        unsafe {
+            (kernel_virt_a.start_addr().as_usize() as *mut u64).write_volatile(0xAAAA);
+            (kernel_virt_b.start_addr().as_usize() as *mut u64).write_volatile(0xBBBB);
+
+            space_a.map_at(&virt, &phys_a, &ATTR).unwrap();
+            space_b.map_at(&virt, &phys_b, &ATTR).unwrap();
+        }
+
+        let ptr = virt.start_addr().as_usize() as *const u64;
+        unsafe {
+            space_a.activate();
+            assert!(space_a.is_active());
+            assert_eq!(ptr.read_volatile(), 0xAAAA);
+
+            space_b.activate();
+            assert_eq!(ptr.read_volatile(), 0xBBBB);
+
+            space_a.activate();
+            assert_eq!(ptr.read_volatile(), 0xAAAA);
+
+            AddressSpace::deactivate();
+        }
+        assert!(!space_a.is_active());
+
+        drop(space_a);
+        drop(space_b);
+        free_page(kernel_virt_a);
+        free_page(kernel_virt_b);
+    }
+
+    /// Remapping a page of the active address space takes effect without switching address spaces.
+    #[kernel_test]
+    fn address_space_remap_while_active() {
+        let space = AddressSpace::new().unwrap();
+        let (kernel_virt_a, phys_a) = alloc_page();
+        let (kernel_virt_b, phys_b) = alloc_page();
+        let virt = user_region(2);
+
+        let ptr = virt.start_addr().as_usize() as *const u64;
+        unsafe {
+            (kernel_virt_a.start_addr().as_usize() as *mut u64).write_volatile(0xAAAA);
+            (kernel_virt_b.start_addr().as_usize() as *mut u64).write_volatile(0xBBBB);
+
+            space.map_at(&virt, &phys_a, &ATTR).unwrap();
+            space.activate();
+            assert_eq!(ptr.read_volatile(), 0xAAAA);
+
+            // A stale TLB entry would still point to the first page.
+            space.unmap(&virt).unwrap();
+            space.map_at(&virt, &phys_b, &ATTR).unwrap();
+            assert_eq!(ptr.read_volatile(), 0xBBBB);
+
+            AddressSpace::deactivate();
+        }
+
+        drop(space);
+        free_page(kernel_virt_a);
+        free_page(kernel_virt_b);
+    }
+
+    /// ASIDs are handed out again after their address space was dropped.
+    #[kernel_test]
+    fn address_space_asids_are_recycled() {
+        let mut spaces = Vec::new();
+
+        while let Ok(space) = AddressSpace::new() {
+            spaces.push(space);
+        }
+        assert_eq!(spaces.len(), arch_mmu::NUM_ASIDS - 1);
+
+        let asid = spaces.pop().unwrap().asid();
+        assert_eq!(AddressSpace::new().map(|space| space.asid()), Ok(asid));
+    }
+}
@@ -5,10 +5,10 @@
 //! A record of mapped pages.
 
 use super::{
-    AccessPermissions, Address, AttributeFields, MMIODescriptor, MemAttributes, MemoryRegion,
-    Physical, Virtual,
+    Address, AttributeFields, MMIODescriptor, MemAttributes, MemoryRegion, PageAddress, Physical,
+    Virtual,
 };
-use crate::{bsp, common, info, synchronization, synchronization::InitStateLock};
+use crate::{bsp, common, info, synchronization, synchronization::RwSpinLock};
 use alloc::{vec, vec::Vec};
 
 //--------------------------------------------------------------------------------------------------
@@ -17,6 +17,7 @@ use alloc::{vec, vec::Vec};
 
 /// Type describing a virtual memory mapping.
 #[allow(missing_docs)]
+#[derive(Clone)]
 struct MappingRecordEntry {
     pub users: Vec<&'static str>,
     pub phys_start_addr: Address<Physical>,
@@ -33,8 +34,7 @@ struct MappingRecord {
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static KERNEL_MAPPING_RECORD: InitStateLock<MappingRecord> =
-    InitStateLock::new(MappingRecord::new());
+static KERNEL_MAPPING_RECORD: RwSpinLock<MappingRecord> = RwSpinLock::new(MappingRecord::new());
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -59,6 +59,32 @@ impl MappingRecordEntry {
     pub fn add_user(&mut self, user: &'static str) {
         self.users.push(user);
     }
+
+    fn virt_region(&self) -> MemoryRegion<Virtual> {
+        let start = PageAddress::from(self.virt_start_addr);
+
+        MemoryRegion::new(
+            start,
+            start.checked_offset(self.num_pages as isize).unwrap(),
+        )
+    }
+
+    /// Split off the part starting at `virt_page_addr`, which must lie inside of the entry.
+    fn split_off(&mut self, virt_page_addr: PageAddress<Virtual>) -> Self {
+        let offset = virt_page_addr.into_inner().as_usize() - self.virt_start_addr.as_usize();
+        let num_pages_left = offset >> bsp::memory::mmu::KernelGranule::SHIFT;
+
+        let right = Self {
+            users: self.users.clone(),
+            phys_start_addr: self.phys_start_addr + offset,
+            virt_start_addr: virt_page_addr.into_inner(),
+            num_pages: self.num_pages - num_pages_left,
+            attribute_fields: self.attribute_fields,
+        };
+        self.num_pages = num_pages_left;
+
+        right
+    }
 }
 
 impl MappingRecord {
@@ -92,6 +118,99 @@ impl MappingRecord {
             })
     }
 
+    /// Split entries so that no entry straddles a boundary of `virt_region`.
+    fn split_at_boundaries(&mut self, virt_region: &MemoryRegion<Virtual>) {
+        for boundary in [
+            virt_region.start_page_addr(),
+            virt_region.end_exclusive_page_addr(),
+        ] {
+            let straddling = self.inner.iter_mut().find(|x| {
+                x.virt_region().contains(boundary.into_inner())
+                    && x.virt_start_addr != boundary.into_inner()
+            });
+
+            if let Some(entry) = straddling {
+                let right = entry.split_off(boundary);
+                self.inner.push(right);
+            }
+        }
+
+        self.sort();
+    }
+
+    fn is_inside(entry: &MappingRecordEntry, virt_region: &MemoryRegion<Virtual>) -> bool {
+        let entry_region = entry.virt_region();
+
+        entry_region.start_page_addr() >= virt_region.start_page_addr()
+            && entry_region.end_exclusive_page_addr() <= virt_region.end_exclusive_page_addr()
+    }
+
+    pub fn remove(&mut self, virt_region: &MemoryRegion<Virtual>) {
+        self.split_at_boundaries(virt_region);
+        self.inner.retain(|x| !Self::is_inside(x, virt_region));
+    }
+
+    pub fn protect(&mut self, virt_region: &MemoryRegion<Virtual>, attr: &AttributeFields) {
+        self.split_at_boundaries(virt_region);
+
+        for entry in self
+            .inner
+            .iter_mut()
+            .filter(|x| Self::is_inside(x, virt_region))
+        {
+            entry.attribute_fields = *attr;
+        }
+    }
+
+    /// Remove a user from the MMIO mapping starting at `virt_start_addr`.
+    ///
+    /// Returns the region of the mapping if this was the last user, so that it can be unmapped.
+    pub fn release_mmio_user(
+        &mut self,
+        virt_start_addr: Address<Virtual>,
+        user: &'static str,
+    ) -> Result<Option<MemoryRegion<Virtual>>, &'static str> {
+        let i = self
+            .inner
+            .iter()
+            .position(|x| {
+                x.virt_start_addr == virt_start_addr
+                    && x.attribute_fields.mem_attributes == MemAttributes::Device
+            })
+            .ok_or("No MMIO mapping at this address")?;
+
+        let entry = &mut self.inner[i];
+        let user_index = entry
+            .users
+            .iter()
+            .position(|x| *x == user)
+            .ok_or("Not a user of this MMIO mapping")?;
+        entry.users.remove(user_index);
+
+        if !entry.users.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.inner.remove(i).virt_region()))
+    }
+
+    /// The recorded translation of a single page.
+    pub fn lookup(
+        &self,
+        virt_page_addr: PageAddress<Virtual>,
+    ) -> Option<(PageAddress<Physical>, AttributeFields)> {
+        let entry = self
+            .inner
+            .iter()
+            .find(|x| x.virt_region().contains(virt_page_addr.into_inner()))?;
+        let offset = virt_page_addr.into_inner().as_usize() - entry.virt_start_addr.as_usize();
+
+        Some((
+            PageAddress::from(entry.phys_start_addr + offset),
+            entry.attribute_fields,
+        ))
+    }
+
     pub fn add(
         &mut self,
         name: &'static str,
@@ -126,33 +245,15 @@ impl MappingRecord {
 
             let (size, unit) = common::size_human_readable_ceil(size);
 
-            let attr = match i.attribute_fields.mem_attributes {
-                MemAttributes::CacheableDRAM => "C",
-                MemAttributes::Device => "Dev",
-            };
-
-            let acc_p = match i.attribute_fields.acc_perms {
-                AccessPermissions::ReadOnly => "RO",
-                AccessPermissions::ReadWrite => "RW",
-            };
-
-            let xn = if i.attribute_fields.execute_never {
-                "XN"
-            } else {
-                "X"
-            };
-
             info!(
-                "      {}..{} --> {}..{} | {:>3} {} | {:<3} {} {:<2} | {}",
+                "      {}..{} --> {}..{} | {:>3} {} | {} | {}",
                 virt_start,
                 virt_end_inclusive,
                 phys_start,
                 phys_end_inclusive,
                 size,
                 unit,
-                attr,
-                acc_p,
-                xn,
+                i.attribute_fields,
                 i.users[0]
             );
 
@@ -198,6 +299,46 @@ pub fn kernel_find_and_insert_mmio_duplicate(
     })
 }
 
+/// Remove the given region from the mapping info record.
+///
+/// Entries that only partially overlap with the region are trimmed.
+pub fn kernel_remove(virt_region: &MemoryRegion<Virtual>) {
+    KERNEL_MAPPING_RECORD.write(|mr| mr.remove(virt_region))
+}
+
+/// Update the attributes of the given region in the mapping info record.
+pub fn kernel_protect(virt_region: &MemoryRegion<Virtual>, attr: &AttributeFields) {
+    KERNEL_MAPPING_RECORD.write(|mr| mr.protect(virt_region, attr))
+}
+
+/// Remove a user from a shared MMIO mapping.
+///
+/// Returns the region of the mapping once the last user is gone. It is removed from the record
+/// then, and must be unmapped by the caller.
+pub fn kernel_release_mmio_user(
+    virt_start_addr: Address<Virtual>,
+    user: &'static str,
+) -> Result<Option<MemoryRegion<Virtual>>, &'static str> {
+    KERNEL_MAPPING_RECORD.write(|mr| mr.release_mmio_user(virt_start_addr, user))
+}
+
+/// The recorded translation of a kernel page, if any.
+pub fn kernel_lookup(
+    virt_page_addr: PageAddress<Virtual>,
+) -> Option<(PageAddress<Physical>, AttributeFields)> {
+    KERNEL_MAPPING_RECORD.read(|mr| mr.lookup(virt_page_addr))
+}
+
+/// The virtual regions of all recorded kernel mappings, together with their first user.
+pub fn kernel_recorded_regions() -> Vec<(MemoryRegion<Virtual>, &'static str)> {
+    KERNEL_MAPPING_RECORD.read(|mr| {
+        mr.inner
+            .iter()
+            .map(|x| (x.virt_region(), x.users[0]))
+            .collect()
+    })
+}
+
 /// Human-readable print of all recorded kernel mappings.
 pub fn kernel_print() {
     KERNEL_MAPPING_RECORD.read(|mr| mr.print());
@@ -7,9 +7,10 @@
 use super::MemoryRegion;
 use crate::{
     memory::{AddressType, Virtual},
-    synchronization::IRQSafeNullLock,
+    synchronization::IRQSafeSpinLock,
     warn,
 };
+use alloc::vec::Vec;
 use core::num::NonZeroUsize;
 
 //--------------------------------------------------------------------------------------------------
@@ -19,28 +20,34 @@ use core::num::NonZeroUsize;
 /// A page allocator that can be lazyily initialized.
 pub struct PageAllocator<ATYPE: AddressType> {
     pool: Option<MemoryRegion<ATYPE>>,
+
+    /// Freed regions that lie below the pool, sorted by address and without adjacent neighbors.
+    free: Vec<MemoryRegion<ATYPE>>,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static KERNEL_MMIO_VA_ALLOCATOR: IRQSafeNullLock<PageAllocator<Virtual>> =
-    IRQSafeNullLock::new(PageAllocator::new());
+static KERNEL_MMIO_VA_ALLOCATOR: IRQSafeSpinLock<PageAllocator<Virtual>> =
+    IRQSafeSpinLock::new(PageAllocator::new());
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
 /// Return a reference to the kernel's MMIO virtual address allocator.
-pub fn kernel_mmio_va_allocator() -> &'static IRQSafeNullLock<PageAllocator<Virtual>> {
+pub fn kernel_mmio_va_allocator() -> &'static IRQSafeSpinLock<PageAllocator<Virtual>> {
     &KERNEL_MMIO_VA_ALLOCATOR
 }
 
 impl<ATYPE: AddressType> PageAllocator<ATYPE> {
     /// Create an instance.
     pub const fn new() -> Self {
-        Self { pool: None }
+        Self {
+            pool: None,
+            free: Vec::new(),
+        }
     }
 
     /// Initialize the allocator.
@@ -54,17 +61,120 @@ impl<ATYPE: AddressType> PageAllocator<ATYPE> {
     }
 
     /// Allocate a number of pages.
+    ///
+    /// Freed regions are reused first.
     pub fn alloc(
         &mut self,
         num_requested_pages: NonZeroUsize,
     ) -> Result<MemoryRegion<ATYPE>, &'static str> {
-        if self.pool.is_none() {
-            return Err("Allocator not initialized");
+        let pool = self.pool.as_mut().ok_or("Allocator not initialized")?;
+
+        if let Some(i) = self
+            .free
+            .iter()
+            .position(|region| region.num_pages() >= num_requested_pages.get())
+        {
+            let allocation = self.free[i].take_first_n_pages(num_requested_pages)?;
+            if self.free[i].num_pages() == 0 {
+                self.free.remove(i);
+            }
+
+            return Ok(allocation);
+        }
+
+        pool.take_first_n_pages(num_requested_pages)
+    }
+
+    /// Return pages that were handed out by [`PageAllocator::alloc`].
+    pub fn free(&mut self, region: MemoryRegion<ATYPE>) -> Result<(), &'static str> {
+        let pool = self.pool.as_mut().ok_or("Allocator not initialized")?;
+
+        if region.num_pages() == 0 {
+            return Err("Tried to free an empty region");
+        }
+
+        if region.end_exclusive_page_addr() > pool.start_page_addr()
+            || self
+                .free
+                .iter()
+                .any(|x| x.overlaps(&region) || region.overlaps(x))
+        {
+            return Err("Region is not allocated");
+        }
+
+        let i = self
+            .free
+            .partition_point(|x| x.start_page_addr() < region.start_page_addr());
+        self.free.insert(i, region);
+
+        // Merge with the right neighbor, then with the left one.
+        if i + 1 < self.free.len()
+            && self.free[i].end_exclusive_page_addr() == self.free[i + 1].start_page_addr()
+        {
+            let right = self.free.remove(i + 1);
+            self.free[i] = MemoryRegion::new(
+                self.free[i].start_page_addr(),
+                right.end_exclusive_page_addr(),
+            );
         }
+        if i > 0 && self.free[i - 1].end_exclusive_page_addr() == self.free[i].start_page_addr() {
+            let right = self.free.remove(i);
+            self.free[i - 1] = MemoryRegion::new(
+                self.free[i - 1].start_page_addr(),
+                right.end_exclusive_page_addr(),
+            );
+        }
+
+        // Give a trailing free region back to the pool.
+        if let Some(last) = self.free.last() {
+            if last.end_exclusive_page_addr() == pool.start_page_addr() {
+                *pool = MemoryRegion::new(last.start_page_addr(), pool.end_exclusive_page_addr());
+                self.free.pop();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mmu::PageAddress;
+    use test_macros::kernel_test;
+
+    fn pages(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    /// Freed pages are reused, coalesced, and eventually returned to the pool.
+    #[kernel_test]
+    fn page_alloc_free_and_reuse() {
+        let start = PageAddress::<Virtual>::from(0);
+        let mut allocator = PageAllocator::new();
+        allocator.init(MemoryRegion::new(start, start.checked_offset(16).unwrap()));
+
+        let a = allocator.alloc(pages(2)).unwrap();
+        let b = allocator.alloc(pages(3)).unwrap();
+        let c = allocator.alloc(pages(1)).unwrap();
+
+        assert_eq!(allocator.free(b), Ok(()));
+        assert_eq!(allocator.free(b), Err("Region is not allocated"));
+
+        // Served from the hole that b left behind.
+        let d = allocator.alloc(pages(1)).unwrap();
+        assert_eq!(d.start_page_addr(), b.start_page_addr());
+
+        assert_eq!(allocator.free(a), Ok(()));
+        assert_eq!(allocator.free(d), Ok(()));
+        assert_eq!(allocator.free.len(), 1);
 
-        self.pool
-            .as_mut()
-            .unwrap()
-            .take_first_n_pages(num_requested_pages)
+        assert_eq!(allocator.free(c), Ok(()));
+        assert!(allocator.free.is_empty());
+        assert_eq!(allocator.alloc(pages(16)).unwrap().start_page_addr(), start);
     }
 }
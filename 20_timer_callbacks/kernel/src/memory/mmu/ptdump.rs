@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Dump of the live kernel translation tables.
+//!
+//! In contrast to the mapping record, which is bookkeeping done in software, the tables are walked
+//! the way the hardware sees them, starting at the base address that is programmed into the MMU.
+
+use super::{
+    arch_mmu, interface::MMU, mapping_record, AttributeFields, PageAddress, Physical, Virtual,
+};
+use crate::{bsp, common, info, memory::Address, synchronization::interface::ReadWriteEx, warn};
+use alloc::vec::Vec;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Contiguous pages with identical attributes.
+struct Range {
+    virt_start_page_addr: PageAddress<Virtual>,
+    phys_start_page_addr: PageAddress<Physical>,
+    num_pages: usize,
+    attr: Result<AttributeFields, &'static str>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Range {
+    /// Append pages that continue the range seamlessly, in both address spaces.
+    fn try_extend(&mut self, other: &Self) -> bool {
+        let offset = self.num_pages as isize;
+
+        if self.virt_start_page_addr.checked_offset(offset) != Some(other.virt_start_page_addr)
+            || self.phys_start_page_addr.checked_offset(offset) != Some(other.phys_start_page_addr)
+            || self.attr != other.attr
+        {
+            return false;
+        }
+
+        self.num_pages += other.num_pages;
+        true
+    }
+
+    fn contains(&self, virt_page_addr: PageAddress<Virtual>) -> bool {
+        let start = self.virt_start_page_addr.into_inner().as_usize();
+        let addr = virt_page_addr.into_inner().as_usize();
+
+        addr >= start && ((addr - start) >> bsp::memory::mmu::KernelGranule::SHIFT) < self.num_pages
+    }
+
+    /// Compare the range against the mapping record, page by page.
+    ///
+    /// Demand paged VMAs and kernel stacks are tracked by the VMA allocator instead of the mapping
+    /// record, so unrecorded pages in their region are fine.
+    fn check_record(&self) -> Result<(), &'static str> {
+        let vma_region = bsp::memory::mmu::virt_vma_region();
+
+        for i in 0..self.num_pages {
+            let virt_page_addr = self
+                .virt_start_page_addr
+                .checked_offset(i as isize)
+                .unwrap();
+            let phys_page_addr = self
+                .phys_start_page_addr
+                .checked_offset(i as isize)
+                .unwrap();
+
+            match mapping_record::kernel_lookup(virt_page_addr) {
+                None if vma_region.contains(virt_page_addr.into_inner()) => (),
+                None => return Err("Not in the mapping record"),
+                Some((phys, _)) if phys != phys_page_addr => {
+                    return Err("Physical address differs from the mapping record")
+                }
+                Some((_, attr)) if Ok(attr) != self.attr => {
+                    return Err("Attributes differ from the mapping record")
+                }
+                Some(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print(&self, check: Result<(), &'static str>) {
+        let size = self.num_pages * bsp::memory::mmu::KernelGranule::SIZE;
+        let virt_start = self.virt_start_page_addr.into_inner();
+        let virt_end_inclusive = virt_start + (size - 1);
+        let phys_start = self.phys_start_page_addr.into_inner();
+        let phys_end_inclusive = phys_start + (size - 1);
+
+        let (size, unit) = common::size_human_readable_ceil(size);
+
+        let check = match check {
+            Ok(()) => "",
+            Err(x) => x,
+        };
+
+        match self.attr {
+            Ok(attr) => info!(
+                "      {}..{} --> {}..{} | {:>3} {} | {} | {}",
+                virt_start,
+                virt_end_inclusive,
+                phys_start,
+                phys_end_inclusive,
+                size,
+                unit,
+                attr,
+                check
+            ),
+            Err(x) => info!(
+                "      {}..{} --> {}..{} | {:>3} {} | {:<10} | {}",
+                virt_start,
+                virt_end_inclusive,
+                phys_start,
+                phys_end_inclusive,
+                size,
+                unit,
+                "???",
+                x
+            ),
+        }
+    }
+}
+
+/// Walk the tables that the hardware uses and call `f` for each range.
+fn walk_ranges(
+    phys_root_addr: Address<Physical>,
+    mut f: impl FnMut(Range),
+) -> Result<(), &'static str> {
+    let mut current: Option<Range> = None;
+
+    bsp::memory::mmu::kernel_translation_tables().read(|tables| {
+        tables.walk(phys_root_addr, |virt, phys, num_pages, attr| {
+            let next = Range {
+                virt_start_page_addr: virt,
+                phys_start_page_addr: phys,
+                num_pages,
+                attr,
+            };
+
+            if let Some(range) = current.as_mut() {
+                if range.try_extend(&next) {
+                    return;
+                }
+            }
+
+            if let Some(range) = current.replace(next) {
+                f(range);
+            }
+        })
+    })?;
+
+    if let Some(range) = current {
+        f(range);
+    }
+
+    Ok(())
+}
+
+/// Collect the ranges of the live tables.
+///
+/// Heap growth maps pages, so nothing must be allocated while the tables are locked. The ranges are
+/// counted first for this reason.
+fn collect_ranges(phys_root_addr: Address<Physical>) -> Result<Vec<Range>, &'static str> {
+    let mut num_ranges = 0;
+    walk_ranges(phys_root_addr, |_| num_ranges += 1)?;
+
+    let mut ranges = Vec::with_capacity(num_ranges);
+    let mut truncated = false;
+    walk_ranges(phys_root_addr, |range| {
+        if ranges.len() < ranges.capacity() {
+            ranges.push(range);
+        } else {
+            truncated = true;
+        }
+    })?;
+
+    if truncated {
+        return Err("Translation tables changed during the dump");
+    }
+
+    Ok(ranges)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Print the live kernel translation tables, merged into ranges, and check them against the
+/// mapping record.
+pub fn kernel_print() -> Result<(), &'static str> {
+    let phys_root_addr = arch_mmu::mmu().kernel_tables_phys_base_addr();
+    let ranges = collect_ranges(phys_root_addr)?;
+    let mut num_disagreements = 0;
+
+    info!("      Tables at {}", phys_root_addr);
+    info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+    info!(
+        "      {:^44}     {:^30}   {:^7}   {:^9}   {:^35}",
+        "Virtual", "Physical", "Size", "Attr", "Mapping record"
+    );
+    info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+
+    for range in ranges.iter() {
+        let check = range.check_record();
+        if check.is_err() {
+            num_disagreements += 1;
+        }
+
+        range.print(check);
+    }
+
+    info!("      -------------------------------------------------------------------------------------------------------------------------------------------");
+
+    for (virt_region, name) in mapping_record::kernel_recorded_regions() {
+        let fully_mapped = virt_region
+            .into_iter()
+            .all(|page| ranges.iter().any(|range| range.contains(page)));
+
+        if !fully_mapped {
+            warn!(
+                "Page table dump: {}..{} | {}: Recorded, but not mapped",
+                virt_region.start_addr(),
+                virt_region.end_inclusive_page_addr().into_inner(),
+                name
+            );
+            num_disagreements += 1;
+        }
+    }
+
+    if num_disagreements > 0 {
+        return Err("Translation tables disagree with the mapping record");
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mmu::{AccessPermissions, MemAttributes};
+    use test_macros::kernel_test;
+
+    /// Pages are merged only if they are contiguous in both address spaces and share attributes.
+    #[kernel_test]
+    fn ranges_merge_only_seamless_pages() {
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        let page = |virt: usize, phys: usize, attr| Range {
+            virt_start_page_addr: PageAddress::from(virt * bsp::memory::mmu::KernelGranule::SIZE),
+            phys_start_page_addr: PageAddress::from(phys * bsp::memory::mmu::KernelGranule::SIZE),
+            num_pages: 1,
+            attr,
+        };
+
+        let mut range = page(10, 20, Ok(attr));
+        assert!(range.try_extend(&page(11, 21, Ok(attr))));
+        assert_eq!(range.num_pages, 2);
+
+        // Gap in the virtual or physical address space.
+        assert!(!range.try_extend(&page(13, 22, Ok(attr))));
+        assert!(!range.try_extend(&page(12, 23, Ok(attr))));
+
+        // Different attributes.
+        let ro = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnly,
+            ..attr
+        };
+        assert!(!range.try_extend(&page(12, 22, Ok(ro))));
+        assert!(!range.try_extend(&page(12, 22, Err("Unexpected memory attribute"))));
+
+        assert!(range.contains(PageAddress::from(
+            11 * bsp::memory::mmu::KernelGranule::SIZE
+        )));
+        assert!(!range.contains(PageAddress::from(
+            12 * bsp::memory::mmu::KernelGranule::SIZE
+        )));
+    }
+}
@@ -15,7 +15,7 @@ use crate::memory::{Address, Physical, Virtual};
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 #[cfg(target_arch = "aarch64")]
-pub use arch_translation_table::FixedSizeTranslationTable;
+pub use arch_translation_table::{DynamicTranslationTable, FixedSizeTranslationTable};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -53,6 +53,34 @@ pub mod interface {
             attr: &AttributeFields,
         ) -> Result<(), &'static str>;
 
+        /// Unmap the given virtual memory region.
+        ///
+        /// Fails without changing anything if any page of the region is not mapped. Invalidating
+        /// stale TLB entries is the responsibility of the caller.
+        ///
+        /// # Safety
+        ///
+        /// - The region must not be accessed anymore, e.g. through references into it.
+        unsafe fn unmap_at(
+            &mut self,
+            virt_region: &MemoryRegion<Virtual>,
+        ) -> Result<(), &'static str>;
+
+        /// Change the attributes of the given, mapped virtual memory region.
+        ///
+        /// Fails without changing anything if any page of the region is not mapped, or if the
+        /// memory type would change, which needs a break-before-make sequence. Invalidating stale
+        /// TLB entries is the responsibility of the caller.
+        ///
+        /// # Safety
+        ///
+        /// - Same as `map_at()`.
+        unsafe fn protect_at(
+            &mut self,
+            virt_region: &MemoryRegion<Virtual>,
+            attr: &AttributeFields,
+        ) -> Result<(), &'static str>;
+
         /// Try to translate a virtual page address to a physical page address.
         ///
         /// Will only succeed if there exists a valid mapping for the input page.
@@ -113,7 +141,8 @@ mod tests {
         let attr = AttributeFields {
             mem_attributes: MemAttributes::CacheableDRAM,
             acc_perms: AccessPermissions::ReadWrite,
-            execute_never: true,
+            privileged_execute_never: true,
+            user_execute_never: true,
         };
 
         unsafe { assert_eq!(tables.map_at(&virt_region, &phys_region, &attr), Ok(())) };
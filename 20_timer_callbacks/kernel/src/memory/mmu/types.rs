@@ -8,7 +8,7 @@ use crate::{
     bsp, common,
     memory::{Address, AddressType, Physical},
 };
-use core::{convert::From, iter::Step, num::NonZeroUsize, ops::Range};
+use core::{convert::From, fmt, iter::Step, num::NonZeroUsize, ops::Range};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -28,28 +28,54 @@ pub struct MemoryRegion<ATYPE: AddressType> {
 }
 
 /// Architecture agnostic memory attributes.
-#[allow(missing_docs)]
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq)]
 pub enum MemAttributes {
+    /// Normal memory, write-back cacheable.
     CacheableDRAM,
+
+    /// Normal memory, write-through cacheable. Writes reach memory right away, reads are cached.
+    WriteThroughDRAM,
+
+    /// Normal memory that is not cached. For memory that is shared with devices, like DMA buffers
+    /// and framebuffers.
+    NonCacheableDRAM,
+
+    /// Device memory without gathering and reordering, but with early write acknowledgement
+    /// (nGnRE). The default for MMIO.
     Device,
+
+    /// Device memory that allows gathering, reordering and early write acknowledgement (GRE).
+    DeviceGRE,
 }
 
 /// Architecture agnostic access permissions.
+///
+/// The `User` variants additionally grant access to user space.
 #[allow(missing_docs)]
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq)]
 pub enum AccessPermissions {
     ReadOnly,
     ReadWrite,
+    ReadOnlyUser,
+    ReadWriteUser,
 }
 
 /// Collection of memory attributes.
-#[allow(missing_docs)]
+///
+/// Mappings must follow the policy enforced by [`AttributeFields::check_policy`].
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq)]
 pub struct AttributeFields {
+    /// The memory type.
     pub mem_attributes: MemAttributes,
+
+    /// The access permissions.
     pub acc_perms: AccessPermissions,
-    pub execute_never: bool,
+
+    /// The kernel must not execute from the mapping.
+    pub privileged_execute_never: bool,
+
+    /// User space must not execute from the mapping.
+    pub user_execute_never: bool,
 }
 
 /// An MMIO descriptor for use in device drivers.
@@ -271,6 +297,78 @@ impl From<MMIODescriptor> for MemoryRegion<Physical> {
     }
 }
 
+//------------------------------------------------------------------------------
+// AccessPermissions
+//------------------------------------------------------------------------------
+
+impl AccessPermissions {
+    /// Whether the permissions grant access to user space.
+    pub fn is_user_accessible(&self) -> bool {
+        matches!(self, Self::ReadOnlyUser | Self::ReadWriteUser)
+    }
+
+    /// Whether the permissions allow writes, at any privilege level.
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadWriteUser)
+    }
+}
+
+//------------------------------------------------------------------------------
+// AttributeFields
+//------------------------------------------------------------------------------
+
+impl AttributeFields {
+    /// Whether the mapping is executable, at any privilege level.
+    pub fn is_executable(&self) -> bool {
+        !self.privileged_execute_never || !self.user_execute_never
+    }
+
+    /// Check the attributes against the kernel's mapping policy.
+    ///
+    /// - No mapping is both writable and executable (W^X).
+    /// - The kernel never executes pages that user space can access.
+    /// - User space never executes kernel pages.
+    pub fn check_policy(&self) -> Result<(), &'static str> {
+        if self.acc_perms.is_writable() && self.is_executable() {
+            return Err("Mapping would be writable and executable");
+        }
+
+        if self.acc_perms.is_user_accessible() {
+            if !self.privileged_execute_never {
+                return Err("The kernel must not execute user pages");
+            }
+        } else if !self.user_execute_never {
+            return Err("User space must not execute kernel pages");
+        }
+
+        Ok(())
+    }
+}
+
+/// Short labels for memory type, access permissions and executability, padded to fixed widths.
+impl fmt::Display for AttributeFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attr = match self.mem_attributes {
+            MemAttributes::CacheableDRAM => "C",
+            MemAttributes::WriteThroughDRAM => "WT",
+            MemAttributes::NonCacheableDRAM => "NC",
+            MemAttributes::Device => "Dev",
+            MemAttributes::DeviceGRE => "GRE",
+        };
+
+        let acc_p = match self.acc_perms {
+            AccessPermissions::ReadOnly => "RO",
+            AccessPermissions::ReadWrite => "RW",
+            AccessPermissions::ReadOnlyUser => "URO",
+            AccessPermissions::ReadWriteUser => "URW",
+        };
+
+        let xn = if self.is_executable() { "X" } else { "XN" };
+
+        write!(f, "{:<3} {:<3} {:<2}", attr, acc_p, xn)
+    }
+}
+
 //------------------------------------------------------------------------------
 // MMIODescriptor
 //------------------------------------------------------------------------------
@@ -375,4 +473,59 @@ mod tests {
             );
         }
     }
+
+    /// Sanity of the mapping policy.
+    #[kernel_test]
+    fn attributefields_policy_sanity() {
+        let kernel_data = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        assert_eq!(kernel_data.check_policy(), Ok(()));
+
+        let kernel_code = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnly,
+            privileged_execute_never: false,
+            ..kernel_data
+        };
+        assert_eq!(kernel_code.check_policy(), Ok(()));
+
+        let user_code = AttributeFields {
+            acc_perms: AccessPermissions::ReadOnlyUser,
+            user_execute_never: false,
+            ..kernel_data
+        };
+        assert_eq!(user_code.check_policy(), Ok(()));
+
+        for wx in [
+            AttributeFields {
+                acc_perms: AccessPermissions::ReadWrite,
+                ..kernel_code
+            },
+            AttributeFields {
+                acc_perms: AccessPermissions::ReadWriteUser,
+                ..user_code
+            },
+        ] {
+            assert_eq!(
+                wx.check_policy(),
+                Err("Mapping would be writable and executable")
+            );
+        }
+
+        assert!(AttributeFields {
+            privileged_execute_never: false,
+            ..user_code
+        }
+        .check_policy()
+        .is_err());
+        assert!(AttributeFields {
+            user_execute_never: false,
+            ..kernel_code
+        }
+        .check_policy()
+        .is_err());
+    }
 }
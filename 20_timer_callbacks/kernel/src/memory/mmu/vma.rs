@@ -0,0 +1,463 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Demand paged virtual memory areas of the kernel.
+//!
+//! A VMA reserves a range of kernel virtual addresses without backing it with memory. The first
+//! access to a page results in a translation fault, which is resolved by allocating a zeroed frame
+//! and mapping it with the VMA's attributes.
+//!
+//! Kernel stacks are carved from the same virtual window. In contrast to VMAs, they are backed
+//! right away, and the page below each stack stays unmapped as a guard page.
+
+use super::{
+    page_alloc::PageAllocator, translation_table::interface::TranslationTable, AccessPermissions,
+    AttributeFields, MemAttributes, MemoryRegion, PageAddress,
+};
+use crate::{
+    bsp, cpu,
+    memory::{frame_alloc, Address, Physical, Virtual},
+    synchronization::{
+        interface::{Mutex, ReadWriteEx},
+        IRQSafeSpinLock,
+    },
+};
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct Vma {
+    name: &'static str,
+    virt_region: MemoryRegion<Virtual>,
+    attr: AttributeFields,
+}
+
+struct VmaList {
+    va_allocator: PageAllocator<Virtual>,
+
+    /// One page per core, through which fresh frames are zeroed before they become visible.
+    zeroing_pages: Option<MemoryRegion<Virtual>>,
+
+    vmas: Vec<Vma>,
+
+    /// Virtual slots of freed stacks, including their guard page.
+    free_stack_slots: Vec<MemoryRegion<Virtual>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KERNEL_VMAS: IRQSafeSpinLock<VmaList> = IRQSafeSpinLock::new(VmaList::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl VmaList {
+    const fn new() -> Self {
+        Self {
+            va_allocator: PageAllocator::new(),
+            zeroing_pages: None,
+            vmas: Vec::new(),
+            free_stack_slots: Vec::new(),
+        }
+    }
+
+    fn find(&self, addr: Address<Virtual>) -> Option<&Vma> {
+        self.vmas.iter().find(|vma| vma.virt_region.contains(addr))
+    }
+
+    /// Get a virtual slot of `num_pages`, preferably one of a freed stack.
+    fn alloc_stack_slot(
+        &mut self,
+        num_pages: NonZeroUsize,
+    ) -> Result<MemoryRegion<Virtual>, &'static str> {
+        match self
+            .free_stack_slots
+            .iter()
+            .position(|slot| slot.num_pages() == num_pages.get())
+        {
+            Some(i) => Ok(self.free_stack_slots.swap_remove(i)),
+            None => self.va_allocator.alloc(num_pages),
+        }
+    }
+}
+
+fn single_page_region<ATYPE: crate::memory::AddressType>(
+    page_addr: PageAddress<ATYPE>,
+) -> MemoryRegion<ATYPE> {
+    MemoryRegion::new(page_addr, page_addr.checked_offset(1).unwrap())
+}
+
+/// Zero a frame through the executing core's zeroing page.
+///
+/// # Safety
+///
+/// - The frame must not be mapped anywhere else yet.
+unsafe fn zero_frame(
+    zeroing_page: PageAddress<Virtual>,
+    frame: &MemoryRegion<Physical>,
+) -> Result<(), &'static str> {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        privileged_execute_never: true,
+        user_execute_never: true,
+    };
+    let virt_region = single_page_region(zeroing_page);
+
+    bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| tables.map_at(&virt_region, frame, &attr))?;
+
+    core::ptr::write_bytes(
+        zeroing_page.into_inner().as_usize() as *mut u8,
+        0,
+        virt_region.size(),
+    );
+
+    bsp::memory::mmu::kernel_translation_tables().write(|tables| tables.unmap_at(&virt_region))?;
+    super::kernel_invalidate_tlb(&virt_region);
+
+    Ok(())
+}
+
+/// Unmap a region that is backed by physically contiguous frames and return the frames.
+///
+/// # Safety
+///
+/// - The region must not be in use anymore.
+unsafe fn unmap_and_free_frames(virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+    let phys_start_page_addr = bsp::memory::mmu::kernel_translation_tables().read(|tables| {
+        tables.try_virt_page_addr_to_phys_page_addr(virt_region.start_page_addr())
+    })?;
+    let frames = MemoryRegion::new(
+        phys_start_page_addr,
+        phys_start_page_addr
+            .checked_offset(virt_region.num_pages() as isize)
+            .unwrap(),
+    );
+
+    bsp::memory::mmu::kernel_translation_tables().write(|tables| tables.unmap_at(virt_region))?;
+    super::kernel_invalidate_tlb(virt_region);
+
+    frame_alloc::kernel_frame_allocator().free_frames(&frames)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Initialize the VA allocator for VMAs and set aside the zeroing pages.
+pub fn kernel_init_va_allocator() {
+    let region = bsp::memory::mmu::virt_vma_region();
+
+    KERNEL_VMAS.lock(|list| {
+        list.va_allocator.init(region);
+
+        let num_cores = NonZeroUsize::new(bsp::cpu::NUM_CORES).unwrap();
+        list.zeroing_pages = list.va_allocator.alloc(num_cores).ok();
+    });
+}
+
+/// Reserve `num_pages` of kernel virtual address space that are backed on first access.
+pub fn kernel_register(
+    name: &'static str,
+    num_pages: NonZeroUsize,
+    attr: &AttributeFields,
+) -> Result<MemoryRegion<Virtual>, &'static str> {
+    if attr.mem_attributes != MemAttributes::CacheableDRAM
+        || attr.acc_perms != AccessPermissions::ReadWrite
+    {
+        return Err("Demand paged VMAs must be cacheable, kernel read-write memory");
+    }
+
+    KERNEL_VMAS.lock(|list| {
+        let virt_region = list.va_allocator.alloc(num_pages)?;
+
+        list.vmas.push(Vma {
+            name,
+            virt_region,
+            attr: *attr,
+        });
+
+        Ok(virt_region)
+    })
+}
+
+/// The name of the VMA that contains the address, if any.
+pub fn kernel_find(addr: Address<Virtual>) -> Option<&'static str> {
+    KERNEL_VMAS.lock(|list| list.find(addr).map(|vma| vma.name))
+}
+
+/// Resolve a translation fault by backing the faulting page with a zeroed frame.
+///
+/// Fails if the address is not part of a VMA.
+pub fn kernel_handle_translation_fault(addr: Address<Virtual>) -> Result<(), &'static str> {
+    let (attr, zeroing_pages) = KERNEL_VMAS.lock(|list| {
+        let vma = list.find(addr).ok_or("Address is not part of any VMA")?;
+        let zeroing_pages = list.zeroing_pages.ok_or("VMAs not initialized")?;
+
+        Ok((vma.attr, zeroing_pages))
+    })?;
+
+    let virt_page_addr = PageAddress::from(addr.align_down_page());
+    let zeroing_page = zeroing_pages
+        .start_page_addr()
+        .checked_offset(cpu::smp::core_id::<u8>() as isize)
+        .unwrap();
+
+    let frame = frame_alloc::kernel_frame_allocator().alloc_frames(
+        NonZeroUsize::new(1).unwrap(),
+        bsp::memory::mmu::KernelGranule::SIZE,
+    )?;
+
+    let result = unsafe {
+        zero_frame(zeroing_page, &frame).and_then(|_| {
+            bsp::memory::mmu::kernel_translation_tables().write(|tables| {
+                // Another core might have resolved the same fault in the meantime.
+                if tables
+                    .try_virt_page_addr_to_phys_page_addr(virt_page_addr)
+                    .is_ok()
+                {
+                    return Ok(false);
+                }
+
+                tables
+                    .map_at(&single_page_region(virt_page_addr), &frame, &attr)
+                    .map(|_| true)
+            })
+        })
+    };
+
+    if result != Ok(true) {
+        unsafe { frame_alloc::kernel_frame_allocator().free_frames(&frame)? };
+    }
+
+    result.map(|_| ())
+}
+
+/// Reserve `num_pages` of kernel virtual address space without backing it.
+pub fn kernel_reserve(num_pages: NonZeroUsize) -> Result<MemoryRegion<Virtual>, &'static str> {
+    KERNEL_VMAS.lock(|list| list.va_allocator.alloc(num_pages))
+}
+
+/// Back a reserved, unmapped region with physically contiguous frames.
+///
+/// # Safety
+///
+/// - The region must have been reserved with [`kernel_reserve`] and must not be mapped yet.
+pub unsafe fn kernel_map_fresh_frames(
+    virt_region: &MemoryRegion<Virtual>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    let num_pages = NonZeroUsize::new(virt_region.num_pages()).ok_or("Empty region")?;
+    let frames = frame_alloc::kernel_frame_allocator()
+        .alloc_frames(num_pages, bsp::memory::mmu::KernelGranule::SIZE)?;
+
+    let result = bsp::memory::mmu::kernel_translation_tables()
+        .write(|tables| tables.map_at(virt_region, &frames, attr));
+
+    if result.is_err() {
+        frame_alloc::kernel_frame_allocator().free_frames(&frames)?;
+    }
+
+    result
+}
+
+/// Back a reserved, unmapped region with one fresh frame per page.
+///
+/// In contrast to [`kernel_map_fresh_frames`], the frames need not be physically contiguous. On
+/// failure, the pages that were already backed are unmapped and their frames returned.
+///
+/// # Safety
+///
+/// - The region must have been reserved with [`kernel_reserve`] and must not be mapped yet.
+pub unsafe fn kernel_map_fresh_pages(
+    virt_region: &MemoryRegion<Virtual>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    for (i, virt_page_addr) in virt_region.into_iter().enumerate() {
+        let page = single_page_region(virt_page_addr);
+
+        if let Err(x) = kernel_map_fresh_frames(&page, attr) {
+            if i > 0 {
+                let mapped = MemoryRegion::new(virt_region.start_page_addr(), virt_page_addr);
+
+                for virt_page_addr in mapped.into_iter() {
+                    unmap_and_free_frames(&single_page_region(virt_page_addr))?;
+                }
+            }
+
+            return Err(x);
+        }
+    }
+
+    Ok(())
+}
+
+/// Allocate a kernel stack of `num_pages` that is backed by physically contiguous frames.
+///
+/// The page below the returned region is left unmapped, so that overflowing the stack faults.
+pub fn kernel_alloc_stack(num_pages: NonZeroUsize) -> Result<MemoryRegion<Virtual>, &'static str> {
+    let num_slot_pages = num_pages.checked_add(1).ok_or("Stack too large")?;
+    let slot = KERNEL_VMAS.lock(|list| list.alloc_stack_slot(num_slot_pages))?;
+
+    let start_page_addr = slot.start_page_addr().checked_offset(1).unwrap();
+    let virt_region = MemoryRegion::new(start_page_addr, slot.end_exclusive_page_addr());
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        privileged_execute_never: true,
+        user_execute_never: true,
+    };
+
+    if let Err(x) = unsafe { kernel_map_fresh_frames(&virt_region, &attr) } {
+        KERNEL_VMAS.lock(|list| list.free_stack_slots.push(slot));
+        return Err(x);
+    }
+
+    Ok(virt_region)
+}
+
+/// Unmap a stack that was allocated with [`kernel_alloc_stack`] and return its frames.
+///
+/// # Safety
+///
+/// - The stack must not be in use anymore.
+pub unsafe fn kernel_free_stack(virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+    unmap_and_free_frames(virt_region)?;
+
+    let slot = MemoryRegion::new(
+        virt_region.start_page_addr().checked_offset(-1).unwrap(),
+        virt_region.end_exclusive_page_addr(),
+    );
+    KERNEL_VMAS.lock(|list| list.free_stack_slots.push(slot));
+
+    Ok(())
+}
+
+/// Map fresh, physically contiguous frames to newly reserved virtual addresses.
+///
+/// In contrast to VMA pages, the frames are not zeroed.
+pub fn kernel_alloc_contiguous(
+    num_pages: NonZeroUsize,
+    attr: &AttributeFields,
+) -> Result<MemoryRegion<Virtual>, &'static str> {
+    let virt_region = kernel_reserve(num_pages)?;
+
+    if let Err(x) = unsafe { kernel_map_fresh_frames(&virt_region, attr) } {
+        KERNEL_VMAS.lock(|list| list.va_allocator.free(virt_region))?;
+        return Err(x);
+    }
+
+    Ok(virt_region)
+}
+
+/// Unmap a region that was allocated with [`kernel_alloc_contiguous`] and return its frames and
+/// virtual addresses.
+///
+/// # Safety
+///
+/// - The region must not be in use anymore.
+pub unsafe fn kernel_free_contiguous(
+    virt_region: &MemoryRegion<Virtual>,
+) -> Result<(), &'static str> {
+    unmap_and_free_frames(virt_region)?;
+
+    KERNEL_VMAS.lock(|list| list.va_allocator.free(*virt_region))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Touching a VMA page backs it with exactly one zeroed frame.
+    #[kernel_test]
+    fn vma_page_is_backed_on_first_access() {
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+        let region = kernel_register("test", NonZeroUsize::new(4).unwrap(), &attr).unwrap();
+        assert_eq!(kernel_find(region.start_addr()), Some("test"));
+
+        let page_addr = region.start_page_addr().checked_offset(2).unwrap();
+        let ptr = (page_addr.into_inner().as_usize() + 8) as *mut u64;
+        let (used_before, _) = frame_alloc::kernel_frame_allocator().usage();
+
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(0x1234);
+            assert_eq!(ptr.read_volatile(), 0x1234);
+        }
+
+        assert_eq!(
+            frame_alloc::kernel_frame_allocator().usage().0,
+            used_before + 1
+        );
+        assert!(
+            crate::memory::mmu::try_kernel_virt_page_addr_to_phys_page_addr(
+                region.start_page_addr()
+            )
+            .is_err()
+        );
+    }
+
+    /// Stacks are backed, have an unmapped guard page and their slots are reused.
+    #[kernel_test]
+    fn stack_has_guard_page() {
+        let num_pages = NonZeroUsize::new(2).unwrap();
+        let (used_before, _) = frame_alloc::kernel_frame_allocator().usage();
+
+        let stack = kernel_alloc_stack(num_pages).unwrap();
+        assert_eq!(stack.num_pages(), 2);
+        assert_eq!(
+            frame_alloc::kernel_frame_allocator().usage().0,
+            used_before + 2
+        );
+
+        let guard_page_addr = stack.start_page_addr().checked_offset(-1).unwrap();
+        assert!(
+            crate::memory::mmu::try_kernel_virt_page_addr_to_phys_page_addr(guard_page_addr)
+                .is_err()
+        );
+
+        let ptr = stack.start_addr().as_usize() as *mut u64;
+        unsafe {
+            ptr.write_volatile(0x5678);
+            assert_eq!(ptr.read_volatile(), 0x5678);
+
+            assert_eq!(kernel_free_stack(&stack), Ok(()));
+        }
+        assert_eq!(frame_alloc::kernel_frame_allocator().usage().0, used_before);
+
+        let again = kernel_alloc_stack(num_pages).unwrap();
+        assert_eq!(again.start_addr(), stack.start_addr());
+        unsafe { kernel_free_stack(&again).unwrap() };
+    }
+
+    /// Read-only VMAs can not be demand paged.
+    #[kernel_test]
+    fn vma_rejects_read_only() {
+        let attr = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadOnly,
+            privileged_execute_never: true,
+            user_execute_never: true,
+        };
+
+        assert!(kernel_register("test", NonZeroUsize::new(1).unwrap(), &attr).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! User processes.
+//!
+//! A process is a kernel thread that drops to EL0 and executes its entry function there, on a stack
+//! of its own. It talks to the kernel exclusively through system calls.
+//!
+//! User programs are built from the `user_programs` crate, separately from the kernel. Their flat
+//! images are embedded into the kernel with [`include_user_program`](crate::include_user_program)
+//! and executed in place.
+//!
+//! There are no separate address spaces yet. The images live in the `.user_programs` section, which
+//! together with `.user_text` are the only parts of the kernel image that are executable from EL0.
+//! User stacks are carved out of the `.user_stacks` section.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/process.rs"]
+mod arch_process;
+
+pub mod syscall;
+
+use crate::{
+    bsp, info,
+    memory::{Address, Virtual},
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
+    task::{self, ThreadId},
+    warn,
+};
+use alloc::vec::Vec;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const USER_STACK_SIZE: usize = 64 * 1024;
+
+/// Limited by the width of the stack slot bitmap.
+const MAX_STACK_SLOTS: usize = u64::BITS as usize;
+
+struct Process {
+    thread: ThreadId,
+    name: &'static str,
+    image: &'static [u8],
+    stack_slot: usize,
+}
+
+struct ProcessTable {
+    processes: Vec<Process>,
+
+    /// Bitmap of the user stacks that are in use.
+    used_stack_slots: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The flat image of a user program. Execution starts at its first byte.
+///
+/// Position independent code addresses data relative to the 4 KiB page it executes from, so images
+/// must keep the page offset they were linked with.
+#[repr(C, align(4096))]
+pub struct UserImage<T: ?Sized>(pub T);
+
+/// Embed the image of a program from the `user_programs` crate.
+///
+/// Evaluates to a `&'static UserImage<[u8]>`. The `USER_PROGRAMS_PATH` environment variable must
+/// point to the directory containing the images at compile time.
+#[macro_export]
+macro_rules! include_user_program {
+    ($name:literal) => {{
+        const SIZE: usize =
+            include_bytes!(concat!(env!("USER_PROGRAMS_PATH"), "/", $name, ".img")).len();
+
+        #[link_section = ".user_programs"]
+        static IMAGE: $crate::process::UserImage<[u8; SIZE]> = $crate::process::UserImage(
+            *include_bytes!(concat!(env!("USER_PROGRAMS_PATH"), "/", $name, ".img")),
+        );
+
+        &IMAGE as &'static $crate::process::UserImage<[u8]>
+    }};
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static PROCESS_TABLE: IRQSafeSpinLock<ProcessTable> = IRQSafeSpinLock::new(ProcessTable::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ProcessTable {
+    const fn new() -> Self {
+        Self {
+            processes: Vec::new(),
+            used_stack_slots: 0,
+        }
+    }
+
+    fn num_stack_slots() -> usize {
+        let num = bsp::memory::mmu::virt_user_stacks_region().size() / USER_STACK_SIZE;
+
+        num.min(MAX_STACK_SLOTS)
+    }
+
+    fn alloc_stack_slot(&mut self) -> Result<usize, &'static str> {
+        let slot = (0..Self::num_stack_slots())
+            .find(|i| self.used_stack_slots & (1 << i) == 0)
+            .ok_or("No free user stack")?;
+
+        self.used_stack_slots |= 1 << slot;
+
+        Ok(slot)
+    }
+
+    /// Remove the process that runs on the given thread and free its stack.
+    fn remove(&mut self, thread: ThreadId) -> Option<Process> {
+        let index = self.processes.iter().position(|p| p.thread == thread)?;
+        let process = self.processes.remove(index);
+
+        self.used_stack_slots &= !(1 << process.stack_slot);
+
+        Some(process)
+    }
+}
+
+impl Process {
+    /// Checks if the given range is part of the image or the stack of this process.
+    fn contains(&self, start: usize, end_exclusive: usize) -> bool {
+        let image = self.image.as_ptr_range();
+        let stack_top = user_stack_top(self.stack_slot).as_usize();
+
+        [
+            (image.start as usize, image.end as usize),
+            (stack_top - USER_STACK_SIZE, stack_top),
+        ]
+        .iter()
+        .any(|&(region_start, region_end)| region_start <= start && end_exclusive <= region_end)
+    }
+}
+
+/// The initial stack pointer for the given stack slot.
+fn user_stack_top(stack_slot: usize) -> Address<Virtual> {
+    let start = bsp::memory::mmu::virt_user_stacks_region().start_addr();
+
+    start + (stack_slot + 1) * USER_STACK_SIZE
+}
+
+/// Remove the process that runs on the current thread.
+fn remove_current() -> Option<Process> {
+    let id = task::current_id()?;
+
+    PROCESS_TABLE.lock(|table| table.remove(id))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Spawn a new process that executes `image` in user space.
+///
+/// Returns the ID of the thread that runs the process.
+pub fn spawn(
+    name: &'static str,
+    image: &'static UserImage<[u8]>,
+) -> Result<ThreadId, &'static str> {
+    let image = &image.0;
+    let entry_addr = Address::<Virtual>::new(image.as_ptr() as usize);
+    let user_code = bsp::memory::mmu::virt_user_code_region();
+
+    if image.is_empty()
+        || !user_code.contains(entry_addr)
+        || !user_code.contains(entry_addr + (image.len() - 1))
+    {
+        return Err("Image is not part of the user code region");
+    }
+
+    // The lock masks IRQs, so the new thread can not start before its process is recorded.
+    PROCESS_TABLE.lock(|table| {
+        let stack_slot = table.alloc_stack_slot()?;
+        let stack_top = user_stack_top(stack_slot);
+
+        let thread = task::spawn(name, move || unsafe {
+            arch_process::enter_user(entry_addr, stack_top)
+        });
+
+        table.processes.push(Process {
+            thread,
+            name,
+            image,
+            stack_slot,
+        });
+
+        Ok(thread)
+    })
+}
+
+/// Checks if the given range is part of the image or the stack of the calling process.
+///
+/// Without separate address spaces, EL0 can access the stacks of other processes, too. System calls
+/// must not help with that.
+pub fn is_current_process_range(start: Address<Virtual>, size: usize) -> bool {
+    let end_exclusive = match start.as_usize().checked_add(size) {
+        None => return false,
+        Some(x) => x,
+    };
+
+    let id = match task::current_id() {
+        None => return false,
+        Some(x) => x,
+    };
+
+    PROCESS_TABLE.lock(|table| {
+        table
+            .processes
+            .iter()
+            .find(|p| p.thread == id)
+            .map_or(false, |p| p.contains(start.as_usize(), end_exclusive))
+    })
+}
+
+/// Terminate the current process with the given exit code.
+pub fn exit_current(code: u64) -> ! {
+    if let Some(process) = remove_current() {
+        info!("Process '{}' exited with code {}", process.name, code);
+    }
+
+    task::exit()
+}
+
+/// Terminate the current process because it caused a fault.
+pub fn kill_current() -> ! {
+    if let Some(process) = remove_current() {
+        warn!("Process '{}' killed", process.name);
+    }
+
+    task::exit()
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! System calls.
+//!
+//! The calling convention and the system call numbers are shared with user programs through the
+//! `syscall-types` library.
+
+use crate::{memory::Address, print, task, time, warn};
+use core::{slice, str, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+type Handler = fn(&[u64; 6]) -> Result<u64, &'static str>;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub use syscall_types::{nr, ERROR};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Indexed by the system call number.
+static SYSCALL_TABLE: [Handler; 4] = [sys_write, sys_uptime, sys_sleep, sys_exit];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn sys_write(args: &[u64; 6]) -> Result<u64, &'static str> {
+    let start = Address::new(args[0] as usize);
+    let len = args[1] as usize;
+
+    if !super::is_current_process_range(start, len) {
+        return Err("Buffer not part of the calling process");
+    }
+
+    let buf = unsafe { slice::from_raw_parts(start.as_usize() as *const u8, len) };
+    let s = str::from_utf8(buf).map_err(|_| "Buffer is not valid UTF-8")?;
+
+    print!("{}", s);
+
+    Ok(len as u64)
+}
+
+fn sys_uptime(_args: &[u64; 6]) -> Result<u64, &'static str> {
+    Ok(time::time_manager().uptime().as_nanos() as u64)
+}
+
+fn sys_sleep(args: &[u64; 6]) -> Result<u64, &'static str> {
+    const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+    if args[1] >= NANOS_PER_SEC {
+        return Err("Invalid duration");
+    }
+
+    // The wake-up time must be representable, otherwise the thread would never wake up.
+    let duration = Duration::new(args[0], args[1] as u32);
+    let now = time::time_manager().uptime();
+    if now.checked_add(duration).is_none() {
+        return Err("Duration too long");
+    }
+
+    task::sleep(duration);
+
+    Ok(0)
+}
+
+fn sys_exit(args: &[u64; 6]) -> Result<u64, &'static str> {
+    super::exit_current(args[0])
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Execute the system call with the given number and arguments.
+///
+/// Returns the value that shall be passed back to user space.
+pub fn dispatch(nr: u64, args: &[u64; 6]) -> u64 {
+    let handler = match SYSCALL_TABLE.get(nr as usize) {
+        None => {
+            warn!("Unknown system call: {}", nr);
+            return ERROR;
+        }
+        Some(x) => x,
+    };
+
+    match handler(args) {
+        Err(x) => {
+            warn!("System call {} failed: {}", nr, x);
+            ERROR
+        }
+        Ok(x) => x,
+    }
+}
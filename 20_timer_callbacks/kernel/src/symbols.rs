@@ -4,7 +4,7 @@
 
 //! Debug symbol support.
 
-use crate::memory::{Address, Virtual};
+use crate::memory::{kaslr, Address, Virtual};
 use core::{cell::UnsafeCell, slice};
 use debug_symbol_types::Symbol;
 
@@ -53,10 +53,16 @@ fn kernel_symbols_slice() -> &'static [Symbol] {
 //--------------------------------------------------------------------------------------------------
 
 /// Retrieve the symbol corresponding to a virtual address, if any.
-pub fn lookup_symbol(addr: Address<Virtual>) -> Option<&'static Symbol> {
+///
+/// The symbols were generated from the link addresses, so they are relocated by the KASLR offset.
+pub fn lookup_symbol(addr: Address<Virtual>) -> Option<Symbol> {
+    let offset = kaslr::offset();
+    let link_addr = addr.as_usize().checked_sub(offset)?;
+
     kernel_symbols_slice()
         .iter()
-        .find(|&i| i.contains(addr.as_usize()))
+        .find(|&i| i.contains(link_addr))
+        .map(|sym| unsafe { sym.relocate(offset) })
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -71,13 +77,11 @@ mod tests {
     /// Sanity of symbols module.
     #[kernel_test]
     fn symbols_sanity() {
-        let first_sym = lookup_symbol(Address::new(
-            crate::common::is_aligned as *const usize as usize,
-        ))
-        .unwrap()
-        .name();
+        let first_addr = Address::new(crate::common::is_aligned as *const usize as usize);
+        let first_sym = lookup_symbol(first_addr).unwrap();
 
-        assert_eq!(first_sym, "libkernel::common::is_aligned");
+        assert_eq!(first_sym.name(), "libkernel::common::is_aligned");
+        assert!(first_sym.contains(first_addr.as_usize()));
 
         let second_sym = lookup_symbol(Address::new(crate::version as *const usize as usize))
             .unwrap()
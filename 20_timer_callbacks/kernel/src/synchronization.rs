@@ -10,7 +10,11 @@
 //!   - <https://stackoverflow.com/questions/59428096/understanding-the-send-trait>
 //!   - <https://doc.rust-lang.org/std/cell/index.html>
 
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -51,7 +55,8 @@ pub mod interface {
 /// other cores to the contained data. This part is preserved for later lessons.
 ///
 /// The lock will only be used as long as it is safe to do so, i.e. as long as the kernel is
-/// executing on a single core.
+/// executing on a single core. Locking it once the secondary cores run is a bug and panics, so data
+/// that is shared with them must use [`IRQSafeSpinLock`].
 pub struct IRQSafeNullLock<T>
 where
     T: ?Sized,
@@ -69,6 +74,38 @@ where
     data: UnsafeCell<T>,
 }
 
+/// A spinning mutex that masks IRQs on the executing core while it is held.
+///
+/// Protects against concurrent access from other cores as well as from IRQ handlers on the same
+/// core. The lock is not reentrant. Locking it again from within the closure deadlocks.
+pub struct IRQSafeSpinLock<T>
+where
+    T: ?Sized,
+{
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+/// A spinning reader-writer lock that masks IRQs on the executing core while it is held.
+///
+/// In contrast to [`InitStateLock`], writing is possible at any time. Like [`IRQSafeSpinLock`],
+/// the lock is not reentrant for writers.
+///
+/// A reader may unmask IRQs from within the closure, as IRQ handlers that allow nesting do. This
+/// is safe for readers in nested IRQ handlers, because the lock prefers readers. It comes at a
+/// price for writers though: They wait until all readers are done, including preempted ones, and
+/// taking the write lock from a nested IRQ handler on the same core deadlocks.
+///
+/// The data is guaranteed to be placed at offset zero, so that the address of a static instance
+/// is also the address of the data.
+#[repr(C)]
+pub struct RwSpinLock<T> {
+    data: UnsafeCell<T>,
+
+    /// Either [`RwSpinLock::WRITER`], or the number of readers.
+    state: AtomicUsize,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -97,6 +134,42 @@ impl<T> InitStateLock<T> {
     }
 }
 
+unsafe impl<T> Send for IRQSafeSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for IRQSafeSpinLock<T> where T: ?Sized + Send {}
+
+impl<T> IRQSafeSpinLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+unsafe impl<T> Send for RwSpinLock<T> where T: Send {}
+unsafe impl<T> Sync for RwSpinLock<T> where T: Send + Sync {}
+
+impl<T> RwSpinLock<T> {
+    const WRITER: usize = usize::MAX;
+
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    /// Raw pointer to the wrapped data, bypassing the lock.
+    ///
+    /// Needed during early boot, when the MMU is off and atomic read-modify-write operations are
+    /// not available yet.
+    pub const fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -106,6 +179,11 @@ impl<T> interface::Mutex for IRQSafeNullLock<T> {
     type Data = T;
 
     fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        assert!(
+            !state::state_manager().is_multi_core_main(),
+            "IRQSafeNullLock::lock called while multiple cores are running"
+        );
+
         // In a real lock, there would be code encapsulating this line that ensures that this
         // mutable reference will ever only be given out once at a time.
         let data = unsafe { &mut *self.data.get() };
@@ -115,6 +193,88 @@ impl<T> interface::Mutex for IRQSafeNullLock<T> {
     }
 }
 
+impl<T> interface::Mutex for IRQSafeSpinLock<T> {
+    type Data = T;
+
+    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        // Mask IRQs before taking the lock, so that a handler on this core can not spin on it
+        // forever.
+        exception::asynchronous::exec_with_irq_masked(|| {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                // Wait with plain loads, which do not claim exclusive ownership of the cache line.
+                while self.locked.load(Ordering::Relaxed) {
+                    hint::spin_loop();
+                }
+            }
+
+            let data = unsafe { &mut *self.data.get() };
+            let ret = f(data);
+
+            self.locked.store(false, Ordering::Release);
+
+            ret
+        })
+    }
+}
+
+impl<T> interface::ReadWriteEx for RwSpinLock<T> {
+    type Data = T;
+
+    fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            while self
+                .state
+                .compare_exchange_weak(0, Self::WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                hint::spin_loop();
+            }
+
+            let data = unsafe { &mut *self.data.get() };
+            let ret = f(data);
+
+            self.state.store(0, Ordering::Release);
+
+            ret
+        })
+    }
+
+    fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            loop {
+                let readers = self.state.load(Ordering::Relaxed);
+
+                if readers != Self::WRITER
+                    && self
+                        .state
+                        .compare_exchange_weak(
+                            readers,
+                            readers + 1,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                {
+                    break;
+                }
+
+                hint::spin_loop();
+            }
+
+            let data = unsafe { &*self.data.get() };
+            let ret = f(data);
+
+            self.state.fetch_sub(1, Ordering::Release);
+
+            ret
+        })
+    }
+}
+
 impl<T> interface::ReadWriteEx for InitStateLock<T> {
     type Data = T;
 
@@ -156,4 +316,43 @@ mod tests {
 
         assert_eq!(size_of::<InitStateLock<u64>>(), size_of::<u64>());
     }
+
+    /// RwSpinLock must place the data first.
+    #[kernel_test]
+    fn rw_spin_lock_data_at_offset_zero() {
+        use interface::ReadWriteEx;
+
+        let lock = RwSpinLock::new(0_u64);
+        let data_addr = lock.read(|data| data as *const u64 as usize);
+
+        assert_eq!(data_addr, &lock as *const _ as usize);
+    }
+
+    /// The spin lock is released after the closure returned.
+    #[kernel_test]
+    fn irq_safe_spin_lock_is_released() {
+        use interface::Mutex;
+
+        let lock = IRQSafeSpinLock::new(0);
+
+        lock.lock(|data| *data += 1);
+        lock.lock(|data| *data += 1);
+
+        assert_eq!(lock.lock(|data| *data), 2);
+    }
+
+    /// Readers can nest, and a writer gets in once they are gone.
+    #[kernel_test]
+    fn rw_spin_lock_readers_and_writer() {
+        use interface::ReadWriteEx;
+
+        let lock = RwSpinLock::new(1);
+
+        let sum = lock.read(|outer| lock.read(|inner| outer + inner));
+        assert_eq!(sum, 2);
+
+        lock.write(|data| *data = 5);
+        assert_eq!(lock.read(|data| *data), 5);
+        assert_eq!(lock.state.load(Ordering::Relaxed), 0);
+    }
 }
@@ -0,0 +1,405 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel threads.
+//!
+//! Threads are scheduled round-robin. Preemption is driven by a periodic timeout of the timer
+//! subsystem, which requests a reschedule that is carried out at the end of IRQ handling.
+//!
+//! The flow of execution that booted the kernel is turned into the thread `main`, which keeps
+//! running on the boot-core stack. All other threads get their own stack, with an unmapped guard
+//! page below it.
+//!
+//! Threads are only scheduled on the boot core. Secondary cores run the function they were started
+//! with, and do not have a current thread.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/task.rs"]
+mod arch_task;
+
+mod sched;
+
+use crate::{
+    bsp, cpu, exception, info,
+    memory::{
+        mmu::{self, address_space::AddressSpace, MemoryRegion},
+        Address, Virtual,
+    },
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
+    time,
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    fmt,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use sched::{Scheduler, SwitchReason};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const THREAD_STACK_SIZE: usize = 64 * 1024;
+
+/// The time a thread may run before it is preempted.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+/// The function a thread executes.
+type ThreadEntry = Box<dyn FnOnce() + Send>;
+
+/// A guarded thread stack.
+struct Stack {
+    virt_region: MemoryRegion<Virtual>,
+}
+
+/// A kernel thread.
+struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    context: arch_task::Context,
+
+    /// `None` for the `main` thread, which runs on the boot-core stack.
+    stack: Option<Stack>,
+
+    /// Taken by the thread itself when it starts running.
+    entry: Option<ThreadEntry>,
+
+    /// The lower half address space. `None` for threads that only use kernel memory.
+    address_space: Option<Arc<AddressSpace>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A unique thread identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ThreadId(u64);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SCHEDULER: IRQSafeSpinLock<Scheduler> = IRQSafeSpinLock::new(Scheduler::new());
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Set by the time slice timeout, consumed at the end of IRQ handling.
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+/// Stack bounds of the running thread. Kept outside of the scheduler lock so that backtraces can be
+/// generated from any context.
+static CUR_STACK_START: AtomicUsize = AtomicUsize::new(0);
+static CUR_STACK_END_EXCLUSIVE: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ThreadId {
+    fn new() -> Self {
+        Self(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Stack {
+    fn new() -> Self {
+        let num_pages = THREAD_STACK_SIZE.div_ceil(bsp::memory::mmu::KernelGranule::SIZE);
+
+        match mmu::kernel_alloc_stack(NonZeroUsize::new(num_pages).unwrap()) {
+            Err(x) => panic!("Allocating a thread stack failed: {}", x),
+            Ok(virt_region) => Self { virt_region },
+        }
+    }
+
+    fn start_addr(&self) -> Address<Virtual> {
+        self.virt_region.start_addr()
+    }
+
+    fn end_addr_exclusive(&self) -> Address<Virtual> {
+        self.virt_region.end_exclusive_page_addr().into_inner()
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        unsafe { mmu::kernel_free_stack(&self.virt_region).unwrap() }
+    }
+}
+
+impl Thread {
+    /// Create a thread for the flow of execution that is already running.
+    fn new_running(name: &'static str) -> Self {
+        Self {
+            id: ThreadId::new(),
+            name,
+            context: arch_task::Context::new(),
+            stack: None,
+            entry: None,
+            address_space: None,
+        }
+    }
+
+    /// Create a thread that starts executing `entry` on a fresh stack.
+    fn new(name: &'static str, entry: ThreadEntry) -> Self {
+        let stack = Stack::new();
+        let context = arch_task::Context::new_for_entry(stack.end_addr_exclusive(), thread_entry);
+
+        Self {
+            id: ThreadId::new(),
+            name,
+            context,
+            stack: Some(stack),
+            entry: Some(entry),
+            address_space: None,
+        }
+    }
+}
+
+/// Publish the stack bounds of the thread that is about to run.
+fn set_current_stack(thread: &Thread) {
+    let (start, end) = match &thread.stack {
+        None => (0, 0),
+        Some(stack) => (
+            stack.start_addr().as_usize(),
+            stack.end_addr_exclusive().as_usize(),
+        ),
+    };
+
+    CUR_STACK_START.store(start, Ordering::Relaxed);
+    CUR_STACK_END_EXCLUSIVE.store(end, Ordering::Relaxed);
+}
+
+/// Install the address space of the thread that is about to run.
+fn set_current_address_space(thread: &Thread) {
+    match &thread.address_space {
+        None => unsafe { AddressSpace::deactivate() },
+        Some(space) => unsafe { space.activate() },
+    }
+}
+
+/// Checks if the executing core is the one that runs the threads.
+fn is_scheduling_core() -> bool {
+    cpu::smp::core_id::<u64>() == bsp::cpu::BOOT_CORE_ID
+}
+
+/// Give up the CPU.
+///
+/// Does nothing on secondary cores.
+fn schedule(reason: SwitchReason) {
+    if !is_scheduling_core() {
+        return;
+    }
+
+    exception::asynchronous::exec_with_irq_masked(|| {
+        let contexts = SCHEDULER.lock(|sched| {
+            if !sched.is_initialized() {
+                return None;
+            }
+
+            let contexts = sched.switch_prepare(reason)?;
+            set_current_stack(sched.current());
+            set_current_address_space(sched.current());
+
+            Some(contexts)
+        });
+
+        // Important: Switch while not holding the scheduler lock. The incoming thread resumes
+        // wherever it gave up the CPU, which is outside of the lock as well.
+        if let Some(contexts) = contexts {
+            unsafe { arch_task::switch(contexts.prev, contexts.next) };
+        }
+    });
+}
+
+/// The first function executed by every spawned thread.
+extern "C" fn thread_entry() -> ! {
+    let entry = SCHEDULER.lock(|sched| sched.current_mut().entry.take());
+
+    // A new thread is always switched to with IRQs masked, either from IRQ context or from
+    // `schedule()`. Since it does not return through either of them, it must unmask IRQs itself.
+    // Exception entry masks FIQs as well.
+    exception::asynchronous::local_irq_unmask();
+    exception::asynchronous::local_fiq_unmask();
+
+    if let Some(entry) = entry {
+        entry();
+    }
+
+    exit()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Spawn a new kernel thread that executes `f`.
+///
+/// The thread is appended to the run queue and will be scheduled at the next yield or preemption.
+/// It exits once `f` returns.
+pub fn spawn(name: &'static str, f: impl FnOnce() + Send + 'static) -> ThreadId {
+    let thread = Box::new(Thread::new(name, Box::new(f)));
+    let id = thread.id;
+
+    SCHEDULER.lock(|sched| sched.enqueue(thread));
+
+    id
+}
+
+/// Give up the rest of the current time slice.
+///
+/// Returns immediately if there is no other runnable thread.
+pub fn yield_now() {
+    schedule(SwitchReason::Yield);
+}
+
+/// Terminate the current thread.
+pub fn exit() -> ! {
+    schedule(SwitchReason::Exit);
+
+    panic!("Exited thread was scheduled again")
+}
+
+/// Block the current thread for at least the given duration.
+///
+/// Falls back to spinning if the task subsystem is not initialized yet.
+pub fn sleep(duration: Duration) {
+    let id = match current_id() {
+        None => {
+            time::time_manager().spin_for(duration);
+            return;
+        }
+        Some(id) => id,
+    };
+
+    // Mask IRQs so that the timeout can not fire before the thread is actually blocked.
+    exception::asynchronous::exec_with_irq_masked(|| {
+        time::time_manager().set_timeout_once(duration, Box::new(move || wake(id)));
+
+        schedule(SwitchReason::Block);
+    });
+}
+
+/// Wake up a blocked thread.
+///
+/// The woken thread is appended to the run queue. A reschedule is requested so that it does not
+/// have to wait for the end of the current time slice if the CPU is idling.
+pub fn wake(id: ThreadId) {
+    if SCHEDULER.lock(|sched| sched.wake(id)) {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Switch the current thread to the given lower half address space.
+///
+/// The address space is installed whenever the thread is scheduled. `None` removes the lower half
+/// address space.
+///
+/// # Safety
+///
+/// - References into the previous lower half address space become invalid.
+pub unsafe fn set_address_space(space: Option<Arc<AddressSpace>>) {
+    if !is_scheduling_core() {
+        return;
+    }
+
+    SCHEDULER.lock(|sched| {
+        if !sched.is_initialized() {
+            return;
+        }
+
+        let current = sched.current_mut();
+        current.address_space = space;
+        set_current_address_space(current);
+    });
+}
+
+/// Return the ID of the current thread.
+///
+/// Returns `None` on secondary cores.
+pub fn current_id() -> Option<ThreadId> {
+    if !is_scheduling_core() {
+        return None;
+    }
+
+    SCHEDULER.lock(|sched| sched.is_initialized().then(|| sched.current().id))
+}
+
+/// Checks if the address is part of the current thread's stack.
+///
+/// The `main` thread runs on the boot-core stack, for which this function returns `false`.
+pub fn is_current_stack_addr(addr: Address<Virtual>) -> bool {
+    let start = CUR_STACK_START.load(Ordering::Relaxed);
+    let end_exclusive = CUR_STACK_END_EXCLUSIVE.load(Ordering::Relaxed);
+
+    is_scheduling_core() && (start..end_exclusive).contains(&addr.as_usize())
+}
+
+/// Preempt the current thread if its time slice has run out.
+///
+/// Must be called at the very end of IRQ handling, after the interrupt controller was told that
+/// handling is complete. The IRQContext token ensures this.
+///
+/// Nested IRQs leave the reschedule to the handler they preempted, which still has to complete.
+pub fn preempt_if_needed(ic: &exception::asynchronous::IRQContext) {
+    if exception::asynchronous::is_nested(ic) || !is_scheduling_core() {
+        return;
+    }
+
+    if NEED_RESCHED.swap(false, Ordering::Relaxed) {
+        schedule(SwitchReason::Yield);
+    }
+}
+
+/// Print the list of threads.
+pub fn print_threads() {
+    SCHEDULER.lock(|sched| {
+        if !sched.is_initialized() {
+            return;
+        }
+
+        sched.for_each_thread(|thread, is_current| {
+            info!(
+                "      {:>3}. {:<10}{}",
+                thread.id,
+                thread.name,
+                if is_current { " (running)" } else { "" }
+            );
+        });
+    });
+}
+
+/// Initialize the task subsystem.
+///
+/// Turns the calling flow of execution into the thread `main`, creates the idle thread and starts
+/// preemption.
+///
+/// Must be called after the timer subsystem was initialized.
+pub fn init() -> Result<(), &'static str> {
+    static INIT_DONE: AtomicBool = AtomicBool::new(false);
+    if INIT_DONE.load(Ordering::Relaxed) {
+        return Err("Init already done");
+    }
+
+    let main = Box::new(Thread::new_running("main"));
+    let idle = Box::new(Thread::new("idle", Box::new(|| cpu::wait_forever())));
+
+    SCHEDULER.lock(|sched| sched.init(main, idle));
+
+    time::time_manager().set_timeout_periodic(
+        TIME_SLICE,
+        Box::new(|| NEED_RESCHED.store(true, Ordering::Relaxed)),
+    );
+
+    INIT_DONE.store(true, Ordering::Relaxed);
+    Ok(())
+}
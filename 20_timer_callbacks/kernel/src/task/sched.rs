@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Round-robin scheduler.
+
+use super::{arch_task::Context, Thread, ThreadId};
+use alloc::{boxed::Box, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The reason for giving up the CPU.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SwitchReason {
+    /// The current thread stays runnable and is put at the end of the run queue.
+    Yield,
+
+    /// The current thread waits for an event and must not run until it is woken up.
+    Block,
+
+    /// The current thread has finished and must never run again.
+    Exit,
+}
+
+/// The raw contexts needed to perform a switch.
+pub struct SwitchContexts {
+    /// Where to save the outgoing thread's context.
+    pub prev: *mut Context,
+
+    /// Where to load the incoming thread's context from.
+    pub next: *const Context,
+}
+
+/// A round-robin scheduler.
+///
+/// Threads are owned by the scheduler. The running thread is `current`. The idle thread is parked
+/// in `idle` whenever it is not running and is only picked if the run queue is empty.
+///
+/// Threads are boxed so that their contexts never move, which is required because raw pointers to
+/// them are handed to the context switch code.
+#[allow(clippy::vec_box)]
+pub(super) struct Scheduler {
+    current: Option<Box<Thread>>,
+    idle: Option<Box<Thread>>,
+
+    // Can be replaced with a VecDeque once it's new() becomes const.
+    run_queue: Vec<Box<Thread>>,
+    blocked: Vec<Box<Thread>>,
+    zombies: Vec<Box<Thread>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Scheduler {
+    /// Create an instance.
+    pub(super) const fn new() -> Self {
+        Self {
+            current: None,
+            idle: None,
+            run_queue: Vec::new(),
+            blocked: Vec::new(),
+            zombies: Vec::new(),
+        }
+    }
+
+    /// Whether a current thread has been set up.
+    pub(super) fn is_initialized(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Set up the scheduler with the thread that is already running and the idle thread.
+    pub(super) fn init(&mut self, current: Box<Thread>, idle: Box<Thread>) {
+        self.current = Some(current);
+        self.idle = Some(idle);
+    }
+
+    /// Return a reference to the running thread.
+    pub(super) fn current(&self) -> &Thread {
+        self.current.as_ref().unwrap()
+    }
+
+    /// Return a mutable reference to the running thread.
+    pub(super) fn current_mut(&mut self) -> &mut Thread {
+        self.current.as_mut().unwrap()
+    }
+
+    /// Append a thread to the run queue.
+    pub(super) fn enqueue(&mut self, thread: Box<Thread>) {
+        self.run_queue.push(thread);
+    }
+
+    /// Pick the next thread and make it the current one.
+    ///
+    /// Returns `None` if the current thread shall continue running. Otherwise, returns the
+    /// contexts that the caller must pass on to the arch context switch _after_ releasing the lock
+    /// that protects the scheduler.
+    pub(super) fn switch_prepare(&mut self, reason: SwitchReason) -> Option<SwitchContexts> {
+        // Zombies were put here by threads that switched away for the last time. Since the
+        // executing thread can not be one of them, it is safe to free their stacks now.
+        self.zombies.clear();
+
+        // The idle thread is the only one that is not parked anywhere while it runs.
+        let current_is_idle = self.idle.is_none();
+
+        let next = if !self.run_queue.is_empty() {
+            self.run_queue.remove(0)
+        } else {
+            match reason {
+                SwitchReason::Yield => return None,
+                SwitchReason::Block | SwitchReason::Exit => self.idle.take()?,
+            }
+        };
+
+        let mut prev = self.current.replace(next).unwrap();
+        let prev_context = &mut prev.context as *mut Context;
+
+        match reason {
+            SwitchReason::Exit => self.zombies.push(prev),
+            SwitchReason::Block => self.blocked.push(prev),
+            SwitchReason::Yield if current_is_idle => self.idle = Some(prev),
+            SwitchReason::Yield => self.run_queue.push(prev),
+        }
+
+        Some(SwitchContexts {
+            prev: prev_context,
+            next: &self.current().context as *const Context,
+        })
+    }
+
+    /// Move a blocked thread back to the run queue.
+    ///
+    /// Returns `false` if no blocked thread with the given ID exists.
+    pub(super) fn wake(&mut self, id: ThreadId) -> bool {
+        let index = match self.blocked.iter().position(|thread| thread.id == id) {
+            None => return false,
+            Some(i) => i,
+        };
+
+        let thread = self.blocked.remove(index);
+        self.run_queue.push(thread);
+
+        true
+    }
+
+    /// Call `f` for every thread, starting with the current one.
+    pub(super) fn for_each_thread(&self, mut f: impl FnMut(&Thread, bool)) {
+        f(self.current(), true);
+
+        let others = self.run_queue.iter().chain(self.blocked.iter());
+        for thread in others.chain(self.idle.iter()) {
+            f(thread, false);
+        }
+    }
+}
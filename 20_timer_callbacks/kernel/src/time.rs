@@ -4,6 +4,11 @@
 
 //! Timer primitives.
 //!
+//! There is a single timeout queue, but every core has its own timer. Setting a timeout arms the
+//! timer of the executing core, and handling one re-arms the timer of the handling core for the
+//! next timeout in the queue. A callback therefore runs on whichever core's timer fires first.
+//! Timers of other cores that were armed for the same timeout find nothing due when they fire.
+//!
 //! # Resources
 //!
 //! - <https://stackoverflow.com/questions/41081240/idiomatic-callbacks-in-rust>
@@ -16,8 +21,7 @@ mod arch_time;
 use crate::{
     driver, exception,
     exception::asynchronous::IRQNumber,
-    synchronization::{interface::Mutex, IRQSafeNullLock},
-    warn,
+    synchronization::{interface::Mutex, IRQSafeSpinLock},
 };
 use alloc::{boxed::Box, vec::Vec};
 use core::{
@@ -49,7 +53,8 @@ pub type TimeoutCallback = Box<dyn Fn() + Send>;
 
 /// Provides time management functions.
 pub struct TimeManager {
-    queue: IRQSafeNullLock<OrderedTimeoutQueue>,
+    queue: IRQSafeSpinLock<OrderedTimeoutQueue>,
+    defer_callbacks: AtomicBool,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -69,7 +74,7 @@ impl Timeout {
 
     pub fn refresh(&mut self) {
         if let Some(delay) = self.period {
-            self.due_time += delay;
+            self.due_time = self.due_time.saturating_add(delay);
         }
     }
 }
@@ -114,7 +119,8 @@ impl TimeManager {
     /// Create an instance.
     pub const fn new() -> Self {
         Self {
-            queue: IRQSafeNullLock::new(OrderedTimeoutQueue::new()),
+            queue: IRQSafeSpinLock::new(OrderedTimeoutQueue::new()),
+            defer_callbacks: AtomicBool::new(false),
         }
     }
 
@@ -135,7 +141,35 @@ impl TimeManager {
         arch_time::spin_for(duration)
     }
 
-    /// Set a timeout.
+    /// Run the callback of a timeout that is due, and queue the timeout again if it is periodic.
+    fn run_callback(&self, timeout: Timeout) {
+        // Important: Call the callback while not holding any lock, because the callback might
+        // attempt to modify data that is protected by a lock (in particular, the timeout queue
+        // itself).
+        (timeout.callback)();
+
+        self.queue.lock(|queue| {
+            if timeout.is_periodic() {
+                // There might be some overhead involved in the periodic path, because the timeout
+                // item is first popped from the underlying Vec and then pushed back again. It could
+                // be faster to keep the item in the queue and find a way to work with a reference
+                // to it.
+                //
+                // We are not going this route on purpose, though. It allows to keep the code simple
+                // and the focus on the high-level concepts.
+                queue.push(timeout);
+            };
+
+            if let Some(due_time) = queue.peek_next_due_time() {
+                arch_time::set_timeout_irq(due_time);
+            }
+        });
+    }
+
+    /// Set a timeout and arm the executing core's timer for the next one that is due.
+    ///
+    /// The timeout is only delivered on time if the core unmasks IRQs. Otherwise, it is handled
+    /// when the timer of another core fires for a later timeout.
     fn set_timeout(&self, timeout: Timeout) {
         self.queue.lock(|queue| {
             queue.push(timeout);
@@ -145,9 +179,11 @@ impl TimeManager {
     }
 
     /// Set a one-shot timeout.
+    ///
+    /// A due time beyond [`Duration::MAX`] is saturated. Such a timeout never fires.
     pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) {
         let timeout = Timeout {
-            due_time: self.uptime() + delay,
+            due_time: self.uptime().saturating_add(delay),
             period: None,
             callback,
         };
@@ -156,15 +192,25 @@ impl TimeManager {
     }
 
     /// Set a periodic timeout.
+    ///
+    /// Due times are saturated like in [`Self::set_timeout_once()`].
     pub fn set_timeout_periodic(&self, delay: Duration, callback: TimeoutCallback) {
         let timeout = Timeout {
-            due_time: self.uptime() + delay,
+            due_time: self.uptime().saturating_add(delay),
             period: Some(delay),
             callback,
         };
 
         self.set_timeout(timeout);
     }
+
+    /// Choose whether callbacks run in the timer IRQ handler, which is the default, or as deferred
+    /// work with IRQs unmasked.
+    ///
+    /// A periodic timeout is queued again only after its deferred callback ran.
+    pub fn set_defer_callbacks(&self, defer: bool) {
+        self.defer_callbacks.store(defer, Ordering::Relaxed);
+    }
 }
 
 /// Initialize the timer subsystem.
@@ -182,6 +228,13 @@ pub fn init() -> Result<(), &'static str> {
     Ok(())
 }
 
+/// Prepare the timer of the executing secondary core.
+///
+/// The timer IRQ itself is enabled for the core by the interrupt controller.
+pub fn secondary_core_init() {
+    arch_time::core_init();
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -197,9 +250,10 @@ impl driver::interface::DeviceDriver for TimeManager {
         &'static self,
         irq_number: &Self::IRQNumberType,
     ) -> Result<(), &'static str> {
-        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
 
-        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self)
+            .with_priority(IRQPriority::High);
 
         irq_manager().register_handler(descriptor)?;
         irq_manager().enable(irq_number);
@@ -209,7 +263,9 @@ impl driver::interface::DeviceDriver for TimeManager {
 }
 
 impl exception::asynchronous::interface::IRQHandler for TimeManager {
-    fn handle(&self) -> Result<(), &'static str> {
+    fn handle(&self) -> Result<exception::asynchronous::IRQReturn, &'static str> {
+        use exception::asynchronous::IRQReturn;
+
         arch_time::conclude_timeout_irq();
 
         let maybe_timeout: Option<Timeout> = self.queue.lock(|queue| {
@@ -228,36 +284,29 @@ impl exception::asynchronous::interface::IRQHandler for TimeManager {
             Some(timeout)
         });
 
+        // Another core might have handled the timeout that this core's timer was armed for.
         let timeout = match maybe_timeout {
-            None => {
-                warn!("Spurious timeout IRQ");
-                return Ok(());
-            }
+            None => return Ok(IRQReturn::Handled),
             Some(t) => t,
         };
 
-        // Important: Call the callback while not holding any lock, because the callback might
-        // attempt to modify data that is protected by a lock (in particular, the timeout queue
-        // itself).
-        (timeout.callback)();
+        if !self.defer_callbacks.load(Ordering::Relaxed) {
+            self.run_callback(timeout);
+            return Ok(IRQReturn::Handled);
+        }
 
-        self.queue.lock(|queue| {
-            if timeout.is_periodic() {
-                // There might be some overhead involved in the periodic path, because the timeout
-                // item is first popped from the underlying Vec and then pushed back again. It could
-                // be faster to keep the item in the queue and find a way to work with a reference
-                // to it.
-                //
-                // We are not going this route on purpose, though. It allows to keep the code simple
-                // and the focus on the high-level concepts.
-                queue.push(timeout);
-            };
+        // The deferred work owns the timeout until the callback ran. Meanwhile, keep the timer
+        // armed for the rest of the queue.
+        exception::asynchronous::deferred::defer(Box::new(move || {
+            time_manager().run_callback(timeout)
+        }));
 
+        self.queue.lock(|queue| {
             if let Some(due_time) = queue.peek_next_due_time() {
                 arch_time::set_timeout_irq(due_time);
             }
         });
 
-        Ok(())
+        Ok(IRQReturn::Handled)
     }
 }
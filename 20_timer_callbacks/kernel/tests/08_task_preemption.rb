@@ -0,0 +1,39 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'console_io_test'
+
+# Verify that two threads that never yield still get to run in turns.
+class ThreadInterleavingTest < SubtestBase
+    def name
+        'Threads interleave'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Thread A: 0')
+        expect_or_raise(qemu_out, 'Thread B: 0')
+        expect_or_raise(qemu_out, 'Thread A: 1')
+        expect_or_raise(qemu_out, 'Thread B: 1')
+    end
+end
+
+# Verify that threads exit and the spawning thread continues.
+class ThreadExitTest < SubtestBase
+    def name
+        'Threads exit'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Threads joined')
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [ThreadInterleavingTest.new, ThreadExitTest.new]
+end
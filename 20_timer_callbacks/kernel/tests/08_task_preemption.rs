@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Preemptive kernel threads sanity test.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, driver, exception, info, memory, println, state, task, time};
+
+static NUM_EXITED: AtomicUsize = AtomicUsize::new(0);
+
+/// Print some lines without ever yielding voluntarily.
+fn busy_printer(name: &'static str) {
+    for i in 0..4 {
+        info!("Thread {}: {}", name, i);
+        time::time_manager().spin_for(Duration::from_millis(100));
+    }
+
+    NUM_EXITED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::wait_forever();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    if task::init().is_err() {
+        cpu::wait_forever();
+    }
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    // This line will be printed as the test header.
+    println!("Testing preemptive kernel threads");
+
+    task::spawn("A", || busy_printer("A"));
+    task::spawn("B", || busy_printer("B"));
+
+    while NUM_EXITED.load(Ordering::Relaxed) < 2 {
+        task::yield_now();
+    }
+
+    info!("Threads joined");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
@@ -0,0 +1,40 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'console_io_test'
+
+# Verify that a user process can use all system calls.
+class SyscallTest < SubtestBase
+    def name
+        'System calls'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Hello from user space')
+        expect_or_raise(qemu_out, 'Uptime advanced in EL0')
+        expect_or_raise(qemu_out, 'Overlong sleep refused')
+        expect_or_raise(qemu_out, "Process 'hello' exited with code 42")
+    end
+end
+
+# Verify that a faulting user process is killed while the kernel keeps running.
+class UserFaultTest < SubtestBase
+    def name
+        'User fault kills process'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, "Process 'faulty' killed")
+        expect_or_raise(qemu_out, 'Kernel survived')
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [SyscallTest.new, UserFaultTest.new]
+end
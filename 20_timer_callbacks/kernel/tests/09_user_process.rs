@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! User process sanity test.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use core::time::Duration;
+use libkernel::{
+    bsp, cpu, driver, exception, include_user_program, info, memory, println, process, state, task,
+    time,
+};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::wait_forever();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    if task::init().is_err() {
+        cpu::wait_forever();
+    }
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    // This line will be printed as the test header.
+    println!("Testing user processes");
+
+    // Both programs are part of the user_programs crate.
+    if process::spawn("hello", include_user_program!("hello")).is_err()
+        || process::spawn("faulty", include_user_program!("faulty")).is_err()
+    {
+        cpu::wait_forever();
+    }
+
+    task::sleep(Duration::from_secs(1));
+
+    info!("Kernel survived");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
@@ -0,0 +1,48 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'console_io_test'
+
+# Verify that all secondary cores come online.
+class CoresOnlineTest < SubtestBase
+    def name
+        'Secondary cores online'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Cores online: 4')
+        expect_or_raise(qemu_out, 'All secondary cores entered')
+    end
+end
+
+# Verify that secondary cores can use the timer.
+class SecondaryTimeoutTest < SubtestBase
+    def name
+        'Timeouts set by secondary cores'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Timeouts of secondary cores fired')
+    end
+end
+
+# Verify that the kernel refuses to boot the secondary cores twice.
+class MultiCoreMainTest < SubtestBase
+    def name
+        'MultiCoreMain is enforced'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Second bring-up refused')
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [CoresOnlineTest.new, SecondaryTimeoutTest.new, MultiCoreMainTest.new]
+end
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Secondary core bring-up test.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use alloc::boxed::Box;
+use core::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, driver, exception, info, memory, println, state, task, time};
+
+/// Bitmask of the secondary cores that reached their entry function.
+static CORES_ENTERED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of timeouts set by secondary cores that have fired.
+static TIMEOUTS_FIRED: AtomicUsize = AtomicUsize::new(0);
+
+/// Executed by every secondary core once the kernel is in `MultiCoreMain`.
+fn secondary_entry() -> ! {
+    CORES_ENTERED.fetch_or(1 << cpu::smp::core_id::<usize>(), Ordering::Release);
+
+    // The timer IRQ is enabled for every core, so a timeout can be armed from here.
+    exception::asynchronous::local_irq_unmask();
+    time::time_manager().set_timeout_once(
+        Duration::from_millis(10),
+        Box::new(|| {
+            TIMEOUTS_FIRED.fetch_add(1, Ordering::Relaxed);
+        }),
+    );
+
+    cpu::wait_forever()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::wait_forever();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    if task::init().is_err() {
+        cpu::wait_forever();
+    }
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    // This line will be printed as the test header.
+    println!("Testing secondary core bring-up");
+
+    if let Err(x) = cpu::smp::start_secondary_cores(secondary_entry) {
+        info!("Bring-up failed: {}", x);
+        cpu::wait_forever();
+    }
+
+    info!("Cores online: {}", cpu::smp::num_cores_online());
+
+    let secondary_cores = ((1 << bsp::cpu::NUM_CORES) - 1) & !(1 << bsp::cpu::BOOT_CORE_ID);
+    while CORES_ENTERED.load(Ordering::Acquire) != secondary_cores {
+        hint::spin_loop();
+    }
+
+    info!("All secondary cores entered");
+
+    let deadline = time::time_manager().uptime() + Duration::from_secs(1);
+    while TIMEOUTS_FIRED.load(Ordering::Relaxed) != bsp::cpu::NUM_CORES - 1 {
+        if time::time_manager().uptime() > deadline {
+            info!("Timeouts of secondary cores missing");
+            cpu::wait_forever();
+        }
+        hint::spin_loop();
+    }
+
+    info!("Timeouts of secondary cores fired");
+
+    // A second bring-up must be refused.
+    if cpu::smp::start_secondary_cores(secondary_entry).is_err() {
+        info!("Second bring-up refused");
+    }
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
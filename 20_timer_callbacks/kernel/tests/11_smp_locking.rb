@@ -0,0 +1,36 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'console_io_test'
+
+# Verify that no increment of the shared counter got lost.
+class MutexTest < SubtestBase
+    def name
+        'IRQSafeSpinLock'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Counter: 400000')
+    end
+end
+
+# Verify that readers never observed a half-finished write.
+class RwLockTest < SubtestBase
+    def name
+        'RwSpinLock'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Torn reads: 0')
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [MutexTest.new, RwLockTest.new]
+end
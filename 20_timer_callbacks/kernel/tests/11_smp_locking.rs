@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Spin lock test with all cores competing for the same data.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use alloc::boxed::Box;
+use core::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libkernel::{
+    bsp, cpu, driver, exception, info, memory, println, state,
+    synchronization::{
+        interface::{Mutex, ReadWriteEx},
+        IRQSafeSpinLock, RwSpinLock,
+    },
+    task, time,
+};
+
+const ITERATIONS: u64 = 100_000;
+
+static COUNTER: IRQSafeSpinLock<u64> = IRQSafeSpinLock::new(0);
+
+/// Always holds two equal values. A reader seeing them differ has raced with a writer.
+static PAIR: RwSpinLock<(u64, u64)> = RwSpinLock::new((0, 0));
+
+static TORN_READS: AtomicUsize = AtomicUsize::new(0);
+static CORES_DONE: AtomicUsize = AtomicUsize::new(0);
+
+/// Hammer the locks and the heap, which is protected by a spin lock as well.
+fn contend() {
+    for i in 0..ITERATIONS {
+        COUNTER.lock(|counter| *counter += 1);
+
+        if i % 16 == 0 {
+            PAIR.write(|(a, b)| {
+                *a += 1;
+                *b += 1;
+            });
+        } else if PAIR.read(|(a, b)| a != b) {
+            TORN_READS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let boxed = Box::new(i);
+        assert_eq!(*boxed, i);
+    }
+
+    CORES_DONE.fetch_add(1, Ordering::Release);
+}
+
+fn secondary_entry() -> ! {
+    contend();
+
+    cpu::wait_forever()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::wait_forever();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    if task::init().is_err() {
+        cpu::wait_forever();
+    }
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    // This line will be printed as the test header.
+    println!("Testing spin locks on all cores");
+
+    if let Err(x) = cpu::smp::start_secondary_cores(secondary_entry) {
+        info!("Bring-up failed: {}", x);
+        cpu::wait_forever();
+    }
+
+    contend();
+
+    while CORES_DONE.load(Ordering::Acquire) != bsp::cpu::NUM_CORES {
+        hint::spin_loop();
+    }
+
+    info!("Counter: {}", COUNTER.lock(|counter| *counter));
+    info!("Torn reads: {}", TORN_READS.load(Ordering::Relaxed));
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
@@ -0,0 +1,27 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'console_io_test'
+
+# Verify that a stack overflow is reported instead of faulting recursively.
+class StackOverflowTest < SubtestBase
+    def name
+        'Stack overflow detected'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Kernel stack overflow!')
+        expect_or_raise(qemu_out, /Data Abort, current EL/)
+        expect_or_raise(qemu_out, /recurse/)
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [StackOverflowTest.new]
+end
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Overflowing a thread stack must hit its guard page and panic on the emergency stack.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use libkernel::{bsp, cpu, driver, exception, info, memory, println, state, task, time};
+
+/// Recurse without end. The buffer makes each frame big enough to quickly exhaust the stack.
+#[inline(never)]
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    let buf = [depth; 32];
+    core::hint::black_box(&buf);
+
+    recurse(depth + 1) + buf[0]
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::wait_forever();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    if task::init().is_err() {
+        cpu::wait_forever();
+    }
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    // This line will be printed as the test header.
+    println!("Testing kernel stack overflow detection");
+
+    task::spawn("overflow", || {
+        info!("Recursing...");
+        recurse(0);
+    });
+
+    loop {
+        task::yield_now();
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mapping policy tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use core::num::NonZeroUsize;
+use libkernel::{
+    bsp, cpu, exception,
+    memory::{
+        self,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes},
+    },
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    bsp::driver::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// No page of the live kernel tables is both writable and executable, and no code page is
+/// writable.
+#[kernel_test]
+fn live_kernel_tables_pass_audit() {
+    assert_eq!(memory::mmu::kernel_audit_mappings(), Ok(()));
+}
+
+/// Writable and executable mappings are rejected.
+#[kernel_test]
+fn writable_executable_mapping_is_rejected() {
+    let rwx = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        privileged_execute_never: false,
+        user_execute_never: true,
+    };
+
+    let virt_region = memory::mmu::kernel_reserve_va(NonZeroUsize::new(1).unwrap()).unwrap();
+    assert_eq!(
+        unsafe { memory::mmu::kernel_map_fresh_frames(&virt_region, &rwx) },
+        Err("Mapping would be writable and executable")
+    );
+
+    let code_region = bsp::memory::mmu::virt_code_region();
+    let code_attr = memory::mmu::try_kernel_page_attributes(code_region.start_page_addr()).unwrap();
+    let writable_code = AttributeFields {
+        acc_perms: AccessPermissions::ReadWrite,
+        ..code_attr
+    };
+    assert_eq!(
+        unsafe { memory::mmu::kernel_protect(&code_region, &writable_code) },
+        Err("Mapping would be writable and executable")
+    );
+    assert_eq!(
+        memory::mmu::try_kernel_page_attributes(code_region.start_page_addr()),
+        Ok(code_attr)
+    );
+    assert_eq!(memory::mmu::kernel_audit_mappings(), Ok(()));
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Page table dump tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use core::num::NonZeroUsize;
+use libkernel::{
+    bsp, cpu, exception,
+    memory::{
+        self,
+        mmu::{AccessPermissions, AttributeFields, MemAttributes, MemoryRegion, PageAddress},
+    },
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    bsp::driver::qemu_bring_up_console();
+    bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The live kernel tables match the mapping record.
+#[kernel_test]
+fn live_tables_agree_with_record() {
+    assert_eq!(memory::mmu::kernel_print_page_tables(), Ok(()));
+}
+
+/// A recorded mapping that is missing from the tables is flagged.
+///
+/// The record is not removed again, so this must stay the last test.
+#[kernel_test]
+fn unmapped_record_is_flagged() {
+    let attr = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        privileged_execute_never: true,
+        user_execute_never: true,
+    };
+
+    let virt_region = memory::mmu::kernel_reserve_va(NonZeroUsize::new(1).unwrap()).unwrap();
+    let phys_start = PageAddress::from(0);
+    let phys_region = MemoryRegion::new(phys_start, phys_start.checked_offset(1).unwrap());
+
+    memory::mmu::kernel_add_mapping_record("Unmapped", &virt_region, &phys_region, &attr);
+
+    assert_eq!(
+        memory::mmu::kernel_print_page_tables(),
+        Err("Translation tables disagree with the mapping record")
+    );
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ priority and nesting tests.
+//!
+//! The virtual timer serves as a low priority IRQ that can be raised on every BSP. On the default
+//! rpi3 target, this only covers nesting on the BCM interrupt controllers, which works by disabling
+//! the low priority IRQ and unmasking IRQs.
+//!
+//! The GIC's priorities (`IPRIORITYR`, `PMR` and `BPR`) are only exercised by the tests in `gic`,
+//! which need `BSP=rpi4` and a QEMU that emulates the Raspberry Pi 4 (`raspi4b`, QEMU 9.0 or
+//! newer). The QEMU of the docker image is older, so `make test` does not run them. See the
+//! Makefile for how to run them with local tools. Until then, GIC priority preemption is
+//! unverified.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use exception::asynchronous::{
+    interface::IRQHandler, irq_manager, IRQHandlerDescriptor, IRQNumber, IRQPriority, IRQReturn,
+};
+use libkernel::{bsp, cpu, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+/// Spins while a timeout is due, and records when it started and ended.
+struct VirtualTimerHandler;
+
+static VIRTUAL_TIMER_HANDLER: VirtualTimerHandler = VirtualTimerHandler;
+
+static SEQ: AtomicUsize = AtomicUsize::new(1);
+static LOW_START: AtomicUsize = AtomicUsize::new(0);
+static LOW_END: AtomicUsize = AtomicUsize::new(0);
+static HIGH: AtomicUsize = AtomicUsize::new(0);
+
+fn record(x: &AtomicUsize) {
+    x.store(SEQ.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Reset the records, let `raise` trigger the low priority IRQ and return the sequence numbers of
+/// the low priority start and end, and of the high priority handler.
+fn run(raise: impl FnOnce()) -> (usize, usize, usize) {
+    for x in [&LOW_START, &LOW_END, &HIGH] {
+        x.store(0, Ordering::Relaxed);
+    }
+
+    raise();
+    time::time_manager().spin_for(Duration::from_millis(50));
+
+    (
+        LOW_START.load(Ordering::Relaxed),
+        LOW_END.load(Ordering::Relaxed),
+        HIGH.load(Ordering::Relaxed),
+    )
+}
+
+impl IRQHandler for VirtualTimerHandler {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        use aarch64_cpu::registers::CNTV_CTL_EL0;
+        use tock_registers::interfaces::Writeable;
+
+        // Deassert the IRQ.
+        CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::CLEAR);
+
+        record(&LOW_START);
+        time::time_manager().spin_for(Duration::from_millis(10));
+        record(&LOW_END);
+
+        Ok(IRQReturn::Handled)
+    }
+}
+
+/// The descriptor of the virtual timer handler, with low priority.
+fn virtual_timer_descriptor(nesting: bool) -> IRQHandlerDescriptor<IRQNumber> {
+    use bsp::exception::asynchronous::irq_map;
+
+    let descriptor = IRQHandlerDescriptor::new(
+        irq_map::ARM_VIRTUAL_TIMER,
+        "Virtual timer",
+        &VIRTUAL_TIMER_HANDLER,
+    )
+    .with_priority(IRQPriority::Low);
+
+    if nesting {
+        descriptor.with_nesting()
+    } else {
+        descriptor
+    }
+}
+
+/// Register the virtual timer handler.
+fn register_virtual_timer(nesting: bool) {
+    use bsp::exception::asynchronous::irq_map;
+
+    irq_manager()
+        .register_handler(virtual_timer_descriptor(nesting))
+        .unwrap();
+    irq_manager().enable(&irq_map::ARM_VIRTUAL_TIMER);
+}
+
+/// Arm a timeout, which is handled with high priority, and raise the virtual timer IRQ before it
+/// is due.
+fn raise_virtual_timer() {
+    use aarch64_cpu::registers::{CNTV_CTL_EL0, CNTV_TVAL_EL0};
+    use tock_registers::interfaces::Writeable;
+
+    time::time_manager().set_timeout_once(Duration::from_millis(2), Box::new(|| record(&HIGH)));
+
+    CNTV_TVAL_EL0.set(0);
+    CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::SET);
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::qemu_exit_failure();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    #[cfg(feature = "bsp_rpi4")]
+    gic::register_handlers();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The timer IRQ preempts a low priority handler that allows nesting, and waits for one that does
+/// not.
+#[kernel_test]
+fn timer_irq_preempts_nesting_handler_only() {
+    register_virtual_timer(true);
+    let (low_start, low_end, high) = run(raise_virtual_timer);
+
+    assert!(low_start != 0 && low_end != 0 && high != 0);
+    assert!(low_start < high && high < low_end);
+
+    irq_manager()
+        .unregister_handler(virtual_timer_descriptor(true))
+        .unwrap();
+    register_virtual_timer(false);
+    let (low_start, low_end, high) = run(raise_virtual_timer);
+
+    assert!(low_start != 0 && low_end != 0 && high != 0);
+    assert!(low_start < low_end && low_end < high);
+}
+
+/// Only SGIs can be raised from software, if at all.
+#[kernel_test]
+fn raising_a_peripheral_irq_fails() {
+    use bsp::exception::asynchronous::irq_map;
+
+    assert!(exception::asynchronous::irq_manager()
+        .raise_software_irq(&irq_map::ARM_NS_PHYSICAL_TIMER)
+        .is_err());
+}
+
+/// GIC priority tests. Not run by `make test`, see the module documentation.
+#[cfg(feature = "bsp_rpi4")]
+mod gic {
+    use super::*;
+
+    /// Raises the high priority SGI and gives it time to arrive.
+    struct LowPriority;
+
+    /// Records when it ran.
+    struct HighPriority;
+
+    const NESTING_SGI: IRQNumber = IRQNumber::new(0);
+    const NON_NESTING_SGI: IRQNumber = IRQNumber::new(1);
+    const HIGH_PRIORITY_SGI: IRQNumber = IRQNumber::new(2);
+
+    static LOW_PRIORITY: LowPriority = LowPriority;
+    static HIGH_PRIORITY: HighPriority = HighPriority;
+
+    impl IRQHandler for LowPriority {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            record(&LOW_START);
+
+            irq_manager().raise_software_irq(&HIGH_PRIORITY_SGI)?;
+            time::time_manager().spin_for(Duration::from_millis(10));
+
+            record(&LOW_END);
+            Ok(IRQReturn::Handled)
+        }
+    }
+
+    impl IRQHandler for HighPriority {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            record(&HIGH);
+            Ok(IRQReturn::Handled)
+        }
+    }
+
+    pub fn register_handlers() {
+        let descriptors = [
+            IRQHandlerDescriptor::new(NESTING_SGI, "Nesting", &LOW_PRIORITY)
+                .with_priority(IRQPriority::Low)
+                .with_nesting(),
+            IRQHandlerDescriptor::new(NON_NESTING_SGI, "Non-nesting", &LOW_PRIORITY)
+                .with_priority(IRQPriority::Low),
+            IRQHandlerDescriptor::new(HIGH_PRIORITY_SGI, "High priority", &HIGH_PRIORITY)
+                .with_priority(IRQPriority::High),
+        ];
+
+        for descriptor in descriptors {
+            if irq_manager().register_handler(descriptor).is_err() {
+                cpu::qemu_exit_failure();
+            }
+            irq_manager().enable(&descriptor.number());
+        }
+    }
+
+    fn raise(sgi: IRQNumber) -> impl FnOnce() {
+        move || irq_manager().raise_software_irq(&sgi).unwrap()
+    }
+
+    /// A high priority IRQ preempts a low priority handler that allows nesting.
+    #[kernel_test]
+    fn high_priority_irq_preempts_nesting_handler() {
+        let (low_start, low_end, high) = run(raise(NESTING_SGI));
+
+        assert!(low_start != 0 && low_end != 0 && high != 0);
+        assert!(low_start < high && high < low_end);
+    }
+
+    /// Without nesting, the high priority IRQ waits until the low priority handler is done.
+    #[kernel_test]
+    fn non_nesting_handler_runs_to_completion() {
+        let (low_start, low_end, high) = run(raise(NON_NESTING_SGI));
+
+        assert!(low_start != 0 && low_end != 0 && high != 0);
+        assert!(low_start < low_end && low_end < high);
+    }
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Deferred work tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::qemu_exit_failure();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    time::time_manager().set_defer_callbacks(true);
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Deferred timer callbacks run with IRQs unmasked.
+#[kernel_test]
+fn deferred_callback_runs_with_irqs_unmasked() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static UNMASKED: AtomicUsize = AtomicUsize::new(0);
+
+    time::time_manager().set_timeout_once(
+        Duration::from_millis(10),
+        Box::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+
+            // Returns true if IRQs are unmasked, see 04_exception_irq_sanity.
+            if exception::asynchronous::is_local_irq_masked() {
+                UNMASKED.fetch_add(1, Ordering::Relaxed);
+            }
+        }),
+    );
+    time::time_manager().spin_for(Duration::from_millis(50));
+
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    assert_eq!(UNMASKED.load(Ordering::Relaxed), 1);
+}
+
+/// Periodic timeouts keep firing when their callbacks are deferred.
+#[kernel_test]
+fn deferred_periodic_callback_is_requeued() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    time::time_manager().set_timeout_periodic(
+        Duration::from_millis(10),
+        Box::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }),
+    );
+    time::time_manager().spin_for(Duration::from_millis(100));
+
+    assert!(CALLS.load(Ordering::Relaxed) >= 3);
+}
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ sharing and unregistration tests.
+//!
+//! The virtual timer covers every BSP. The tests in `gic` additionally use an SGI and need
+//! `BSP=rpi4`.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use exception::asynchronous::{
+    interface::IRQHandler, irq_manager, IRQHandlerDescriptor, IRQReturn,
+};
+use libkernel::{bsp, cpu, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+struct TestHandler {
+    calls: AtomicUsize,
+    ret: IRQReturn,
+}
+
+static MINE: TestHandler = TestHandler::new(IRQReturn::Handled);
+
+/// Counts its calls. The owner deasserts the virtual timer IRQ and claims it.
+struct VirtualTimerHandler {
+    calls: AtomicUsize,
+    is_owner: bool,
+}
+
+static VIRTUAL_TIMER_OWNER: VirtualTimerHandler = VirtualTimerHandler {
+    calls: AtomicUsize::new(0),
+    is_owner: true,
+};
+static VIRTUAL_TIMER_BYSTANDER: VirtualTimerHandler = VirtualTimerHandler {
+    calls: AtomicUsize::new(0),
+    is_owner: false,
+};
+
+impl TestHandler {
+    const fn new(ret: IRQReturn) -> Self {
+        Self {
+            calls: AtomicUsize::new(0),
+            ret,
+        }
+    }
+}
+
+impl IRQHandler for TestHandler {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        Ok(self.ret)
+    }
+}
+
+impl IRQHandler for VirtualTimerHandler {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        use aarch64_cpu::registers::CNTV_CTL_EL0;
+        use tock_registers::interfaces::Writeable;
+
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if !self.is_owner {
+            return Ok(IRQReturn::NotHandled);
+        }
+
+        CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::CLEAR);
+        Ok(IRQReturn::Handled)
+    }
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::qemu_exit_failure();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The timer registered its handler without sharing, so no other handler can join.
+#[kernel_test]
+fn exclusive_irq_cannot_be_shared() {
+    use bsp::exception::asynchronous::irq_map;
+
+    let descriptor =
+        IRQHandlerDescriptor::new(irq_map::ARM_NS_PHYSICAL_TIMER, "Intruder", &MINE).with_sharing();
+
+    assert!(irq_manager().register_handler(descriptor).is_err());
+}
+
+/// Only registered handlers can be removed.
+#[kernel_test]
+fn unregistering_an_unknown_handler_fails() {
+    use bsp::exception::asynchronous::irq_map;
+
+    let descriptor = IRQHandlerDescriptor::new(irq_map::ARM_NS_PHYSICAL_TIMER, "Intruder", &MINE);
+
+    assert!(irq_manager().unregister_handler(descriptor).is_err());
+}
+
+/// Every handler of a shared IRQ is called, until it is unregistered. Uses the virtual timer, which
+/// exists on every BSP.
+#[kernel_test]
+fn shared_virtual_timer_handlers_are_called_until_unregistered() {
+    use aarch64_cpu::registers::{CNTV_CTL_EL0, CNTV_TVAL_EL0};
+    use bsp::exception::asynchronous::irq_map;
+    use tock_registers::interfaces::Writeable;
+
+    let descriptor = |name, handler: &'static VirtualTimerHandler| {
+        IRQHandlerDescriptor::new(irq_map::ARM_VIRTUAL_TIMER, name, handler).with_sharing()
+    };
+    let owner = descriptor("Owner", &VIRTUAL_TIMER_OWNER);
+    let bystander = descriptor("Bystander", &VIRTUAL_TIMER_BYSTANDER);
+
+    irq_manager().register_handler(owner).unwrap();
+    irq_manager().register_handler(bystander).unwrap();
+    irq_manager().enable(&irq_map::ARM_VIRTUAL_TIMER);
+
+    let calls = || {
+        (
+            VIRTUAL_TIMER_OWNER.calls.load(Ordering::Relaxed),
+            VIRTUAL_TIMER_BYSTANDER.calls.load(Ordering::Relaxed),
+        )
+    };
+    let raise = || {
+        CNTV_TVAL_EL0.set(0);
+        CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::SET);
+        time::time_manager().spin_for(Duration::from_millis(10));
+    };
+    let (owner_calls, bystander_calls) = calls();
+
+    raise();
+    assert_eq!(calls(), (owner_calls + 1, bystander_calls + 1));
+
+    irq_manager().unregister_handler(bystander).unwrap();
+    raise();
+    assert_eq!(calls(), (owner_calls + 2, bystander_calls + 1));
+
+    // Removing the last handler disables the IRQ.
+    irq_manager().unregister_handler(owner).unwrap();
+    raise();
+    assert_eq!(calls(), (owner_calls + 2, bystander_calls + 1));
+
+    CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::CLEAR);
+}
+
+#[cfg(feature = "bsp_rpi4")]
+mod gic {
+    use super::*;
+    use exception::asynchronous::IRQNumber;
+
+    const SHARED_SGI: IRQNumber = IRQNumber::new(3);
+
+    static NOT_MINE: TestHandler = TestHandler::new(IRQReturn::NotHandled);
+
+    impl TestHandler {
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::Relaxed)
+        }
+    }
+
+    fn raise() {
+        irq_manager().raise_software_irq(&SHARED_SGI).unwrap();
+        time::time_manager().spin_for(Duration::from_millis(10));
+    }
+
+    /// Every handler of a shared SGI is called, until it is unregistered.
+    #[kernel_test]
+    fn shared_handlers_are_called_until_unregistered() {
+        let descriptor = |handler: &'static TestHandler| {
+            IRQHandlerDescriptor::new(SHARED_SGI, "Shared", handler).with_sharing()
+        };
+        let (not_mine_descriptor, mine_descriptor) = (descriptor(&NOT_MINE), descriptor(&MINE));
+
+        for descriptor in [not_mine_descriptor, mine_descriptor] {
+            irq_manager().register_handler(descriptor).unwrap();
+        }
+        irq_manager().enable(&SHARED_SGI);
+
+        let (not_mine, mine) = (NOT_MINE.calls(), MINE.calls());
+
+        raise();
+        assert_eq!((NOT_MINE.calls(), MINE.calls()), (not_mine + 1, mine + 1));
+
+        irq_manager().unregister_handler(mine_descriptor).unwrap();
+        raise();
+        assert_eq!((NOT_MINE.calls(), MINE.calls()), (not_mine + 2, mine + 1));
+
+        // Removing the last handler disables the IRQ.
+        irq_manager()
+            .unregister_handler(not_mine_descriptor)
+            .unwrap();
+        raise();
+        assert_eq!((NOT_MINE.calls(), MINE.calls()), (not_mine + 2, mine + 1));
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! FIQ tests.
+//!
+//! On rpi3, the system timer's compare channel 1 is routed as FIQ through the peripheral
+//! interrupt controller. On rpi4, an SGI is routed as FIQ through the GIC.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+#[cfg(feature = "bsp_rpi4")]
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use exception::asynchronous::{fiq, interface::IRQHandler, IRQHandlerDescriptor, IRQReturn};
+use libkernel::{bsp, cpu, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+struct TestHandler {
+    calls: AtomicUsize,
+}
+
+static HANDLER: TestHandler = TestHandler {
+    calls: AtomicUsize::new(0),
+};
+
+/// Set in `kernel_init()` if routing the timer IRQ as FIQ was refused.
+static TIMER_REFUSED: AtomicBool = AtomicBool::new(false);
+
+impl IRQHandler for TestHandler {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        Ok(IRQReturn::Handled)
+    }
+}
+
+/// FIQ handlers can only be registered during kernel init, with IRQs masked.
+fn register_handlers() {
+    use bsp::exception::asynchronous::irq_map;
+
+    let descriptor = IRQHandlerDescriptor::new(irq_map::ARM_NS_PHYSICAL_TIMER, "Timer", &HANDLER);
+    let refused = fiq::register_handler(descriptor).is_err() && !fiq::is_registered();
+    TIMER_REFUSED.store(refused, Ordering::Relaxed);
+
+    #[cfg(feature = "bsp_rpi3")]
+    bcm::register_handler();
+
+    #[cfg(feature = "bsp_rpi4")]
+    gic::register_handler();
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::qemu_exit_failure();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    register_handlers();
+
+    exception::asynchronous::local_irq_unmask();
+    exception::asynchronous::local_fiq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The timer IRQ cannot become the FIQ. It is a local IRQ on the BCM controller, and it has a
+/// regular handler on the GIC.
+#[kernel_test]
+fn timer_irq_cannot_be_routed_as_fiq() {
+    assert!(TIMER_REFUSED.load(Ordering::Relaxed));
+}
+
+#[cfg(feature = "bsp_rpi3")]
+mod bcm {
+    use super::*;
+    use bsp::exception::asynchronous::irq_map;
+    use core::time::Duration;
+    use exception::asynchronous::irq_manager;
+    use memory::{
+        mmu::{self, MMIODescriptor},
+        Address, Physical,
+    };
+
+    const SYSTEM_TIMER_START: Address<Physical> = Address::new(0x3F00_3000);
+    const SYSTEM_TIMER_SIZE: usize = 0x1C;
+
+    /// Register offsets of the system timer.
+    const CS: usize = 0x00;
+    const CLO: usize = 0x04;
+    const C1: usize = 0x10;
+
+    /// The match bit of compare channel 1 in `CS`. The channel is not used by the VideoCore.
+    const CS_M1: u32 = 1 << 1;
+
+    /// The virtual address of the system timer. Set in `kernel_init()`.
+    static SYSTEM_TIMER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Deasserts the system timer FIQ and counts its calls.
+    struct SystemTimerHandler {
+        calls: AtomicUsize,
+    }
+
+    static SYSTEM_TIMER_HANDLER: SystemTimerHandler = SystemTimerHandler {
+        calls: AtomicUsize::new(0),
+    };
+
+    /// Arms the system timer and records whether its FIQ arrived before returning.
+    struct VirtualTimerHandler;
+
+    static VIRTUAL_TIMER_HANDLER: VirtualTimerHandler = VirtualTimerHandler;
+    static FIQ_DURING_IRQ: AtomicBool = AtomicBool::new(false);
+
+    fn reg(offset: usize) -> *mut u32 {
+        (SYSTEM_TIMER.load(Ordering::Relaxed) + offset) as *mut u32
+    }
+
+    fn fiq_calls() -> usize {
+        SYSTEM_TIMER_HANDLER.calls.load(Ordering::Relaxed)
+    }
+
+    /// Let compare channel 1 match in 1 ms.
+    fn arm_system_timer() {
+        unsafe {
+            let now = reg(CLO).read_volatile();
+            reg(C1).write_volatile(now.wrapping_add(1000));
+        }
+    }
+
+    impl IRQHandler for SystemTimerHandler {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            // Writing the match bit clears it and deasserts the interrupt.
+            unsafe { reg(CS).write_volatile(CS_M1) };
+            self.calls.fetch_add(1, Ordering::Relaxed);
+
+            Ok(IRQReturn::Handled)
+        }
+    }
+
+    impl IRQHandler for VirtualTimerHandler {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            use aarch64_cpu::registers::CNTV_CTL_EL0;
+            use tock_registers::interfaces::Writeable;
+
+            // Deassert the IRQ.
+            CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::CLEAR);
+
+            let calls = fiq_calls();
+            arm_system_timer();
+            time::time_manager().spin_for(Duration::from_millis(10));
+            FIQ_DURING_IRQ.store(fiq_calls() > calls, Ordering::Relaxed);
+
+            Ok(IRQReturn::Handled)
+        }
+    }
+
+    pub fn register_handler() {
+        let mmio_descriptor = MMIODescriptor::new(SYSTEM_TIMER_START, SYSTEM_TIMER_SIZE);
+        match unsafe { mmu::kernel_map_mmio("System timer", &mmio_descriptor) } {
+            Err(_) => cpu::qemu_exit_failure(),
+            Ok(addr) => SYSTEM_TIMER.store(addr.as_usize(), Ordering::Relaxed),
+        }
+
+        let descriptor = IRQHandlerDescriptor::new(
+            irq_map::SYSTEM_TIMER_1,
+            "System timer",
+            &SYSTEM_TIMER_HANDLER,
+        );
+        if fiq::register_handler(descriptor).is_err() {
+            cpu::qemu_exit_failure();
+        }
+    }
+
+    /// The interrupt routed as FIQ cannot get a regular handler as well.
+    #[kernel_test]
+    fn fiq_cannot_get_regular_handler() {
+        let descriptor =
+            IRQHandlerDescriptor::new(irq_map::SYSTEM_TIMER_1, "Intruder", &SYSTEM_TIMER_HANDLER);
+
+        assert!(irq_manager().register_handler(descriptor).is_err());
+    }
+
+    /// A peripheral IRQ routed as FIQ reaches its handler, and enabling it as IRQ has no effect.
+    #[kernel_test]
+    fn system_timer_is_delivered_as_fiq() {
+        let num_spurious = irq_manager().num_spurious();
+
+        for enable_as_irq in [false, true] {
+            if enable_as_irq {
+                irq_manager().enable(&irq_map::SYSTEM_TIMER_1);
+            }
+
+            let calls = fiq_calls();
+            arm_system_timer();
+            time::time_manager().spin_for(Duration::from_millis(10));
+            assert_eq!(fiq_calls(), calls + 1);
+        }
+
+        // Had it been signaled as IRQ as well, no handler would have claimed it.
+        assert_eq!(irq_manager().num_spurious(), num_spurious);
+    }
+
+    /// The FIQ preempts a running IRQ handler.
+    #[kernel_test]
+    fn fiq_preempts_irq_handler() {
+        use aarch64_cpu::registers::{CNTV_CTL_EL0, CNTV_TVAL_EL0};
+        use tock_registers::interfaces::Writeable;
+
+        let descriptor = IRQHandlerDescriptor::new(
+            irq_map::ARM_VIRTUAL_TIMER,
+            "Virtual timer",
+            &VIRTUAL_TIMER_HANDLER,
+        );
+        irq_manager().register_handler(descriptor).unwrap();
+        irq_manager().enable(&irq_map::ARM_VIRTUAL_TIMER);
+
+        CNTV_TVAL_EL0.set(0);
+        CNTV_CTL_EL0.write(CNTV_CTL_EL0::ENABLE::SET);
+        time::time_manager().spin_for(Duration::from_millis(50));
+
+        assert!(FIQ_DURING_IRQ.load(Ordering::Relaxed));
+
+        irq_manager().unregister_handler(descriptor).unwrap();
+    }
+}
+
+#[cfg(feature = "bsp_rpi4")]
+mod gic {
+    use super::*;
+    use alloc::boxed::Box;
+    use core::time::Duration;
+    use exception::asynchronous::{irq_manager, IRQNumber};
+
+    const FIQ_SGI: IRQNumber = IRQNumber::new(5);
+
+    /// Set in `kernel_init()` if a second FIQ handler was refused.
+    static SECOND_REFUSED: AtomicBool = AtomicBool::new(false);
+
+    pub fn register_handler() {
+        if fiq::register_handler(IRQHandlerDescriptor::new(FIQ_SGI, "FIQ", &HANDLER)).is_err() {
+            cpu::qemu_exit_failure();
+        }
+
+        let second = IRQHandlerDescriptor::new(FIQ_SGI, "Second FIQ", &HANDLER);
+        SECOND_REFUSED.store(fiq::register_handler(second).is_err(), Ordering::Relaxed);
+    }
+
+    /// Only one FIQ handler can be registered.
+    #[kernel_test]
+    fn second_fiq_handler_is_refused() {
+        assert!(SECOND_REFUSED.load(Ordering::Relaxed));
+    }
+
+    /// The SGI routed as FIQ cannot get a regular handler as well.
+    #[kernel_test]
+    fn fiq_cannot_get_regular_handler() {
+        let descriptor = IRQHandlerDescriptor::new(FIQ_SGI, "Intruder", &HANDLER);
+
+        assert!(irq_manager().register_handler(descriptor).is_err());
+    }
+
+    /// An SGI routed as FIQ reaches its handler, and regular IRQs keep working.
+    #[kernel_test]
+    fn sgi_is_delivered_as_fiq() {
+        static TIMEOUTS: AtomicUsize = AtomicUsize::new(0);
+
+        let calls = HANDLER.calls.load(Ordering::Relaxed);
+        irq_manager().raise_software_irq(&FIQ_SGI).unwrap();
+        time::time_manager().spin_for(Duration::from_millis(10));
+        assert_eq!(HANDLER.calls.load(Ordering::Relaxed), calls + 1);
+
+        // Regular IRQs are acknowledged through the aliased registers now.
+        time::time_manager().set_timeout_once(
+            Duration::from_millis(10),
+            Box::new(|| {
+                TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+            }),
+        );
+        time::time_manager().spin_for(Duration::from_millis(50));
+        assert_eq!(TIMEOUTS.load(Ordering::Relaxed), 1);
+    }
+}
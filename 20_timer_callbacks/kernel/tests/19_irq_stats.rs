@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ statistics tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use exception::asynchronous::irq_manager;
+use libkernel::{bsp, cpu, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+
+    if time::init().is_err() || bsp::driver::init().is_err() {
+        cpu::qemu_exit_failure();
+    }
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The interrupt controller counts the timer IRQ that delivers a timeout, and does not mistake it
+/// for a spurious one.
+#[kernel_test]
+fn timer_irq_is_counted() {
+    use bsp::exception::asynchronous::irq_map;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let count_before = irq_manager().irq_count(&irq_map::ARM_NS_PHYSICAL_TIMER);
+    let spurious_before = irq_manager().num_spurious();
+    assert!(count_before.is_some() && spurious_before.is_some());
+
+    time::time_manager().set_timeout_once(
+        Duration::from_millis(10),
+        Box::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }),
+    );
+    time::time_manager().spin_for(Duration::from_millis(50));
+
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    assert!(irq_manager().irq_count(&irq_map::ARM_NS_PHYSICAL_TIMER) > count_before);
+    assert_eq!(irq_manager().num_spurious(), spurious_before);
+}
@@ -42,4 +42,22 @@ impl Symbol {
     pub fn size(&self) -> usize {
         self.addr_range.end - self.addr_range.start
     }
+
+    /// Returns a copy that is moved up by `offset` bytes, name included.
+    ///
+    /// # Safety
+    ///
+    /// - The name must be mapped `offset` bytes above its current address.
+    pub unsafe fn relocate(&self, offset: usize) -> Symbol {
+        let name_ptr = self.name.as_ptr().wrapping_add(offset);
+        let name_bytes = core::slice::from_raw_parts(name_ptr, self.name.len());
+
+        Symbol {
+            addr_range: Range {
+                start: self.addr_range.start + offset,
+                end: self.addr_range.end + offset,
+            },
+            name: core::str::from_utf8_unchecked(name_bytes),
+        }
+    }
 }
@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Types shared by the kernel and user programs for implementing system calls.
+//!
+//! The system call number is passed in x8, up to six arguments in x0 - x5. The result is returned
+//! in x0. Failing system calls return [`ERROR`].
+
+#![no_std]
+
+/// System call numbers.
+pub mod nr {
+    /// Write a UTF-8 string to the console.
+    ///
+    /// Arguments: Start address, length in bytes. Returns the number of bytes written.
+    pub const WRITE: u64 = 0;
+
+    /// Get the time since boot in nanoseconds.
+    pub const UPTIME: u64 = 1;
+
+    /// Sleep for at least the given duration.
+    ///
+    /// Arguments: Seconds, nanoseconds.
+    pub const SLEEP: u64 = 2;
+
+    /// Terminate the calling process with the given exit code. Does not return.
+    pub const EXIT: u64 = 3;
+}
+
+/// The value returned by failing system calls.
+pub const ERROR: u64 = u64::MAX;
@@ -56,11 +56,15 @@ module Arch
 # Arch::ARMv8
 #---------------------------------------------------------------------------------------------------
 module ARMv8
+# The address fields of all descriptors hold bits [47:12] of an address. With granules larger than
+# 4 KiB, the lowest bits of an aligned address are zero.
+ADDR_FIELD_SHIFT = 12
+
 # ARMv8 Table Descriptor.
 class Stage1TableDescriptor < BitField
     module NextLevelTableAddr
-        OFFSET = 16
-        NUMBITS = 32
+        OFFSET = 12
+        NUMBITS = 36
     end
 
     module Type
@@ -84,7 +88,7 @@ class Stage1TableDescriptor < BitField
     attr_bitfield(:valid, Valid::OFFSET, Valid::NUMBITS)
 
     def next_level_table_addr=(addr)
-        addr >>= Granule64KiB::SHIFT
+        addr >>= ADDR_FIELD_SHIFT
 
         self.__next_level_table_addr = addr
     end
@@ -111,8 +115,8 @@ class Stage1PageDescriptor < BitField
     end
 
     module OutputAddr
-        OFFSET = 16
-        NUMBITS = 32
+        OFFSET = 12
+        NUMBITS = 36
     end
 
     module AF
@@ -135,7 +139,9 @@ class Stage1PageDescriptor < BitField
         NUMBITS = 2
 
         RW_EL1 = 0b00
+        RW_EL1_EL0 = 0b01
         RO_EL1 = 0b10
+        RO_EL1_EL0 = 0b11
     end
 
     module AttrIndx
@@ -170,7 +176,7 @@ class Stage1PageDescriptor < BitField
     attr_bitfield(:valid, Valid::OFFSET, Valid::NUMBITS)
 
     def output_addr=(addr)
-        addr >>= Granule64KiB::SHIFT
+        addr >>= ADDR_FIELD_SHIFT
 
         self.__output_addr = addr
     end
@@ -178,6 +184,21 @@ class Stage1PageDescriptor < BitField
     private :__output_addr=
 end
 
+# ARMv8 level 2 block descriptor.
+#
+# Apart from the type and the alignment of the output address, the layout is the same as that of a
+# level 3 page descriptor.
+class Stage1BlockDescriptor < Stage1PageDescriptor
+    module Type
+        OFFSET = 1
+        NUMBITS = 1
+
+        BLOCK = 0
+    end
+
+    attr_bitfield(:type, Type::OFFSET, Type::NUMBITS)
+end
+
 # Translation table representing the structure defined in translation_table.rs.
 class TranslationTable
     module MAIR
@@ -185,32 +206,51 @@ class TranslationTable
     end
 
     def initialize
+        # Each table occupies one granule and holds 8 byte descriptors.
+        @granule = BSP.kernel_granule
+        @num_table_entries = @granule::SIZE / 8
+        @lvl2_shift = @granule::SHIFT + Math.log2(@num_table_entries).to_i
+        @lvl2_size = 1 << @lvl2_shift
+
         do_sanity_checks
 
-        num_lvl2_tables = BSP.kernel_virt_addr_space_size >> Granule512MiB::SHIFT
+        num_lvl2_tables = BSP.kernel_virt_addr_space_size >> @lvl2_shift
 
         @lvl3 = new_lvl3(num_lvl2_tables, BSP.phys_addr_of_kernel_tables)
 
         @lvl2_phys_start_addr = @lvl3.phys_start_addr + @lvl3.size_in_byte
         @lvl2 = new_lvl2(num_lvl2_tables, @lvl2_phys_start_addr)
 
+        # Copies of the lvl2 table descriptors. The kernel needs them to split blocks.
+        @lvl3_table_descs = new_lvl2(num_lvl2_tables, @lvl2_phys_start_addr + @lvl2.size_in_byte)
+
         populate_lvl2_entries
     end
 
+    # Whole, aligned lvl2 windows are mapped with a single block descriptor.
     def map_at(virt_region, phys_region, attributes)
         return if virt_region.empty?
 
         raise if virt_region.size != phys_region.size
         raise if phys_region.last > BSP.phys_addr_space_end_page
 
-        virt_region.zip(phys_region).each do |virt_page, phys_page|
-            desc = page_descriptor_from(virt_page)
-            set_lvl3_entry(desc, phys_page, attributes)
+        attributes.check_policy
+
+        i = 0
+        while i < virt_region.size
+            if block_mappable?(virt_region[i], phys_region[i], virt_region.size - i)
+                set_lvl2_block_entry(virt_region[i], phys_region[i], attributes)
+                i += @num_table_entries
+            else
+                desc = page_descriptor_from(virt_region[i])
+                set_lvl3_entry(desc, phys_region[i], attributes)
+                i += 1
+            end
         end
     end
 
     def to_binary
-        data = @lvl3.flatten.map(&:to_i) + @lvl2.map(&:to_i)
+        data = @lvl3.flatten.map(&:to_i) + @lvl2.map(&:to_i) + @lvl3_table_descs.map(&:to_i)
         data.pack('Q<*') # "Q" == uint64_t, "<" == little endian
     end
 
@@ -225,13 +265,15 @@ class TranslationTable
     private
 
     def do_sanity_checks
-        raise unless BSP.kernel_granule::SIZE == Granule64KiB::SIZE
-        raise unless (BSP.kernel_virt_addr_space_size % Granule512MiB::SIZE).zero?
+        raise unless (BSP.kernel_virt_addr_space_size % @lvl2_size).zero?
+
+        # Only two levels are supported.
+        raise unless (BSP.kernel_virt_addr_space_size >> @lvl2_shift) <= @num_table_entries
     end
 
     def new_lvl3(num_lvl2_tables, start_addr)
         CArray.new(start_addr, num_lvl2_tables) do
-            temp = CArray.new(start_addr, 8192) do
+            temp = CArray.new(start_addr, @num_table_entries) do
                 Stage1PageDescriptor.new
             end
             start_addr += temp.size_in_byte
@@ -247,18 +289,20 @@ class TranslationTable
     end
 
     def populate_lvl2_entries
-        @lvl2.each_with_index do |descriptor, i|
-            descriptor.next_level_table_addr = @lvl3[i].phys_start_addr
-            descriptor.type = Stage1TableDescriptor::Type::TABLE
-            descriptor.valid = Stage1TableDescriptor::Valid::TRUE
+        [@lvl2, @lvl3_table_descs].each do |table|
+            table.each_with_index do |descriptor, i|
+                descriptor.next_level_table_addr = @lvl3[i].phys_start_addr
+                descriptor.type = Stage1TableDescriptor::Type::TABLE
+                descriptor.valid = Stage1TableDescriptor::Valid::TRUE
+            end
         end
     end
 
     def lvl2_lvl3_index_from(addr)
         addr -= BSP.kernel_virt_start_addr
 
-        lvl2_index = addr >> Granule512MiB::SHIFT
-        lvl3_index = (addr & Granule512MiB::MASK) >> Granule64KiB::SHIFT
+        lvl2_index = addr >> @lvl2_shift
+        lvl3_index = (addr & (@lvl2_size - 1)) >> @granule::SHIFT
 
         raise unless lvl2_index < @lvl2.size
 
@@ -268,10 +312,24 @@ class TranslationTable
     def page_descriptor_from(virt_addr)
         lvl2_index, lvl3_index = lvl2_lvl3_index_from(virt_addr)
 
+        raise 'Page is already mapped by a block' if @lvl2[lvl2_index].is_a?(Stage1BlockDescriptor)
+
         @lvl3[lvl2_index][lvl3_index]
     end
 
-    # rubocop:disable Metrics/MethodLength
+    def block_mappable?(virt_addr, phys_addr, num_pages_left)
+        return false unless virt_addr.aligned?(@lvl2_size)
+        return false unless phys_addr.aligned?(@lvl2_size)
+        return false if num_pages_left < @num_table_entries
+
+        lvl2_index, = lvl2_lvl3_index_from(virt_addr)
+
+        # The whole window must still be unmapped.
+        @lvl2[lvl2_index].instance_of?(Stage1TableDescriptor) &&
+            @lvl3[lvl2_index].all? { |desc| desc.to_i.zero? }
+    end
+
+    # rubocop:disable Metrics/MethodLength, Metrics/AbcSize
     def set_attributes(desc, attributes)
         case attributes.mem_attributes
         when :CacheableDRAM
@@ -286,20 +344,27 @@ class TranslationTable
                       Stage1PageDescriptor::AP::RO_EL1
                   when :ReadWrite
                       Stage1PageDescriptor::AP::RW_EL1
+                  when :ReadOnlyUser
+                      Stage1PageDescriptor::AP::RO_EL1_EL0
+                  when :ReadWriteUser
+                      Stage1PageDescriptor::AP::RW_EL1_EL0
                   else
                       raise 'Invalid input'
 
                   end
 
-        desc.pxn = if attributes.execute_never
+        desc.pxn = if attributes.privileged_execute_never
                        Stage1PageDescriptor::PXN::TRUE
                    else
                        Stage1PageDescriptor::PXN::FALSE
                    end
-
-        desc.uxn = Stage1PageDescriptor::UXN::TRUE
+        desc.uxn = if attributes.user_execute_never
+                       Stage1PageDescriptor::UXN::TRUE
+                   else
+                       Stage1PageDescriptor::UXN::FALSE
+                   end
     end
-    # rubocop:enable Metrics/MethodLength
+    # rubocop:enable Metrics/MethodLength, Metrics/AbcSize
 
     def set_lvl3_entry(desc, output_addr, attributes)
         desc.output_addr = output_addr
@@ -309,6 +374,19 @@ class TranslationTable
 
         set_attributes(desc, attributes)
     end
+
+    def set_lvl2_block_entry(virt_addr, output_addr, attributes)
+        lvl2_index, = lvl2_lvl3_index_from(virt_addr)
+
+        desc = Stage1BlockDescriptor.new
+        desc.output_addr = output_addr
+        desc.af = Stage1BlockDescriptor::AF::TRUE
+        desc.type = Stage1BlockDescriptor::Type::BLOCK
+        desc.valid = Stage1BlockDescriptor::Valid::TRUE
+
+        set_attributes(desc, attributes)
+        @lvl2[lvl2_index] = desc
+    end
 end
 end
 end
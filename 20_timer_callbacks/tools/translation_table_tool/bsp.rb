@@ -11,7 +11,16 @@ class RaspberryPi
     MEMORY_SRC = File.read('kernel/src/bsp/raspberrypi/memory.rs').split("\n")
 
     def initialize
-        @kernel_granule = Granule64KiB
+        @kernel_granule = case GRANULE_TYPE
+                          when :'4KiB'
+                              Granule4KiB
+                          when :'16KiB'
+                              Granule16KiB
+                          when :'64KiB'
+                              Granule64KiB
+                          else
+                              raise
+                          end
 
         @kernel_virt_addr_space_size = KERNEL_ELF.symbol_value('__kernel_virt_addr_space_size')
         @kernel_virt_start_addr = KERNEL_ELF.symbol_value('__kernel_virt_start_addr')
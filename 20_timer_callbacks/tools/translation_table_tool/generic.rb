@@ -4,15 +4,19 @@
 #
 # Copyright (c) 2021-2023 Andre Richter <andre.o.richter@gmail.com>
 
-module Granule64KiB
-    SIZE = 64 * 1024
+module Granule4KiB
+    SIZE = 4 * 1024
+    SHIFT = Math.log2(SIZE).to_i
+end
+
+module Granule16KiB
+    SIZE = 16 * 1024
     SHIFT = Math.log2(SIZE).to_i
 end
 
-module Granule512MiB
-    SIZE = 512 * 1024 * 1024
+module Granule64KiB
+    SIZE = 64 * 1024
     SHIFT = Math.log2(SIZE).to_i
-    MASK = SIZE - 1
 end
 
 # Monkey-patch Integer with some helper functions.
@@ -57,12 +61,32 @@ end
 
 # Collection of memory attributes.
 class AttributeFields
-    attr_reader :mem_attributes, :acc_perms, :execute_never
+    attr_reader :mem_attributes, :acc_perms, :privileged_execute_never, :user_execute_never
 
-    def initialize(mem_attributes, acc_perms, execute_never)
+    def initialize(mem_attributes, acc_perms, privileged_execute_never, user_execute_never)
         @mem_attributes = mem_attributes
         @acc_perms = acc_perms
-        @execute_never = execute_never
+        @privileged_execute_never = privileged_execute_never
+        @user_execute_never = user_execute_never
+    end
+
+    def user?
+        %i[ReadOnlyUser ReadWriteUser].include?(@acc_perms)
+    end
+
+    def writable?
+        %i[ReadWrite ReadWriteUser].include?(@acc_perms)
+    end
+
+    def executable?
+        !@privileged_execute_never || !@user_execute_never
+    end
+
+    # The same policy that the kernel enforces in `AttributeFields::check_policy()`.
+    def check_policy
+        raise 'Mapping would be writable and executable' if writable? && executable?
+        raise 'The kernel must not execute user pages' if user? && !@privileged_execute_never
+        raise 'User space must not execute kernel pages' if !user? && !@user_execute_never
     end
 
     def to_s
@@ -78,11 +102,15 @@ class AttributeFields
                 'RW'
             when :ReadOnly
                 'RO'
+            when :ReadWriteUser
+                'URW'
+            when :ReadOnlyUser
+                'URO'
             else
                 '??'
             end
 
-        z = @execute_never ? 'XN' : 'X '
+        z = executable? ? 'X ' : 'XN'
 
         "#{x} #{y} #{z}"
     end
@@ -123,7 +151,7 @@ class MappingDescriptor
         name = @name.ljust(self.class.max_section_name_length)
         virt_start = @virt_region.first.to_hex_underscore(with_leading_zeros: true)
         phys_start = @phys_region.first.to_hex_underscore(with_leading_zeros: true)
-        size = size_human_readable(@virt_region.size * 65_536)
+        size = size_human_readable(@virt_region.size * BSP.kernel_granule::SIZE)
 
         "#{name} | #{virt_start} | #{phys_start} | #{size} | #{@attributes}"
     end
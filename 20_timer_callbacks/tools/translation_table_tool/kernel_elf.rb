@@ -59,16 +59,28 @@ class KernelELF
         end
     end
 
-    def segment_get_acc_perms(segment)
+    # Segments made of `.user*` sections are accessible from user space.
+    def segment_get_acc_perms(segment, section_names)
+        user = section_names.split.all? { |name| name.start_with?('.user') }
+
         if segment.readable? && segment.writable?
-            :ReadWrite
+            user ? :ReadWriteUser : :ReadWrite
         elsif segment.readable?
-            :ReadOnly
+            user ? :ReadOnlyUser : :ReadOnly
         else
             :Invalid
         end
     end
 
+    # The kernel never executes user pages, and user space never executes kernel pages.
+    def segment_get_attributes(segment, section_names)
+        acc_perms = segment_get_acc_perms(segment, section_names)
+        user = %i[ReadOnlyUser ReadWriteUser].include?(acc_perms)
+        executable = segment.executable?
+
+        AttributeFields.new(:CacheableDRAM, acc_perms, user || !executable, !user || !executable)
+    end
+
     def update_max_section_name_length(descriptors)
         MappingDescriptor.update_max_section_name_length(descriptors.map { |i| i.name.size }.max)
     end
@@ -79,13 +91,11 @@ class KernelELF
             size = segment.mem_size.align_up(BSP.kernel_granule::SIZE)
             virt_start_addr = segment.header.p_vaddr
             phys_start_addr = segment.header.p_paddr
-            acc_perms = segment_get_acc_perms(segment)
-            execute_never = !segment.executable?
             section_names = sections_in_segment(segment)
+            attributes = segment_get_attributes(segment, section_names)
 
             virt_region = MemoryRegion.new(virt_start_addr, size, BSP.kernel_granule::SIZE)
             phys_region = MemoryRegion.new(phys_start_addr, size, BSP.kernel_granule::SIZE)
-            attributes = AttributeFields.new(:CacheableDRAM, acc_perms, execute_never)
 
             MappingDescriptor.new(section_names, virt_region, phys_region, attributes)
         end
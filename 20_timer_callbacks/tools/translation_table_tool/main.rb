@@ -16,7 +16,8 @@ require_relative 'bsp'
 require_relative 'arch'
 
 BSP_TYPE = ARGV[0].to_sym
-kernel_elf_path = ARGV[1]
+GRANULE_TYPE = ARGV[1].to_sym
+kernel_elf_path = ARGV[2]
 
 start = Time.now
 
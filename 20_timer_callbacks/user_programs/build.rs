@@ -0,0 +1,8 @@
+use std::path::Path;
+
+fn main() {
+    println!(
+        "cargo:rerun-if-changed={}",
+        Path::new("user_programs.ld").display()
+    );
+}
@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Read from an address that is not mapped.
+
+#![no_main]
+#![no_std]
+
+// Link the panic handler.
+use libuser as _;
+
+/// The entry point.
+#[no_mangle]
+extern "C" fn _start() {
+    unsafe { core::ptr::read_volatile(8 as *const u64) };
+}
@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Exercise all system calls.
+
+#![no_main]
+#![no_std]
+
+use core::time::Duration;
+
+/// The entry point.
+#[no_mangle]
+extern "C" fn _start() {
+    libuser::write(b"Hello from user space\n");
+
+    let before = libuser::uptime();
+    libuser::sleep(Duration::from_millis(50));
+
+    if libuser::uptime() > before {
+        libuser::write(b"Uptime advanced in EL0\n");
+    }
+
+    if libuser::sleep(Duration::from_secs(u64::MAX)) == syscall_types::ERROR {
+        libuser::write(b"Overlong sleep refused\n");
+    }
+
+    libuser::exit(42);
+}
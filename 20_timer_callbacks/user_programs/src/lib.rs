@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! System call wrappers for user programs.
+//!
+//! User programs are linked separately from the kernel, so that all the code they need ends up in
+//! their image, including helpers from `core`. Each program is a binary of this crate that defines
+//! a `_start()` function. Returning from it exits the process with code 0.
+//!
+//! The images are position independent and read-only. Writable data and anything that needs
+//! relocation, like a vtable, is rejected at link time. A panic terminates the process.
+
+#![no_std]
+
+use core::{arch::asm, time::Duration};
+use syscall_types::nr;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Issue a system call.
+///
+/// The system call number is passed in x8, arguments in x0 - x2. The result is returned in x0.
+#[inline(always)]
+fn syscall(nr: u64, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    let ret: u64;
+
+    unsafe {
+        asm!(
+            "svc #0",
+            inlateout("x0") arg0 => ret,
+            in("x1") arg1,
+            in("x2") arg2,
+            in("x8") nr,
+            options(nostack)
+        );
+    }
+
+    ret
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    exit(syscall_types::ERROR)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Write a UTF-8 string to the console.
+///
+/// The buffer must be part of the calling process, i.e. its image or its stack.
+pub fn write(buf: &[u8]) -> u64 {
+    syscall(nr::WRITE, buf.as_ptr() as u64, buf.len() as u64, 0)
+}
+
+/// The time since boot.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(syscall(nr::UPTIME, 0, 0, 0))
+}
+
+/// Sleep for at least the given duration.
+///
+/// Returns [`syscall_types::ERROR`] if the duration is invalid or too long.
+pub fn sleep(duration: Duration) -> u64 {
+    syscall(
+        nr::SLEEP,
+        duration.as_secs(),
+        duration.subsec_nanos() as u64,
+        0,
+    )
+}
+
+/// Terminate the process.
+pub fn exit(code: u64) -> ! {
+    syscall(nr::EXIT, code, 0, 0);
+
+    // The exit system call does not return.
+    #[allow(clippy::empty_loop)]
+    loop {}
+}